@@ -0,0 +1,115 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pprof::criterion::{Output, PProfProfiler};
+
+use ztunnel::identity::Identity;
+use ztunnel::rbac::{Authorization, Connection, RbacAction, RbacMatch, RbacScope, StringMatch};
+use ztunnel::state::policy::{Candidates, PolicyStore};
+use ztunnel::strng;
+
+const NAMESPACE: &str = "default";
+
+/// Creates `n` policies, each selecting a distinct port, destination range or principal, as is
+/// typical for large deployments with a policy per service.
+fn create_policies(n: usize) -> PolicyStore {
+    let mut store = PolicyStore::default();
+    for i in 0..n {
+        let m = match i % 3 {
+            0 => RbacMatch {
+                destination_ports: vec![(1000 + i) as u16],
+                ..Default::default()
+            },
+            1 => RbacMatch {
+                destination_ips: vec![format!("10.{}.{}.0/24", i / 256 % 256, i % 256)
+                    .parse()
+                    .unwrap()],
+                ..Default::default()
+            },
+            _ => RbacMatch {
+                principals: vec![StringMatch::Prefix(strng::format!(
+                    "cluster.local/ns/ns-{i}/"
+                ))],
+                ..Default::default()
+            },
+        };
+        store.insert(Authorization {
+            name: strng::format!("policy-{i}"),
+            namespace: NAMESPACE.into(),
+            scope: RbacScope::Namespace,
+            action: RbacAction::Deny,
+            rules: vec![vec![vec![m]]],
+            dry_run: false,
+        });
+    }
+    store
+}
+
+fn connection() -> Connection {
+    Connection {
+        src_identity: Some(Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "ns-5".into(),
+            service_account: "default".into(),
+        }),
+        src: "192.168.0.1:12345".parse().unwrap(),
+        dst_network: "".into(),
+        dst: "10.0.4.1:1003".parse().unwrap(),
+    }
+}
+
+pub fn policy_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("policy_lookup");
+    group.throughput(Throughput::Elements(1));
+    let conn = connection();
+    let ns = strng::new(NAMESPACE);
+    for n in [100, 1000, 10000] {
+        let store = create_policies(n);
+        group.bench_with_input(BenchmarkId::new("linear", n), &store, |b, store| {
+            b.iter(|| {
+                store
+                    .get_by_namespace(&ns)
+                    .iter()
+                    .filter_map(|k| store.get(k))
+                    .filter(|p| p.matches(&conn))
+                    .count()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("indexed", n), &store, |b, store| {
+            b.iter(|| {
+                let mut candidates = Candidates::default();
+                store.candidates(&ns, &conn, &mut candidates);
+                candidates
+                    .policies
+                    .iter()
+                    .filter(|p| p.matches(&conn))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .with_profiler(PProfProfiler::new(100, Output::Protobuf))
+        .warm_up_time(Duration::from_millis(1));
+    targets = policy_lookup,
+}
+
+criterion_main!(benches);
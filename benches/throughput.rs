@@ -85,6 +85,7 @@ fn create_test_policies() -> Vec<Authorization> {
             not_destination_ips: vec![],
             destination_ports: vec![0],
             not_destination_ports: vec![],
+            ..Default::default()
         }]]);
     }
 
@@ -95,6 +96,7 @@ fn create_test_policies() -> Vec<Authorization> {
             scope: ztunnel::rbac::RbacScope::Global,
             namespace: "default".into(),
             rules: rules.clone(),
+            dry_run: false,
         });
     }
 
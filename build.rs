@@ -24,6 +24,7 @@ fn main() -> Result<(), anyhow::Error> {
         "proto/authorization.proto",
         "proto/citadel.proto",
         "proto/zds.proto",
+        "proto/ext_authz.proto",
     ]
     .iter()
     .map(|name| std::env::current_dir().unwrap().join(name))
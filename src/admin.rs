@@ -12,14 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::Config;
+use crate::config::{Config, ConfigSource};
+use crate::dns::cache::ResponseCache;
 use crate::hyper_util::{empty_response, plaintext_response, Server};
-use crate::identity::SecretManager;
-use crate::state::DemandProxyState;
+use crate::identity::{Identity, SecretManager};
+use crate::state::{DemandProxyState, ProxyRbacContext};
+use crate::strng::Strng;
 use crate::tls::Certificate;
 use crate::version::BuildInfo;
 use crate::xds::LocalConfig;
-use crate::{signal, telemetry};
+use crate::{rbac, signal, telemetry};
 
 use base64::engine::general_purpose::STANDARD;
 use bytes::Bytes;
@@ -31,10 +33,11 @@ use pprof::protos::Message;
 use std::borrow::Borrow;
 use std::collections::HashMap;
 
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::SystemTime;
-use std::{net::SocketAddr, time::Duration};
 
 use tokio::time;
 use tracing::{error, info, warn};
@@ -57,7 +60,16 @@ pub trait AdminHandler2: Sync + Send {
     // sadly can't use async trait because no Sync
     // see: https://github.com/dtolnay/async-trait/issues/248, https://github.com/dtolnay/async-trait/issues/142
     // we can't use FutureExt::shared because our result is not clonable
+    /// Returns the entry of the handler in the config dump, or null if it has none.
     fn handle(&self) -> anyhow::Result<serde_json::Value>;
+    /// Returns the path and description of an endpoint the handler serves, if any.
+    fn endpoint(&self) -> Option<(&'static str, &'static str)> {
+        None
+    }
+    /// Handles a request to the endpoint of the handler, given its query string.
+    fn handle_request(&self, _query: Option<&str>) -> Response<Full<Bytes>> {
+        empty_response(hyper::StatusCode::NOT_FOUND)
+    }
 }
 
 struct State {
@@ -66,6 +78,7 @@ struct State {
     shutdown_trigger: signal::ShutdownTrigger,
     cert_manager: Arc<SecretManager>,
     handlers: Vec<Arc<dyn AdminHandler2>>,
+    dns_cache: Option<Arc<ResponseCache>>,
 }
 
 pub struct Service {
@@ -119,6 +132,7 @@ impl Service {
                 shutdown_trigger,
                 cert_manager,
                 handlers: vec![],
+                dns_cache: None,
             },
         )
         .await
@@ -133,6 +147,11 @@ impl Service {
         self.s.state_mut().handlers.push(handler);
     }
 
+    /// Enables flushing the cache of upstream DNS responses.
+    pub fn set_dns_cache(&mut self, cache: Arc<ResponseCache>) {
+        self.s.state_mut().dns_cache = Some(cache);
+    }
+
     pub fn spawn(self) {
         self.s.spawn(|state, req| async move {
             match req.uri().path() {
@@ -157,16 +176,29 @@ impl Service {
                     )
                     .await
                 }
+                "/dns_cache/flush" => Ok(handle_dns_cache_flush(
+                    state.dns_cache.as_deref(),
+                    req.method(),
+                )),
                 "/logging" => Ok(handle_logging(req).await),
-                "/" => Ok(handle_dashboard(req).await),
-                _ => Ok(empty_response(hyper::StatusCode::NOT_FOUND)),
+                "/" => Ok(handle_dashboard(&state.handlers).await),
+                path => {
+                    let handler = state.handlers.iter().find(|h| {
+                        h.endpoint()
+                            .is_some_and(|(p, _)| path.strip_prefix('/') == Some(p))
+                    });
+                    Ok(match handler {
+                        Some(h) => h.handle_request(req.uri().query()),
+                        None => empty_response(hyper::StatusCode::NOT_FOUND),
+                    })
+                }
             }
         })
     }
 }
 
-async fn handle_dashboard(_req: Request<Incoming>) -> Response<Full<Bytes>> {
-    let apis = &[
+async fn handle_dashboard(handlers: &[Arc<dyn AdminHandler2>]) -> Response<Full<Bytes>> {
+    let mut apis = vec![
         (
             "debug/pprof/profile",
             "build profile using the pprof profiler (if supported)",
@@ -177,8 +209,13 @@ async fn handle_dashboard(_req: Request<Incoming>) -> Response<Full<Bytes>> {
         ),
         ("quitquitquit", "shut down the server"),
         ("config_dump", "dump the current Ztunnel configuration"),
+        (
+            "dns_cache/flush",
+            "flush the cache of upstream DNS responses (POST)",
+        ),
         ("logging", "query/changing logging levels"),
     ];
+    apis.extend(handlers.iter().filter_map(|h| h.endpoint()));
 
     let mut api_rows = String::new();
 
@@ -288,17 +325,18 @@ async fn handle_config_dump(
     mut dump: ConfigDump,
 ) -> anyhow::Result<Response<Full<Bytes>>> {
     if let Some(cfg) = dump.config.local_xds_config.clone() {
-        match cfg.read_to_string().await {
-            Ok(data) => match serde_yaml::from_str(&data) {
-                Ok(c) => dump.static_config = c,
-                Err(e) => error!(
-                    "Failed to load static workloads from local XDS {:?}:{:?}",
-                    dump.config.local_xds_config, e
-                ),
-            },
+        let res = match &cfg {
+            ConfigSource::File(path) => LocalConfig::read(path).await.map_err(Into::into),
+            cfg => cfg
+                .read_to_string()
+                .await
+                .and_then(|data| Ok(LocalConfig::parse(&data, "local config")?)),
+        };
+        match res {
+            Ok(c) => dump.static_config = c,
             Err(e) => error!(
-                "Failed to read local XDS config {:?}:{:?}",
-                dump.config.local_xds_config, e
+                "Failed to load static workloads from local XDS {:?}: {e}",
+                dump.config.local_xds_config
             ),
         }
     }
@@ -309,7 +347,9 @@ async fn handle_config_dump(
 
     for h in handlers {
         let x = h.handle()?;
-        kv.insert(h.key().to_string(), x);
+        if !x.is_null() {
+            kv.insert(h.key().to_string(), x);
+        }
     }
     let body = serde_json::to_string_pretty(&kv)?;
     Ok(Response::builder()
@@ -318,6 +358,115 @@ async fn handle_config_dump(
         .expect("builder with known status code should not fail"))
 }
 
+static EXPLAIN_RBAC_HELP_STRING: &str = "
+usage: GET /explain_rbac?src=<ip>&dst=<ip>:<port>\t\t\t(Explain the decision for a plaintext connection)
+usage: GET /explain_rbac?src=<ip>&dst=<ip>:<port>&identity=<spiffe id>\t(Explain the decision for a mTLS connection)
+";
+
+/// Explains the authorization decision for a connection described by query parameters.
+pub struct ExplainRbacHandler {
+    proxy_state: DemandProxyState,
+    network: Strng,
+}
+
+impl ExplainRbacHandler {
+    pub fn new(proxy_state: DemandProxyState, network: Strng) -> Self {
+        ExplainRbacHandler {
+            proxy_state,
+            network,
+        }
+    }
+}
+
+impl AdminHandler2 for ExplainRbacHandler {
+    fn key(&self) -> &'static str {
+        "explainRbac"
+    }
+
+    fn handle(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn endpoint(&self) -> Option<(&'static str, &'static str)> {
+        Some((
+            "explain_rbac",
+            "explain the authorization decision for a connection",
+        ))
+    }
+
+    fn handle_request(&self, query: Option<&str>) -> Response<Full<Bytes>> {
+        handle_explain_rbac(&self.proxy_state, self.network.clone(), query)
+    }
+}
+
+fn handle_explain_rbac(
+    proxy_state: &DemandProxyState,
+    network: Strng,
+    query: Option<&str>,
+) -> Response<Full<Bytes>> {
+    let qp: HashMap<String, String> = query
+        .map(|v| {
+            url::form_urlencoded::parse(v.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let conn = match explain_rbac_connection(&qp, network) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("{e}\n{EXPLAIN_RBAC_HELP_STRING}"),
+            )
+        }
+    };
+    let ctx = ProxyRbacContext {
+        conn,
+        dest_workload_info: None,
+    };
+    let Some(explanation) = proxy_state.explain_rbac(&ctx) else {
+        return plaintext_response(
+            hyper::StatusCode::NOT_FOUND,
+            format!("destination workload {} not found\n", ctx.conn.dst.ip()),
+        );
+    };
+    match serde_json::to_string_pretty(&explanation) {
+        Ok(body) => Response::builder()
+            .status(hyper::StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .expect("builder with known status code should not fail"),
+        Err(e) => plaintext_response(
+            hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to serialize explanation: {e}\n"),
+        ),
+    }
+}
+
+fn explain_rbac_connection(
+    qp: &HashMap<String, String>,
+    network: Strng,
+) -> anyhow::Result<rbac::Connection> {
+    let Some(src) = qp.get("src") else {
+        anyhow::bail!("src is required");
+    };
+    let Some(dst) = qp.get("dst") else {
+        anyhow::bail!("dst is required");
+    };
+    let src = IpAddr::from_str(src).map_err(|e| anyhow::anyhow!("invalid src {src}: {e}"))?;
+    let dst = SocketAddr::from_str(dst).map_err(|e| anyhow::anyhow!("invalid dst {dst}: {e}"))?;
+    let src_identity = qp
+        .get("identity")
+        .map(|id| Identity::from_str(id).map_err(|e| anyhow::anyhow!("invalid identity {id}: {e}")))
+        .transpose()?;
+    Ok(rbac::Connection {
+        src: SocketAddr::new(src, 0),
+        dst,
+        src_identity,
+        dst_network: network,
+    })
+}
+
 //mirror envoy's behavior: https://www.envoyproxy.io/docs/envoy/latest/operations/admin#post--logging
 //NOTE: multiple query parameters is not supported, for example
 //curl -X POST http://127.0.0.1:15000/logging?"tap=debug&router=debug"
@@ -329,6 +478,29 @@ usage: POST /logging?level={mod1}:{level1},{mod2}:{level2}\t(To change specific
 hint: loglevel:\terror|warn|info|debug|trace|off
 hint: mod_name:\tthe module name, i.e. ztunnel::proxy
 ";
+fn handle_dns_cache_flush(
+    cache: Option<&ResponseCache>,
+    method: &hyper::Method,
+) -> Response<Full<Bytes>> {
+    if method != hyper::Method::POST {
+        return empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED);
+    }
+    match cache {
+        Some(cache) => {
+            let flushed = cache.flush();
+            info!(flushed, "flushed DNS cache");
+            plaintext_response(
+                hyper::StatusCode::OK,
+                format!("flushed {flushed} DNS cache entries\n"),
+            )
+        }
+        None => plaintext_response(
+            hyper::StatusCode::NOT_FOUND,
+            "DNS cache is not enabled\n".into(),
+        ),
+    }
+}
+
 async fn handle_logging(req: Request<Incoming>) -> Response<Full<Bytes>> {
     match *req.method() {
         hyper::Method::POST => {
@@ -452,12 +624,18 @@ mod tests {
     use super::change_log_level;
     use super::dump_certs;
     use super::handle_config_dump;
+    use super::handle_dns_cache_flush;
+    use super::AdminHandler2;
     use super::ConfigDump;
+    use super::ExplainRbacHandler;
     use crate::admin::HELP_STRING;
     use crate::config::construct_config;
     use crate::config::ProxyConfig;
+    use crate::dns::cache::ResponseCache;
+    use crate::dns::resolver::Answer;
     use crate::identity;
     use crate::strng;
+    use crate::test_helpers::dns::{a, a_request, ipv4, n, socket_addr};
     use crate::test_helpers::{get_response_str, helpers, new_proxy_state};
     use crate::xds::istio::security::string_match::MatchType as XdsMatchType;
     use crate::xds::istio::security::Address as XdsAddress;
@@ -467,6 +645,7 @@ mod tests {
     use crate::xds::istio::security::Rule as XdsRule;
     use crate::xds::istio::security::StringMatch as XdsStringMatch;
     use crate::xds::istio::workload::gateway_address::Destination as XdsDestination;
+    use crate::xds::istio::workload::ConnectionRateLimit as XdsConnectionRateLimit;
     use crate::xds::istio::workload::GatewayAddress as XdsGatewayAddress;
     use crate::xds::istio::workload::LoadBalancing as XdsLoadBalancing;
     use crate::xds::istio::workload::Locality as XdsLocality;
@@ -477,6 +656,7 @@ mod tests {
     use crate::xds::istio::workload::Workload as XdsWorkload;
     use crate::xds::istio::workload::WorkloadType as XdsWorkloadType;
     use bytes::Bytes;
+    use hickory_server::server::Protocol;
     use http_body_util::BodyExt;
     use std::collections::HashMap;
     use std::sync::Arc;
@@ -666,6 +846,10 @@ mod tests {
                 zone: "zone".to_string(),
                 subzone: "subezone".to_string(),
             }),
+            connection_rate_limit: Some(XdsConnectionRateLimit {
+                connections_per_second: 100,
+                burst: 200,
+            }),
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -686,6 +870,7 @@ mod tests {
             load_balancing: Some(XdsLoadBalancing {
                 routing_preference: vec![1, 2],
                 mode: 1,
+                strategy: 2,
             }), // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -731,9 +916,44 @@ mod tests {
                                 "spiffe://cluster.local/ns/ns/sa/not-sa".to_string(),
                             )),
                         }],
+                        service_accounts: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("sa".to_string())),
+                        }],
+                        not_service_accounts: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("not-sa".to_string())),
+                        }],
+                        canonical_names: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("app".to_string())),
+                        }],
+                        not_canonical_names: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("not-app".to_string())),
+                        }],
+                        canonical_revisions: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("v1".to_string())),
+                        }],
+                        not_canonical_revisions: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("not-v1".to_string())),
+                        }],
+                        cluster_ids: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("cluster".to_string())),
+                        }],
+                        not_cluster_ids: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("not-cluster".to_string())),
+                        }],
+                        destination_services: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact(
+                                "svc1.ns.svc.cluster.local".to_string(),
+                            )),
+                        }],
+                        not_destination_services: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact(
+                                "not-svc1.ns.svc.cluster.local".to_string(),
+                            )),
+                        }],
                     }],
                 }],
             }],
+            dry_run: true,
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -772,6 +992,7 @@ mod tests {
         // most of the value of this test is ensuring that we can serialize
         // the config dump at all from our internal types
         assert!(resp_str.contains("defaultnw/127.0.0.2"));
+        assert!(resp_str.contains(r#""outlierDetection": {}"#));
         // Check a waypoint
         assert!(resp_str.contains(
             r#"waypoint": {
@@ -782,6 +1003,141 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_explain_rbac() {
+        let wl = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/wl".to_string(),
+            name: "wl".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+            trust_domain: "cluster.local".to_string(),
+            service_account: "sa".to_string(),
+            ..Default::default()
+        };
+        let deny = XdsAuthorization {
+            name: "deny".to_string(),
+            namespace: "ns".to_string(),
+            scope: 1,  // NAMESPACE
+            action: 1, // DENY
+            rules: vec![XdsRule {
+                clauses: vec![XdsClause {
+                    matches: vec![XdsMatch {
+                        destination_ports: vec![9090],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+            dry_run: false,
+        };
+        let allow = XdsAuthorization {
+            name: "allow".to_string(),
+            namespace: "istio-system".to_string(),
+            scope: 0,  // GLOBAL
+            action: 0, // ALLOW
+            rules: vec![XdsRule {
+                clauses: vec![XdsClause {
+                    matches: vec![XdsMatch {
+                        namespaces: vec![XdsStringMatch {
+                            match_type: Some(XdsMatchType::Exact("client".to_string())),
+                        }],
+                        destination_ports: vec![8080, 9090],
+                        ..Default::default()
+                    }],
+                }],
+            }],
+            dry_run: false,
+        };
+        let proxy_state = new_proxy_state(&[wl], &[], &[deny, allow]);
+        let handler = ExplainRbacHandler::new(proxy_state, strng::EMPTY);
+        assert_eq!(handler.endpoint().unwrap().0, "explain_rbac");
+        assert!(handler.handle().unwrap().is_null());
+        let explain = |query: &'static str| {
+            let resp = handler.handle_request(Some(query));
+            async move {
+                let status = resp.status();
+                (status, get_response_str(resp).await)
+            }
+        };
+
+        let (status, body) = explain(
+            "src=127.0.0.1&dst=127.0.0.2:9090&identity=spiffe://cluster.local/ns/client/sa/default",
+        )
+        .await;
+        assert_eq!(status, hyper::StatusCode::OK);
+        let got: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(got["allowed"], false);
+        assert_eq!(got["wouldConsultExtAuthz"], false);
+        assert_eq!(got["destinationWorkload"], "cluster1//v1/Pod/ns/wl");
+        assert_eq!(
+            got["policies"],
+            serde_json::json!([
+                {
+                    "source": "namespace",
+                    "policy": "ns/deny",
+                    "action": "Deny",
+                    "matchedRule": 0,
+                    "rules": [{"matched": true, "clauses": [{"matched": true, "matches": [
+                        {"matched": true, "passed": ["destination_ports"], "failed": []}
+                    ]}]}],
+                },
+                {
+                    "source": "global",
+                    "policy": "istio-system/allow",
+                    "action": "Allow",
+                    "matchedRule": 0,
+                    "rules": [{"matched": true, "clauses": [{"matched": true, "matches": [
+                        {"matched": true, "passed": ["destination_ports", "namespaces"], "failed": []}
+                    ]}]}],
+                },
+            ])
+        );
+
+        // Plaintext connections do not have a namespace.
+        let (status, body) = explain("src=127.0.0.1&dst=127.0.0.2:8080").await;
+        assert_eq!(status, hyper::StatusCode::OK);
+        let got: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(got["allowed"], false);
+        assert_eq!(got["policies"][0]["matchedRule"], serde_json::Value::Null);
+        assert_eq!(
+            got["policies"][1]["rules"][0]["clauses"][0]["matches"][0],
+            serde_json::json!({"matched": false, "passed": ["destination_ports"], "failed": ["namespaces"]})
+        );
+
+        let (status, _) = explain("src=127.0.0.1&dst=127.0.0.3:8080").await;
+        assert_eq!(status, hyper::StatusCode::NOT_FOUND);
+        let (status, _) = explain("src=127.0.0.1").await;
+        assert_eq!(status, hyper::StatusCode::BAD_REQUEST);
+        let (status, _) = explain("src=127.0.0.1&dst=127.0.0.2:8080&identity=bad").await;
+        assert_eq!(status, hyper::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_dns_cache_flush() {
+        let resp = handle_dns_cache_flush(None, &hyper::Method::POST);
+        assert_eq!(resp.status(), hyper::StatusCode::NOT_FOUND);
+
+        let cache = ResponseCache::new(10);
+        let req = a_request(
+            n("www.example.com."),
+            socket_addr("1.1.1.1:80"),
+            Protocol::Udp,
+        );
+        let answer = Answer::new(vec![a(n("www.example.com."), ipv4("1.2.3.4"))], false);
+        cache.insert(&req, &[], &Ok(answer));
+
+        let resp = handle_dns_cache_flush(Some(&cache), &hyper::Method::GET);
+        assert_eq!(resp.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+        assert!(cache.get(&req, &[]).is_some());
+
+        let resp = handle_dns_cache_flush(Some(&cache), &hyper::Method::POST);
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert_eq!(
+            get_response_str(resp).await,
+            "flushed 1 DNS cache entries\n"
+        );
+        assert!(cache.get(&req, &[]).is_none());
+    }
+
     // each of these tests assert that we can change the log level and the
     // appropriate response string is returned.
     //
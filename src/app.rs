@@ -115,6 +115,10 @@ pub async fn build_with_cert(
     )
     .await
     .context("admin server starts")?;
+    admin_server.add_handler(Arc::new(admin::ExplainRbacHandler::new(
+        state.clone(),
+        config.network.clone(),
+    )));
     let admin_address = admin_server.address();
 
     // Optionally create the HBONE proxy.
@@ -131,6 +135,9 @@ pub async fn build_with_cert(
         drain_rx.clone(),
     )
     .map_err(|e| anyhow::anyhow!("failed to start proxy factory {:?}", e))?;
+    if let Some(cache) = proxy_gen.dns_cache() {
+        admin_server.set_dns_cache(cache);
+    }
 
     if config.inpod_enabled {
         tracing::info!("in-pod mode enabled");
@@ -26,7 +26,13 @@ use hickory_resolver::config::{ResolverConfig, ResolverOpts};
 use hyper::http::uri::InvalidUri;
 use hyper::Uri;
 
+use crate::copy::ConnectionTimeouts;
 use crate::identity;
+use crate::proxy::connection_manager::ConnectionLimits;
+use crate::rbac::ext_authz::ExtAuthzConfig;
+use crate::state::outlier::OutlierDetectionConfig;
+use crate::state::service::LoadBalancerStrategy;
+use crate::state::workload::RateLimit;
 use crate::strng::Strng;
 #[cfg(any(test, feature = "testing"))]
 use {crate::test_helpers::MpscAckReceiver, crate::xds::LocalConfig, tokio::sync::Mutex};
@@ -54,8 +60,28 @@ const POOL_MAX_STREAMS_PER_CONNECTION: &str = "POOL_MAX_STREAMS_PER_CONNECTION";
 const POOL_UNUSED_RELEASE_TIMEOUT: &str = "POOL_UNUSED_RELEASE_TIMEOUT";
 const ENABLE_ORIG_SRC: &str = "ENABLE_ORIG_SRC";
 const PROXY_CONFIG: &str = "PROXY_CONFIG";
+const LOAD_BALANCER_STRATEGY: &str = "LOAD_BALANCER_STRATEGY";
+const CONNECT_RETRIES: &str = "CONNECT_RETRIES";
+const CONNECT_TIMEOUT: &str = "CONNECT_TIMEOUT";
+const OUTLIER_CONSECUTIVE_FAILURES: &str = "OUTLIER_CONSECUTIVE_FAILURES";
+const OUTLIER_BASE_EJECTION_TIME: &str = "OUTLIER_BASE_EJECTION_TIME";
+const OUTLIER_MAX_EJECTION_TIME: &str = "OUTLIER_MAX_EJECTION_TIME";
+const OUTLIER_MAX_EJECTION_PERCENT: &str = "OUTLIER_MAX_EJECTION_PERCENT";
+const EXT_AUTHZ_ADDRESS: &str = "EXT_AUTHZ_ADDRESS";
+const EXT_AUTHZ_TIMEOUT: &str = "EXT_AUTHZ_TIMEOUT";
+const EXT_AUTHZ_FAIL_OPEN: &str = "EXT_AUTHZ_FAIL_OPEN";
+const EXT_AUTHZ_CACHE_TTL: &str = "EXT_AUTHZ_CACHE_TTL";
+const INBOUND_CONNECTION_RATE_LIMIT: &str = "INBOUND_CONNECTION_RATE_LIMIT";
+const INBOUND_CONNECTION_RATE_LIMIT_BURST: &str = "INBOUND_CONNECTION_RATE_LIMIT_BURST";
+const MAX_CONNECTIONS_PER_DESTINATION: &str = "MAX_CONNECTIONS_PER_DESTINATION";
+const MAX_CONNECTIONS_PER_SOURCE: &str = "MAX_CONNECTIONS_PER_SOURCE";
+const IDLE_TIMEOUT: &str = "IDLE_TIMEOUT";
+const MAX_CONNECTION_DURATION: &str = "MAX_CONNECTION_DURATION";
+const DNS_ON_DEMAND_TIMEOUT: &str = "DNS_ON_DEMAND_TIMEOUT";
+const DNS_CACHE_MAX_ENTRIES: &str = "DNS_CACHE_MAX_ENTRIES";
 
 const UNSTABLE_ENABLE_SOCKS5: &str = "UNSTABLE_ENABLE_SOCKS5";
+const SOCKS5_CREDENTIALS_FILE: &str = "SOCKS5_CREDENTIALS_FILE";
 
 const DEFAULT_WORKER_THREADS: u16 = 2;
 const DEFAULT_ADMIN_PORT: u16 = 15000;
@@ -67,6 +93,12 @@ const DEFAULT_CLUSTER_ID: &str = "Kubernetes";
 const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
 const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24); // 24 hours
 const DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT: Duration = Duration::from_secs(60 * 5); // 5 minutes
+const DEFAULT_CONNECT_RETRIES: u32 = 2;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_DNS_ON_DEMAND_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_DNS_CACHE_MAX_ENTRIES: usize = 10_000;
+const DEFAULT_EXT_AUTHZ_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_EXT_AUTHZ_CACHE_TTL: Duration = Duration::from_secs(30);
 const DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION: u16 = 100; //Go: 100, Hyper: 200, Envoy: 2147483647 (lol), Spec recommended minimum 100
 
 const DEFAULT_INPOD_MARK: u32 = 1337;
@@ -74,10 +106,12 @@ const DEFAULT_INPOD_MARK: u32 = 1337;
 const ISTIO_META_PREFIX: &str = "ISTIO_META_";
 const DNS_CAPTURE_METADATA: &str = "DNS_CAPTURE";
 const DNS_PROXY_ADDR_METADATA: &str = "DNS_PROXY_ADDR";
+const SOCKS5_CREDENTIALS_METADATA: &str = "SOCKS5_CREDENTIALS";
 
-/// Fetch the XDS/CA root cert file path based on below constants
+/// Fetch the XDS/CA/ext_authz root cert file path based on below constants
 const XDS_ROOT_CA_ENV: &str = "XDS_ROOT_CA";
 const CA_ROOT_CA_ENV: &str = "CA_ROOT_CA";
+const EXT_AUTHZ_ROOT_CA_ENV: &str = "EXT_AUTHZ_ROOT_CA";
 const DEFAULT_ROOT_CERT_PROVIDER: &str = "./var/run/secrets/istio/root-cert.pem";
 const DEFAULT_TOKEN_PROVIDER: &str = "./var/run/secrets/tokens/istio-token";
 const CERT_SYSTEM: &str = "SYSTEM";
@@ -145,7 +179,34 @@ pub struct Config {
 
     pub pool_unused_release_timeout: Duration,
 
+    /// The number of times an outbound connection is retried against a different upstream if
+    /// connecting fails. Retries only happen before any data is sent.
+    pub connect_retries: u32,
+    /// The timeout for each outbound connection attempt, including the HBONE handshake. This also
+    /// bounds the TCP connects made by the inbound and connection pool paths.
+    pub connect_timeout: Duration,
+
+    /// The load balancing strategy used for services that do not specify one.
+    pub load_balancer_strategy: LoadBalancerStrategy,
+    /// Configuration for ejecting service endpoints that repeatedly fail to connect.
+    pub outlier_detection: OutlierDetectionConfig,
+    /// External authorization server used by CUSTOM policies. If unset, connections matching a
+    /// CUSTOM policy are denied.
+    pub ext_authz: Option<ExtAuthzConfig>,
+    /// Limits the rate of new inbound connections from each source to each workload, for workloads
+    /// that do not set their own limit. If unset, connections are not limited.
+    pub inbound_connection_rate_limit: Option<RateLimit>,
+    /// Caps on concurrent inbound and outbound connections per destination workload and source.
+    pub connection_limits: ConnectionLimits,
+    /// Idle timeout and max lifetime of proxied connections. If unset, connections stay open until
+    /// either side closes them.
+    pub connection_timeouts: ConnectionTimeouts,
+
     pub socks5_addr: Option<SocketAddr>,
+    /// YAML map of SOCKS5 usernames to passwords. If set, SOCKS5 clients must authenticate, and the
+    /// username must be the identity of the source workload.
+    #[serde(skip_serializing)]
+    pub socks5_credentials: Option<ConfigSource>,
     pub admin_addr: SocketAddr,
     pub stats_addr: SocketAddr,
     pub readiness_addr: SocketAddr,
@@ -154,6 +215,12 @@ pub struct Config {
     pub outbound_addr: SocketAddr,
     /// The socket address for the DNS proxy. Only applies if `dns_proxy` is true.
     pub dns_proxy_addr: SocketAddr,
+    /// How long the DNS proxy waits for an unknown hostname to be fetched on-demand before
+    /// forwarding the request. Only applies if `xds_on_demand` is true.
+    pub dns_on_demand_timeout: Duration,
+    /// The maximum number of upstream DNS responses cached by the DNS proxy. If 0, responses are
+    /// not cached.
+    pub dns_cache_max_entries: usize,
 
     /// The network of the node this ztunnel is running on.
     pub network: Strng,
@@ -334,6 +401,89 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         None
     };
 
+    let outlier_defaults = OutlierDetectionConfig::default();
+    let outlier_detection = OutlierDetectionConfig {
+        consecutive_failures: parse_default(
+            OUTLIER_CONSECUTIVE_FAILURES,
+            outlier_defaults.consecutive_failures,
+        )?,
+        base_ejection_time: match parse::<String>(OUTLIER_BASE_EJECTION_TIME)? {
+            Some(t) => duration_str::parse(t).unwrap_or(outlier_defaults.base_ejection_time),
+            None => outlier_defaults.base_ejection_time,
+        },
+        max_ejection_time: match parse::<String>(OUTLIER_MAX_EJECTION_TIME)? {
+            Some(t) => duration_str::parse(t).unwrap_or(outlier_defaults.max_ejection_time),
+            None => outlier_defaults.max_ejection_time,
+        },
+        max_ejection_percent: parse_default(
+            OUTLIER_MAX_EJECTION_PERCENT,
+            outlier_defaults.max_ejection_percent,
+        )?
+        .min(100),
+    };
+
+    let inbound_connection_rate_limit = match parse::<u32>(INBOUND_CONNECTION_RATE_LIMIT)? {
+        Some(connections_per_second) if connections_per_second > 0 => Some(RateLimit {
+            connections_per_second,
+            burst: parse_default(INBOUND_CONNECTION_RATE_LIMIT_BURST, connections_per_second)?,
+        }),
+        _ => None,
+    };
+
+    let connection_limits = ConnectionLimits {
+        max_connections_per_destination: parse(MAX_CONNECTIONS_PER_DESTINATION)?,
+        max_connections_per_source: parse(MAX_CONNECTIONS_PER_SOURCE)?,
+    };
+
+    // A zero or invalid duration disables the timeout.
+    let parse_timeout = |env: &str| -> Result<Option<Duration>, Error> {
+        Ok(parse::<String>(env)?
+            .and_then(|t| duration_str::parse(t).ok())
+            .filter(|t| !t.is_zero()))
+    };
+    let connection_timeouts = ConnectionTimeouts {
+        idle_timeout: parse_timeout(IDLE_TIMEOUT)?,
+        max_connection_duration: parse_timeout(MAX_CONNECTION_DURATION)?,
+    };
+
+    let ext_authz = match validate_uri(empty_to_none(parse(EXT_AUTHZ_ADDRESS)?))? {
+        Some(address) => {
+            let root_cert_provider = parse_default(
+                EXT_AUTHZ_ROOT_CA_ENV,
+                DEFAULT_ROOT_CERT_PROVIDER.to_string(),
+            )?;
+            let root_cert = if Path::new(&root_cert_provider).exists() {
+                RootCert::File(root_cert_provider.into())
+            } else if root_cert_provider.eq(&CERT_SYSTEM.to_string()) {
+                RootCert::Default
+            } else {
+                RootCert::Static(Bytes::from(root_cert_provider))
+            };
+            Some(ExtAuthzConfig {
+                address,
+                root_cert,
+                timeout: match parse::<String>(EXT_AUTHZ_TIMEOUT)? {
+                    Some(t) => duration_str::parse(t).unwrap_or(DEFAULT_EXT_AUTHZ_TIMEOUT),
+                    None => DEFAULT_EXT_AUTHZ_TIMEOUT,
+                },
+                fail_open: parse_default(EXT_AUTHZ_FAIL_OPEN, false)?,
+                cache_ttl: match parse::<String>(EXT_AUTHZ_CACHE_TTL)? {
+                    Some(t) => duration_str::parse(t).unwrap_or(DEFAULT_EXT_AUTHZ_CACHE_TTL),
+                    None => DEFAULT_EXT_AUTHZ_CACHE_TTL,
+                },
+            })
+        }
+        None => None,
+    };
+
+    let socks5_credentials = match parse::<PathBuf>(SOCKS5_CREDENTIALS_FILE)? {
+        Some(path) => Some(ConfigSource::File(path)),
+        None => pc
+            .proxy_metadata
+            .get(SOCKS5_CREDENTIALS_METADATA)
+            .map(|creds| ConfigSource::Static(Bytes::from(creds.clone()))),
+    };
+
     validate_config(Config {
         proxy: parse_default(ENABLE_PROXY, true)?,
         dns_proxy: pc
@@ -351,6 +501,12 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             None => DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT,
         },
 
+        connect_retries: parse_default(CONNECT_RETRIES, DEFAULT_CONNECT_RETRIES)?,
+        connect_timeout: match parse::<String>(CONNECT_TIMEOUT)? {
+            Some(t) => duration_str::parse(t).unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            None => DEFAULT_CONNECT_TIMEOUT,
+        },
+
         window_size: 4 * 1024 * 1024,
         connection_window_size: 4 * 1024 * 1024,
         frame_size: 1024 * 1024,
@@ -373,10 +529,16 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         ),
 
         socks5_addr,
+        socks5_credentials,
         inbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15008),
         inbound_plaintext_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15006),
         outbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15001),
         dns_proxy_addr,
+        dns_on_demand_timeout: match parse::<String>(DNS_ON_DEMAND_TIMEOUT)? {
+            Some(t) => duration_str::parse(t).unwrap_or(DEFAULT_DNS_ON_DEMAND_TIMEOUT),
+            None => DEFAULT_DNS_ON_DEMAND_TIMEOUT,
+        },
+        dns_cache_max_entries: parse_default(DNS_CACHE_MAX_ENTRIES, DEFAULT_DNS_CACHE_MAX_ENTRIES)?,
 
         network: parse(NETWORK)?.unwrap_or_default(),
         local_node: parse(NODE_NAME)?,
@@ -402,6 +564,15 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         },
         local_xds_config: parse::<PathBuf>(LOCAL_XDS_PATH)?.map(ConfigSource::File),
         xds_on_demand: parse_default(XDS_ON_DEMAND, false)?,
+        load_balancer_strategy: parse_default(
+            LOAD_BALANCER_STRATEGY,
+            LoadBalancerStrategy::default(),
+        )?,
+        outlier_detection,
+        ext_authz,
+        inbound_connection_rate_limit,
+        connection_limits,
+        connection_timeouts,
         proxy_metadata: pc.proxy_metadata,
 
         fake_ca,
@@ -424,12 +595,6 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
 }
 
 fn validate_config(cfg: Config) -> Result<Config, Error> {
-    if cfg.dns_proxy && cfg.xds_on_demand {
-        return Err(Error::ProxyConfig(anyhow!(
-            "DNS proxy does not currently support on-demand mode"
-        )));
-    }
-
     if !cfg.proxy && !cfg.dns_proxy {
         return Err(Error::ProxyConfig(anyhow!(
             "ztunnel run without any servers enabled"
@@ -12,17 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::proxy::metrics::ResponseFlags;
 use crate::proxy::ConnectionResult;
 use pin_project_lite::pin_project;
 use std::cmp;
 use std::future::Future;
 use std::io::IoSlice;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::task::{ready, Context, Poll};
+use std::time::Duration;
 use tokio::io;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
 use tracing::trace;
 
+/// ConnectionTimeouts bounds how long a proxied connection may stay open.
+#[derive(serde::Serialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionTimeouts {
+    /// Close the connection if no bytes are sent in either direction for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Close the connection once it has been open for this long, regardless of activity.
+    pub max_connection_duration: Option<Duration>,
+}
+
 // BufferedSplitter is a trait to expose splitting an IO object into a buffered reader and a writer
 pub trait BufferedSplitter: Unpin {
     type R: ResizeBufRead + Unpin;
@@ -62,10 +76,47 @@ const LARGE_BUFFER_SIZE: usize = 16_384 - 64;
 // Loosely inspired by https://github.com/golang/go/blame/5122a6796ef98e3453c994c95abd640596540bea/src/crypto/tls/conn.go#L873
 const RESIZE_THRESHOLD: u64 = 128 * 1024;
 
+// Activity records when bytes last moved through a connection, in either direction.
+struct Activity {
+    start: Instant,
+    // milliseconds since start
+    last: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        self.start + Duration::from_millis(self.last.load(Ordering::Relaxed))
+    }
+
+    // Resolves once there has been no activity for `timeout`.
+    async fn idle(&self, timeout: Duration) {
+        loop {
+            let deadline = self.last() + timeout;
+            if deadline <= Instant::now() {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
 pub async fn copy_bidirectional<A, B>(
     downstream: A,
     upstream: B,
     stats: &ConnectionResult,
+    timeouts: ConnectionTimeouts,
 ) -> Result<(), crate::proxy::Error>
 where
     A: BufferedSplitter,
@@ -76,22 +127,56 @@ where
     let (mut ru, mut wu) = upstream.split_into_buffered_reader();
 
     let (mut sent, mut received): (u64, u64) = (0, 0);
+    let activity = timeouts.idle_timeout.map(|_| Activity::new());
 
     let downstream_to_upstream = async {
-        let res = copy_buf(&mut rd, &mut wu, stats, false).await;
+        let res = copy_buf(&mut rd, &mut wu, stats, activity.as_ref(), false).await;
         trace!(?res, "send");
         sent = res?;
         wu.shutdown().await
     };
 
     let upstream_to_downstream = async {
-        let res = copy_buf(&mut ru, &mut wd, stats, true).await;
+        let res = copy_buf(&mut ru, &mut wd, stats, activity.as_ref(), true).await;
         trace!(?res, "recieve");
         received = res?;
         wd.shutdown().await
     };
 
-    tokio::try_join!(downstream_to_upstream, upstream_to_downstream)?;
+    let idle = async {
+        match (timeouts.idle_timeout, &activity) {
+            (Some(timeout), Some(activity)) => {
+                activity.idle(timeout).await;
+                timeout
+            }
+            _ => std::future::pending().await,
+        }
+    };
+    let max_duration = async {
+        match timeouts.max_connection_duration {
+            Some(max) => {
+                tokio::time::sleep(max).await;
+                max
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        res = async { tokio::try_join!(downstream_to_upstream, upstream_to_downstream) } => {
+            res?;
+        }
+        timeout = idle => {
+            trace!(?timeout, "connection idle");
+            stats.set_close_reason(ResponseFlags::StreamIdleTimeout);
+            return Err(crate::proxy::Error::IdleTimeout(timeout));
+        }
+        max = max_duration => {
+            trace!(?max, "connection reached max duration");
+            stats.set_close_reason(ResponseFlags::MaxDurationTimeout);
+            return Err(crate::proxy::Error::MaxConnectionDuration(max));
+        }
+    }
 
     trace!(sent, received, "copy complete");
     Ok(())
@@ -104,6 +189,7 @@ struct CopyBuf<'a, R: ?Sized, W: ?Sized> {
     reader: &'a mut R,
     writer: &'a mut W,
     metrics: &'a ConnectionResult,
+    activity: Option<&'a Activity>,
     amt: u64,
 }
 
@@ -111,6 +197,7 @@ async fn copy_buf<'a, R, W>(
     reader: &'a mut R,
     writer: &'a mut W,
     metrics: &ConnectionResult,
+    activity: Option<&Activity>,
     is_send: bool,
 ) -> std::io::Result<u64>
 where
@@ -122,6 +209,7 @@ where
         reader,
         writer,
         metrics,
+        activity,
         amt: 0,
     }
     .await
@@ -152,6 +240,9 @@ where
             } else {
                 me.metrics.increment_recv(i as u64);
             }
+            if let Some(activity) = me.activity {
+                activity.touch();
+            }
             let old = self.amt;
             self.amt += i as u64;
 
@@ -261,3 +352,114 @@ impl<R: AsyncRead + AsyncWrite> AsyncWrite for BufReader<R> {
         self.get_ref().is_write_vectored()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::metrics::{ConnectionOpen, Reporter, SecurityPolicy};
+    use crate::proxy::Error;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    fn connection_result() -> ConnectionResult {
+        ConnectionResult::new(
+            "127.0.0.1:12345".parse().unwrap(),
+            "127.0.0.2:8080".parse().unwrap(),
+            None,
+            std::time::Instant::now(),
+            ConnectionOpen {
+                reporter: Reporter::source,
+                source: None,
+                derived_source: None,
+                destination: None,
+                destination_service: None,
+                connection_security_policy: SecurityPolicy::unknown,
+                source_user: None,
+                connect_retries: 0,
+            },
+            test_proxy_metrics(),
+        )
+    }
+
+    // Sends a message from client to server every `interval`, `count` times.
+    async fn send_every(
+        client: &mut DuplexStream,
+        server: &mut DuplexStream,
+        interval: Duration,
+        count: usize,
+    ) {
+        for _ in 0..count {
+            tokio::time::sleep(interval).await;
+            client.write_all(b"hello").await.unwrap();
+            let mut buf = [0; 5];
+            server.read_exact(&mut buf).await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let (mut client, downstream) = tokio::io::duplex(1024);
+        let (upstream, mut server) = tokio::io::duplex(1024);
+        let stats = connection_result();
+        let timeouts = ConnectionTimeouts {
+            idle_timeout: Some(Duration::from_secs(10)),
+            max_connection_duration: None,
+        };
+        let start = Instant::now();
+        let (res, _) = tokio::join!(
+            copy_bidirectional(downstream, upstream, &stats, timeouts),
+            // Activity keeps the connection open past the idle timeout.
+            send_every(&mut client, &mut server, Duration::from_secs(6), 3),
+        );
+        assert!(matches!(res, Err(Error::IdleTimeout(_))), "{res:?}");
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(28) && elapsed < Duration::from_secs(29),
+            "{elapsed:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_connection_duration() {
+        let (mut client, downstream) = tokio::io::duplex(1024);
+        let (upstream, mut server) = tokio::io::duplex(1024);
+        let stats = connection_result();
+        let timeouts = ConnectionTimeouts {
+            idle_timeout: Some(Duration::from_secs(10)),
+            max_connection_duration: Some(Duration::from_secs(5)),
+        };
+        let start = Instant::now();
+        let res = tokio::select! {
+            res = copy_bidirectional(downstream, upstream, &stats, timeouts) => res,
+            _ = send_every(&mut client, &mut server, Duration::from_secs(1), 10) => {
+                panic!("connection should be closed")
+            }
+        };
+        assert!(
+            matches!(res, Err(Error::MaxConnectionDuration(_))),
+            "{res:?}"
+        );
+        assert!(start.elapsed() < Duration::from_secs(6));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_without_timeout() {
+        let (mut client, downstream) = tokio::io::duplex(1024);
+        let (upstream, mut server) = tokio::io::duplex(1024);
+        let stats = connection_result();
+        let timeouts = ConnectionTimeouts {
+            idle_timeout: Some(Duration::from_secs(10)),
+            max_connection_duration: Some(Duration::from_secs(60)),
+        };
+        let close = async {
+            send_every(&mut client, &mut server, Duration::from_secs(1), 1).await;
+            client.shutdown().await.unwrap();
+            server.shutdown().await.unwrap();
+        };
+        let (res, _) = tokio::join!(
+            copy_bidirectional(downstream, upstream, &stats, timeouts),
+            close
+        );
+        assert!(res.is_ok(), "{res:?}");
+    }
+}
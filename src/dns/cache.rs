@@ -0,0 +1,420 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{LowerName, Name, Record, RecordType};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_server::authority::LookupError;
+use hickory_server::server::Request;
+use keyed_priority_queue::KeyedPriorityQueue;
+
+use crate::dns::resolver::Answer;
+
+/// The TTL used for negative responses when the upstream resolver does not provide one.
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+/// Where the requests of a client are forwarded to. Responses are only shared between clients
+/// with the same upstream.
+#[derive(Debug, Clone, Default, Eq, Hash, PartialEq)]
+pub struct Upstream {
+    /// The forwarder may qualify the name with the search domains of the client.
+    pub search_domains: Vec<Name>,
+    /// The nameservers the forwarder sends requests to.
+    pub name_servers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+struct CacheKey {
+    name: LowerName,
+    record_type: RecordType,
+    upstream: Upstream,
+}
+
+impl CacheKey {
+    fn new(request: &Request, upstream: &Upstream) -> Self {
+        CacheKey {
+            name: request.query().name().clone(),
+            record_type: request.query().query_type(),
+            upstream: upstream.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CachedResponse {
+    /// The records returned by the upstream resolver, with their original TTLs.
+    Records(Vec<Record>),
+    /// A negative response, such as NXDOMAIN.
+    Error(ResponseCode),
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: CachedResponse,
+    inserted: Instant,
+    expiry: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    responses: HashMap<CacheKey, Entry>,
+    // The keys of `responses`, by expiry and by insertion time.
+    by_expiry: KeyedPriorityQueue<CacheKey, Reverse<Instant>>,
+    by_age: KeyedPriorityQueue<CacheKey, Reverse<Instant>>,
+}
+
+impl Entries {
+    fn insert(&mut self, key: CacheKey, entry: Entry) {
+        self.by_expiry.push(key.clone(), Reverse(entry.expiry));
+        self.by_age.push(key.clone(), Reverse(entry.inserted));
+        self.responses.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        self.responses.remove(key);
+        self.by_expiry.remove(key);
+        self.by_age.remove(key);
+    }
+
+    /// Drops expired entries, and then the oldest entry if there are still `max_entries`.
+    fn make_room(&mut self, max_entries: usize, now: Instant) {
+        while let Some((key, Reverse(expiry))) = self.by_expiry.peek() {
+            if *expiry > now {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+        }
+        if self.responses.len() >= max_entries {
+            if let Some((key, _)) = self.by_age.peek() {
+                let key = key.clone();
+                self.remove(&key);
+            }
+        }
+    }
+}
+
+/// ResponseCache caches the responses of the upstream resolver, for as long as their TTL allows.
+/// Negative responses are cached as well, for the negative TTL of the zone if known.
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ResponseCache {
+    /// Creates a cache holding at most `max_entries` responses. Once reached, expired entries
+    /// are dropped, and if that is not enough the oldest entry is.
+    pub fn new(max_entries: usize) -> Self {
+        ResponseCache {
+            max_entries,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the cached response for the request, if any. The TTLs of returned records are
+    /// reduced by the time they spent in the cache.
+    pub fn get(
+        &self,
+        request: &Request,
+        upstream: &Upstream,
+    ) -> Option<Result<Answer, LookupError>> {
+        self.get_at(request, upstream, Instant::now())
+    }
+
+    /// Caches the response of the upstream resolver for the request. Responses without a TTL and
+    /// failures other than negative responses are not cached.
+    pub fn insert(
+        &self,
+        request: &Request,
+        upstream: &Upstream,
+        response: &Result<Answer, LookupError>,
+    ) {
+        self.insert_at(request, upstream, response, Instant::now())
+    }
+
+    /// Removes all cached responses, returning how many were removed.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let flushed = entries.responses.len();
+        *entries = Entries::default();
+        flushed
+    }
+
+    fn get_at(
+        &self,
+        request: &Request,
+        upstream: &Upstream,
+        now: Instant,
+    ) -> Option<Result<Answer, LookupError>> {
+        let key = CacheKey::new(request, upstream);
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.responses.get(&key).filter(|e| e.expiry > now)?;
+        Some(match &entry.response {
+            CachedResponse::Records(records) => {
+                let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+                let records = records
+                    .iter()
+                    .map(|r| {
+                        let mut r = r.clone();
+                        r.set_ttl(r.ttl().saturating_sub(elapsed));
+                        r
+                    })
+                    .collect();
+                Ok(Answer::new(records, false))
+            }
+            CachedResponse::Error(code) => Err(LookupError::ResponseCode(*code)),
+        })
+    }
+
+    fn insert_at(
+        &self,
+        request: &Request,
+        upstream: &Upstream,
+        response: &Result<Answer, LookupError>,
+        now: Instant,
+    ) {
+        let Some((response, ttl)) = cacheable(response) else {
+            return;
+        };
+        if ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+        let key = CacheKey::new(request, upstream);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.responses.len() >= self.max_entries && !entries.responses.contains_key(&key) {
+            entries.make_room(self.max_entries, now);
+        }
+        entries.insert(
+            key,
+            Entry {
+                response,
+                inserted: now,
+                expiry: now + ttl,
+            },
+        );
+    }
+}
+
+/// Returns the response to cache and for how long, if it can be cached.
+fn cacheable(response: &Result<Answer, LookupError>) -> Option<(CachedResponse, Duration)> {
+    match response {
+        Ok(answer) => {
+            let records: Vec<Record> = answer.record_iter().cloned().collect();
+            let ttl = match records.iter().map(Record::ttl).min() {
+                Some(ttl) => Duration::from_secs(ttl as u64),
+                // No records, the name exists but not with this record type.
+                None => DEFAULT_NEGATIVE_TTL,
+            };
+            Some((CachedResponse::Records(records), ttl))
+        }
+        Err(LookupError::ResponseCode(code @ ResponseCode::NXDomain)) => {
+            Some((CachedResponse::Error(*code), DEFAULT_NEGATIVE_TTL))
+        }
+        Err(LookupError::ResolveError(e)) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound {
+                response_code: code @ (ResponseCode::NXDomain | ResponseCode::NoError),
+                negative_ttl,
+                ..
+            } => {
+                let ttl = negative_ttl
+                    .map(|ttl| Duration::from_secs(ttl as u64))
+                    .unwrap_or(DEFAULT_NEGATIVE_TTL);
+                Some((CachedResponse::Error(*code), ttl))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::dns::{a, a_request, aaaa_request, ipv4, n, socket_addr};
+    use hickory_server::server::Protocol;
+
+    fn request(name: &str) -> Request {
+        a_request(n(name), socket_addr("1.1.1.1:80"), Protocol::Udp)
+    }
+
+    fn records(answer: Answer) -> Vec<Record> {
+        answer.record_iter().cloned().collect()
+    }
+
+    #[test]
+    fn ttl() {
+        let cache = ResponseCache::new(10);
+        let now = Instant::now();
+        let req = request("www.example.com.");
+        let record = a(n("www.example.com."), ipv4("1.2.3.4"));
+        // The test records have a TTL of 5 seconds.
+        cache.insert_at(
+            &req,
+            &Upstream::default(),
+            &Ok(Answer::new(vec![record], false)),
+            now,
+        );
+
+        let answer = cache
+            .get_at(&req, &Upstream::default(), now + Duration::from_secs(2))
+            .unwrap()
+            .unwrap();
+        assert!(!answer.is_authoritative());
+        let got = records(answer);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].ttl(), 3);
+
+        assert!(cache
+            .get_at(&req, &Upstream::default(), now + Duration::from_secs(5))
+            .is_none());
+    }
+
+    #[test]
+    fn key() {
+        let cache = ResponseCache::new(10);
+        let now = Instant::now();
+        let req = request("www.example.com.");
+        let upstream = Upstream {
+            search_domains: vec![n("ns1.svc.cluster.local")],
+            name_servers: vec![socket_addr("10.96.0.10:53")],
+        };
+        let answer = Answer::new(vec![a(n("www.example.com."), ipv4("1.2.3.4"))], false);
+        cache.insert_at(&req, &upstream, &Ok(answer), now);
+
+        assert!(cache.get_at(&req, &upstream, now).is_some());
+        // Names are case insensitive.
+        assert!(cache
+            .get_at(&request("WWW.example.com."), &upstream, now)
+            .is_some());
+        // Different search domains, name servers, name or record type do not match.
+        assert!(cache.get_at(&req, &Upstream::default(), now).is_none());
+        let other_name_servers = Upstream {
+            name_servers: vec![socket_addr("10.96.0.11:53")],
+            ..upstream.clone()
+        };
+        assert!(cache.get_at(&req, &other_name_servers, now).is_none());
+        assert!(cache
+            .get_at(&request("example.com."), &upstream, now)
+            .is_none());
+        let aaaa = aaaa_request(
+            n("www.example.com."),
+            socket_addr("1.1.1.1:80"),
+            Protocol::Udp,
+        );
+        assert!(cache.get_at(&aaaa, &upstream, now).is_none());
+    }
+
+    #[test]
+    fn negative() {
+        let cache = ResponseCache::new(10);
+        let now = Instant::now();
+        let req = request("missing.example.com.");
+        cache.insert_at(
+            &req,
+            &Upstream::default(),
+            &Err(LookupError::ResponseCode(ResponseCode::NXDomain)),
+            now,
+        );
+        assert!(matches!(
+            cache.get_at(&req, &Upstream::default(), now + Duration::from_secs(1)),
+            Some(Err(LookupError::ResponseCode(ResponseCode::NXDomain)))
+        ));
+        assert!(cache
+            .get_at(&req, &Upstream::default(), now + DEFAULT_NEGATIVE_TTL)
+            .is_none());
+
+        // Other failures are not cached.
+        cache.insert_at(
+            &req,
+            &Upstream::default(),
+            &Err(LookupError::ResponseCode(ResponseCode::ServFail)),
+            now,
+        );
+        assert!(cache.get_at(&req, &Upstream::default(), now).is_none());
+    }
+
+    #[test]
+    fn bounded() {
+        let cache = ResponseCache::new(2);
+        let now = Instant::now();
+        let answer = |name: &str| Ok(Answer::new(vec![a(n(name), ipv4("1.2.3.4"))], false));
+        cache.insert_at(
+            &request("a.com."),
+            &Upstream::default(),
+            &answer("a.com."),
+            now,
+        );
+        let later = now + Duration::from_secs(10);
+        cache.insert_at(
+            &request("b.com."),
+            &Upstream::default(),
+            &answer("b.com."),
+            later,
+        );
+        // a.com has expired, so it is dropped to make room.
+        let later = later + Duration::from_secs(1);
+        cache.insert_at(
+            &request("c.com."),
+            &Upstream::default(),
+            &answer("c.com."),
+            later,
+        );
+        assert!(cache
+            .get_at(&request("b.com."), &Upstream::default(), later)
+            .is_some());
+        assert!(cache
+            .get_at(&request("c.com."), &Upstream::default(), later)
+            .is_some());
+
+        // Nothing has expired, so the oldest entry is dropped.
+        cache.insert_at(
+            &request("d.com."),
+            &Upstream::default(),
+            &answer("d.com."),
+            later,
+        );
+        assert!(cache
+            .get_at(&request("b.com."), &Upstream::default(), later)
+            .is_none());
+        assert!(cache
+            .get_at(&request("c.com."), &Upstream::default(), later)
+            .is_some());
+        assert!(cache
+            .get_at(&request("d.com."), &Upstream::default(), later)
+            .is_some());
+
+        // Replacing an entry does not drop another one.
+        cache.insert_at(
+            &request("d.com."),
+            &Upstream::default(),
+            &answer("d.com."),
+            later,
+        );
+        assert!(cache
+            .get_at(&request("c.com."), &Upstream::default(), later)
+            .is_some());
+
+        assert_eq!(cache.flush(), 2);
+        assert!(cache
+            .get_at(&request("d.com."), &Upstream::default(), later)
+            .is_none());
+    }
+}
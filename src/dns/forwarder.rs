@@ -12,22 +12,53 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
 use crate::dns::resolver::{Answer, Resolver};
+use crate::proxy::SocketFactory;
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
 use hickory_resolver::config::{ResolverConfig, ResolverOpts};
 use hickory_resolver::error::ResolveError;
-use hickory_resolver::name_server::TokioConnectionProvider;
-use hickory_resolver::TokioAsyncResolver;
+use hickory_resolver::name_server::{
+    GenericConnector, RuntimeProvider, TokioConnectionProvider, TokioRuntimeProvider,
+};
+use hickory_resolver::{AsyncResolver, TokioAsyncResolver};
 use hickory_server::authority::LookupError;
 use hickory_server::server::Request;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// A forwarding [Resolver] that delegates requests to an upstream [AsyncResolver].
+pub struct Forwarder(Inner);
 
-/// A forwarding [Resolver] that delegates requests to an upstream [TokioAsyncResolver].
-pub struct Forwarder(TokioAsyncResolver);
+enum Inner {
+    Tokio(TokioAsyncResolver),
+    SocketFactory(AsyncResolver<GenericConnector<SocketFactoryRuntimeProvider>>),
+}
 
 impl Forwarder {
     /// Creates a new [Forwarder] from the provided resolver configuration.
     pub fn new(cfg: ResolverConfig, opts: ResolverOpts) -> Result<Self, ResolveError> {
         let resolver = TokioAsyncResolver::new(cfg, opts, TokioConnectionProvider::default());
-        Ok(Self(resolver))
+        Ok(Self(Inner::Tokio(resolver)))
+    }
+
+    /// Creates a new [Forwarder] that creates the sockets used to reach the upstream resolver
+    /// with `socket_factory`, for example to send queries from the network namespace of a pod.
+    pub fn with_socket_factory(
+        cfg: ResolverConfig,
+        opts: ResolverOpts,
+        socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+    ) -> Self {
+        let provider = SocketFactoryRuntimeProvider {
+            runtime: TokioRuntimeProvider::default(),
+            socket_factory,
+        };
+        let resolver = AsyncResolver::new(cfg, opts, GenericConnector::new(provider));
+        Self(Inner::SocketFactory(resolver))
     }
 }
 
@@ -37,24 +68,72 @@ impl Resolver for Forwarder {
         // TODO(nmittler): Should we allow requests to the upstream resolver to be authoritative?
         let name = request.query().name();
         let rr_type = request.query().query_type();
-        self.0
-            .lookup(name, rr_type)
-            .await
-            .map(Answer::from)
-            .map_err(LookupError::from)
+        let res = match &self.0 {
+            Inner::Tokio(resolver) => resolver.lookup(name, rr_type).await,
+            Inner::SocketFactory(resolver) => resolver.lookup(name, rr_type).await,
+        };
+        res.map(Answer::from).map_err(LookupError::from)
+    }
+}
+
+/// A [RuntimeProvider] like [TokioRuntimeProvider], but the sockets are created with a
+/// [SocketFactory].
+#[derive(Clone)]
+struct SocketFactoryRuntimeProvider {
+    runtime: TokioRuntimeProvider,
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+}
+
+impl RuntimeProvider for SocketFactoryRuntimeProvider {
+    type Handle = <TokioRuntimeProvider as RuntimeProvider>::Handle;
+    type Timer = <TokioRuntimeProvider as RuntimeProvider>::Timer;
+    type Udp = UdpSocket;
+    type Tcp = AsyncIoTokioAsStd<TcpStream>;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.runtime.create_handle()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        // The socket must be created here rather than in the future, since the socket factory
+        // may need to run on the current thread (e.g. to enter a network namespace).
+        let socket = match server_addr {
+            SocketAddr::V4(_) => self.socket_factory.new_tcp_v4(),
+            SocketAddr::V6(_) => self.socket_factory.new_tcp_v6(),
+        };
+        Box::pin(async move {
+            let stream = socket?.connect(server_addr).await?;
+            Ok(AsyncIoTokioAsStd(stream))
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        local_addr: SocketAddr,
+        _server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        let socket = self.socket_factory.udp_bind(local_addr);
+        Box::pin(async move { socket })
     }
 }
 
 #[cfg(test)]
 #[cfg(any(unix, target_os = "windows"))]
 mod tests {
+    use super::Forwarder;
     use crate::dns::resolver::Resolver;
+    use crate::proxy::DefaultSocketFactory;
     use crate::test_helpers::dns::{a_request, n, socket_addr, system_forwarder};
     use crate::test_helpers::helpers::initialize_telemetry;
     use hickory_proto::op::ResponseCode;
     use hickory_proto::rr::RecordType;
     use hickory_resolver::error::ResolveErrorKind;
+    use hickory_resolver::system_conf::read_system_conf;
     use hickory_server::server::Protocol;
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn found() {
@@ -76,6 +155,27 @@ mod tests {
         assert_eq!(RecordType::A, record.record_type());
     }
 
+    #[tokio::test]
+    async fn found_with_socket_factory() {
+        initialize_telemetry();
+
+        let (cfg, opts) = read_system_conf().unwrap();
+        let f = Forwarder::with_socket_factory(cfg, opts, Arc::new(DefaultSocketFactory));
+
+        // Lookup a host.
+        let req = a_request(
+            n("www.google.com"),
+            socket_addr("1.1.1.1:80"),
+            Protocol::Udp,
+        );
+        let answer = f.lookup(&req).await.unwrap();
+        assert!(!answer.is_authoritative());
+
+        let record = answer.record_iter().next().unwrap();
+        assert_eq!(n("www.google.com."), *record.name());
+        assert_eq!(RecordType::A, record.record_type());
+    }
+
     #[tokio::test]
     async fn not_found() {
         initialize_telemetry();
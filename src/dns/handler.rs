@@ -98,7 +98,7 @@ async fn send_lookup<R: ResponseHandler>(
         answer.record_iter(),
         None.iter(),
         None.iter(),
-        None.iter(),
+        answer.additional_iter(),
     );
 
     // Send the response.
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use hickory_server::server::Request;
-use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
 use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
 use prometheus_client::metrics::histogram::Histogram;
@@ -30,6 +30,7 @@ pub struct Metrics {
     pub forwarded_requests: Family<DnsLabels, Counter>,
     pub forwarded_failures: Family<DnsLabels, Counter>,
     pub forwarded_duration: Family<DnsLabels, Histogram>,
+    pub cache_lookups: Family<DnsCacheLabels, Counter>,
 }
 
 impl Metrics {
@@ -65,11 +66,19 @@ impl Metrics {
             forwarded_duration.clone(),
         );
 
+        let cache_lookups = Family::default();
+        registry.register(
+            "dns_upstream_cache_lookups",
+            "Total number of DNS requests for upstream hostnames looked up in the cache, by whether they were found (unstable)",
+            cache_lookups.clone(),
+        );
+
         Self {
             requests,
             forwarded_requests,
             forwarded_failures,
             forwarded_duration,
+            cache_lookups,
         }
     }
 }
@@ -199,3 +208,48 @@ impl From<&ForwardedDuration<'_>> for DnsLabels {
         labels
     }
 }
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct DnsCacheLabels {
+    #[prometheus(flatten)]
+    dns: DnsLabels,
+    hit: CacheHit,
+}
+
+/// Whether a request was answered from the cache.
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub struct CacheHit(bool);
+
+impl EncodeLabelValue for CacheHit {
+    fn encode(&self, writer: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        writer.write_str(if self.0 { "true" } else { "false" })
+    }
+}
+
+#[derive(Clone)]
+pub struct CacheLookup<'a> {
+    pub request: &'a Request,
+    pub source: Option<&'a Workload>,
+    pub hit: bool,
+}
+
+impl Recorder<CacheLookup<'_>, u64> for Metrics {
+    fn record(&self, reason: &CacheLookup, count: u64) {
+        self.cache_lookups
+            .get_or_create(&DnsCacheLabels::from(reason))
+            .inc_by(count);
+    }
+}
+
+impl From<&CacheLookup<'_>> for DnsCacheLabels {
+    fn from(value: &CacheLookup) -> Self {
+        let mut dns = DnsLabels::new(value.request);
+        if let Some(source) = &value.source {
+            dns = dns.with_source(source)
+        }
+        DnsCacheLabels {
+            dns,
+            hit: CacheHit(value.hit),
+        }
+    }
+}
@@ -31,6 +31,7 @@ pub trait Resolver: Sync + Send {
 #[derive(Debug)]
 pub struct Answer {
     records: Vec<Record>,
+    additionals: Vec<Record>,
     is_authoritative: bool,
 }
 
@@ -38,15 +39,28 @@ impl Answer {
     pub fn new(records: Vec<Record>, is_authoritative: bool) -> Self {
         Self {
             records,
+            additionals: Vec::new(),
             is_authoritative,
         }
     }
 
+    /// Adds records for the additional section of the response, such as the addresses of the
+    /// targets of SRV records.
+    pub fn with_additionals(mut self, additionals: Vec<Record>) -> Self {
+        self.additionals = additionals;
+        self
+    }
+
     /// Returns an iterator over the records returned by the [Resolver].
     pub fn record_iter(&self) -> RecordIter<'_> {
         RecordIter(self.records.iter())
     }
 
+    /// Returns an iterator over the additional records returned by the [Resolver].
+    pub fn additional_iter(&self) -> RecordIter<'_> {
+        RecordIter(self.additionals.iter())
+    }
+
     /// Indicates whether the [Resolver] is the authority for the returned records.
     pub fn is_authoritative(&self) -> bool {
         self.is_authoritative
@@ -57,6 +71,7 @@ impl From<Lookup> for Answer {
     fn from(value: Lookup) -> Self {
         Self {
             records: value.records().to_vec(),
+            additionals: Vec::new(),
             is_authoritative: false, // Non-authoritative, since results came from upstream resolver.
         }
     }
@@ -12,35 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp::Reverse;
 use std::collections::HashSet;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::ops::Deref;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use drain::Watch;
 use hickory_proto::error::ProtoErrorKind;
 use hickory_proto::op::ResponseCode;
-use hickory_proto::rr::rdata::{A, AAAA, CNAME};
+use hickory_proto::rr::rdata::{A, AAAA, CNAME, PTR, SRV};
 use hickory_proto::rr::{Name, RData, Record, RecordType};
-use hickory_resolver::config::ResolverConfig;
-use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::system_conf::{parse_resolv_conf, read_system_conf};
 use hickory_server::authority::LookupError;
 use hickory_server::server::Request;
 use hickory_server::ServerFuture;
 use itertools::Itertools;
+use keyed_priority_queue::KeyedPriorityQueue;
 use once_cell::sync::Lazy;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 use crate::proxy::SocketFactory;
 
 use crate::config::ProxyMode;
 use crate::dns;
+use crate::dns::cache::{ResponseCache, Upstream};
 use crate::dns::metrics::{
-    DnsRequest, ForwardedDuration, ForwardedFailure, ForwardedRequest, Metrics,
+    CacheLookup, DnsRequest, ForwardedDuration, ForwardedFailure, ForwardedRequest, Metrics,
 };
 use crate::dns::name_util::{has_domain, trim_domain};
 use crate::dns::resolver::{Answer, Resolver};
@@ -50,10 +53,14 @@ use crate::socket::to_canonical;
 use crate::state::workload::address::Address;
 use crate::state::workload::{NetworkAddress, Workload};
 use crate::state::DemandProxyState;
-use crate::strng::Strng;
+use crate::strng::{self, Strng};
 
 const DEFAULT_TCP_REQUEST_TIMEOUT: u64 = 5;
 const DEFAULT_TTL_SECONDS: u32 = 30;
+/// How long to skip on-demand fetches for a hostname that was fetched but not found.
+const ON_DEMAND_MISS_TTL: Duration = Duration::from_secs(30);
+/// The maximum number of on-demand misses to remember.
+const MAX_ON_DEMAND_MISSES: usize = 10_000;
 
 static SVC: Lazy<Name> = Lazy::new(|| as_name("svc"));
 
@@ -75,6 +82,8 @@ impl Server {
     /// * `network` - The network of the current node.
     /// * `state` - The state of ztunnel.
     /// * `forwarder` - The forwarder to use for requests not handled by this server.
+    /// * `cache` - If set, caches the responses of the forwarder.
+    /// * `on_demand_timeout` - How long to wait for unknown hostnames to be fetched on-demand.
     #[allow(clippy::too_many_arguments)] // no good way of grouping arguments here..
     pub async fn new<S: AsRef<str>>(
         domain: String,
@@ -82,6 +91,8 @@ impl Server {
         network: S,
         state: DemandProxyState,
         forwarder: Arc<dyn Forwarder>,
+        cache: Option<Arc<ResponseCache>>,
+        on_demand_timeout: Duration,
         metrics: Arc<Metrics>,
         drain: Watch,
         socket_factory: &(dyn SocketFactory + Send + Sync),
@@ -92,6 +103,8 @@ impl Server {
             network.as_ref().to_string(),
             state,
             forwarder,
+            cache,
+            on_demand_timeout,
             metrics,
         )));
         let mut server = ServerFuture::new(handler);
@@ -156,13 +169,52 @@ impl Server {
     }
 }
 
+/// Remembers on-demand keys that were fetched but did not resolve to anything, so that repeated
+/// queries for them are forwarded without waiting on XDS again.
+#[derive(Default)]
+struct OnDemandMisses {
+    // Keyed by on-demand key, by expiry. Since all entries share a TTL, the first to expire is
+    // also the oldest.
+    misses: Mutex<KeyedPriorityQueue<Strng, Reverse<Instant>>>,
+}
+
+impl OnDemandMisses {
+    fn contains(&self, key: &Strng, now: Instant) -> bool {
+        self.misses
+            .lock()
+            .unwrap()
+            .get_priority(key)
+            .is_some_and(|Reverse(expiry)| *expiry > now)
+    }
+
+    /// Records the keys as misses. Expired misses are dropped, and then the oldest ones if there
+    /// are too many.
+    fn insert(&self, keys: impl IntoIterator<Item = Strng>, now: Instant) {
+        let mut misses = self.misses.lock().unwrap();
+        for key in keys {
+            while let Some(&Reverse(expiry)) = misses.peek().map(|(_, p)| p) {
+                let full =
+                    misses.len() >= MAX_ON_DEMAND_MISSES && misses.get_priority(&key).is_none();
+                if expiry > now && !full {
+                    break;
+                }
+                misses.pop();
+            }
+            misses.push(key, Reverse(now + ON_DEMAND_MISS_TTL));
+        }
+    }
+}
+
 /// A DNS [Resolver] backed by the ztunnel [DemandProxyState].
 struct Store {
     network: Strng,
     state: DemandProxyState,
     forwarder: Arc<dyn Forwarder>,
+    cache: Option<Arc<ResponseCache>>,
     domain: Name,
     svc_domain: Name,
+    on_demand_timeout: Duration,
+    on_demand_misses: OnDemandMisses,
     metrics: Arc<Metrics>,
 }
 
@@ -172,6 +224,8 @@ impl Store {
         network: String,
         state: DemandProxyState,
         forwarder: Arc<dyn Forwarder>,
+        cache: Option<Arc<ResponseCache>>,
+        on_demand_timeout: Duration,
         metrics: Arc<Metrics>,
     ) -> Self {
         let domain = as_name(domain);
@@ -181,19 +235,55 @@ impl Store {
             network: network.into(),
             state,
             forwarder,
+            cache,
             domain,
             svc_domain,
+            on_demand_timeout,
+            on_demand_misses: Default::default(),
             metrics,
         }
     }
 
-    /// Find the workload for the client address.
-    fn find_client(&self, client_addr: SocketAddr) -> Option<Workload> {
-        let state = self.state.read();
-        state.workloads.find_address(&NetworkAddress {
+    /// Find the workload for the client address, fetching it on-demand if needed.
+    async fn find_client(&self, client_addr: SocketAddr) -> Option<Workload> {
+        let addr = NetworkAddress {
             network: self.network.clone(),
             address: client_addr.ip(),
-        })
+        };
+        if let Some(wl) = self.state.read().workloads.find_address(&addr) {
+            return Some(wl);
+        }
+        if !self.state.supports_on_demand() {
+            return None;
+        }
+        tokio::time::timeout(self.on_demand_timeout, self.state.fetch_workload(&addr))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Finds the hostname for a reverse lookup, such as `4.3.2.1.in-addr.arpa.`. Service VIPs
+    /// resolve to the service hostname. Workload IPs resolve to the workload hostname, or to the
+    /// Kubernetes pod FQDN (`<dashed-ip>.<namespace>.pod.<cluster-domain>`) if it has none.
+    async fn find_ptr(&self, client: &Workload, name: &Name) -> Option<Name> {
+        let addr = NetworkAddress {
+            network: client.network.clone(),
+            address: ptr_to_ip(name)?,
+        };
+        let address = tokio::time::timeout(self.on_demand_timeout, self.state.fetch_address(&addr))
+            .await
+            .ok()
+            .flatten()?;
+        let hostname = match address {
+            Address::Service(svc) => svc.hostname.to_string(),
+            Address::Workload(wl) if !wl.hostname.is_empty() => wl.hostname.to_string(),
+            Address::Workload(wl) => {
+                let dashed = addr.address.to_string().replace(['.', ':'], "-");
+                let pod_name = as_name(format!("{dashed}.{}.pod", wl.namespace));
+                return Some(to_fqdn(append_name(pod_name, &self.domain)));
+            }
+        };
+        Name::from_str(&hostname).ok().map(to_fqdn)
     }
 
     /// Enumerates the possible aliases for the requested hostname
@@ -253,62 +343,9 @@ impl Store {
     }
 
     /// Attempts to expand the requested hostname into one or more possible
-    /// Kubernetes FQDNs.
-    ///
-    /// The k8s FQDN forms supported by Ambient:
-    ///
-    /// - Standard service:
-    ///   <service-name>.<namespace>.svc.<cluster-domain>
-    /// - Pod host when sub-domain is set (e.g. when part of a statefulset):
-    ///   <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>
-    ///
-    /// Everything else will not be handled directly by Ambient and will instead
-    /// just be forwarded to k8s.
+    /// Kubernetes FQDNs. See [to_kube_fqdns].
     fn to_kube_fqdns(&self, name: &Name, namespaced_domain: &Name) -> Vec<Name> {
-        let mut out = Vec::new();
-
-        // Rather than just blindly adding every possible extension, only add the extensions
-        // possible given the requested hostname.
-        let iter = name.iter();
-        match iter.len() {
-            1 => {
-                // Only one label in the name. Assume the client is calling a service by name
-                // within the same namespace. Append "<ns>.svc.cluster.local".
-                out.push(append_name(name.clone(), namespaced_domain));
-            }
-            2 => {
-                // Expand <service-name>.<namespace> to
-                // <service-name>.<namespace>.svc.<cluster-domain>.
-                out.push(append_name(name.clone(), &self.svc_domain));
-                // Expand <pod-hostname>.<pod-sub-domain> to
-                // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
-                out.push(append_name(name.clone(), namespaced_domain));
-            }
-            3 => {
-                if has_domain(name, SVC.deref()) {
-                    // Expand <service-name>.<namespace>.svc to
-                    // <service-name>.<namespace>.svc.<cluster-domain>.
-                    out.push(append_name(name.clone(), &self.domain));
-                }
-
-                // Expand <pod-hostname>.<pod-sub-domain>.<namespace> to
-                // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
-                out.push(append_name(name.clone(), &self.svc_domain));
-            }
-            4 => {
-                if has_domain(name, SVC.deref()) {
-                    // Expand <pod-hostname>.<pod-sub-domain>.<namespace>.svc to
-                    // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
-                    out.push(append_name(name.clone(), &self.domain));
-                }
-            }
-            _ => {
-                // Everything else is either already an FQDN or not a supported
-                // kubernetes hostname.
-            }
-        }
-
-        out
+        to_kube_fqdns(name, &self.domain, &self.svc_domain, namespaced_domain)
     }
 
     fn find_server(&self, client: &Workload, requested_name: &Name) -> Option<ServerMatch> {
@@ -368,6 +405,63 @@ impl Store {
         None
     }
 
+    /// Like [Store::find_server], but if the hostname is not known locally, the candidate hostnames
+    /// are fetched on-demand. This waits at most `on_demand_timeout`, after which the request is
+    /// expected to be forwarded. Candidates that were recently fetched without result are skipped.
+    async fn fetch_server(&self, client: &Workload, requested_name: &Name) -> Option<ServerMatch> {
+        if let Some(server) = self.find_server(client, requested_name) {
+            return Some(server);
+        }
+        if !self.state.supports_on_demand() {
+            return None;
+        }
+        let now = Instant::now();
+        let keys: Vec<Strng> = self
+            .on_demand_keys(client, requested_name)
+            .into_iter()
+            .filter(|k| !self.on_demand_misses.contains(k, now))
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+        debug!(name=%requested_name, ?keys, "fetching DNS candidates on-demand");
+        let fetch =
+            futures::future::join_all(keys.iter().map(|k| self.state.fetch_on_demand(k.clone())));
+        if tokio::time::timeout(self.on_demand_timeout, fetch)
+            .await
+            .is_err()
+        {
+            debug!(name=%requested_name, "timed out fetching DNS candidates on-demand");
+        }
+        let server = self.find_server(client, requested_name);
+        if server.is_none() {
+            self.on_demand_misses.insert(keys, Instant::now());
+        }
+        server
+    }
+
+    /// Returns the on-demand keys for each candidate hostname of the requested name, in the
+    /// `<namespace>/<hostname>` form. Only Kubernetes service FQDNs
+    /// (`<service>.<namespace>.svc.<cluster-domain>`) are candidates; other hostnames, such as
+    /// external names, are never fetched on-demand.
+    fn on_demand_keys(&self, client: &Workload, requested_name: &Name) -> Vec<Strng> {
+        self.get_aliases(client, requested_name)
+            .into_iter()
+            .filter_map(|alias| {
+                let mut name = alias.name;
+                if !self.svc_domain.zone_of(&name)
+                    || name.num_labels() != self.svc_domain.num_labels() + 2
+                {
+                    return None;
+                }
+                let namespace = String::from_utf8_lossy(name.iter().nth(1)?).to_string();
+                name.set_fqdn(false);
+                Some(strng::format!("{namespace}/{name}"))
+            })
+            .unique()
+            .collect()
+    }
+
     /// Gets the list of addresses of the requested record type from the server.
     fn get_addresses(
         &self,
@@ -430,6 +524,104 @@ impl Store {
         addrs
     }
 
+    /// Answers an SRV query of the form `_<port>._tcp.<service-host>`. Services do not carry port
+    /// names, so the port label is the numeric service port. Services with VIPs have a single
+    /// target, the service itself. Headless services have a target per endpoint, named after the
+    /// workload hostname, or `<dashed-ip>.<service-host>` if it has none. The addresses of the
+    /// targets are returned as additional records.
+    async fn lookup_srv(
+        &self,
+        client: &Workload,
+        request: &Request,
+        requested_name: Name,
+    ) -> Result<Answer, LookupError> {
+        let Some((port, host)) = parse_srv_name(&requested_name) else {
+            return self.forward(Some(client), request).await;
+        };
+        let Some(service_match) = self.fetch_server(client, &host).await else {
+            // Unknown host. Forward to the upstream resolver.
+            return self.forward(Some(client), request).await;
+        };
+        let Address::Service(service) = &service_match.server else {
+            // Workloads do not have ports to advertise.
+            return self.forward(Some(client), request).await;
+        };
+
+        self.metrics.increment(&DnsRequest {
+            request,
+            source: Some(client),
+        });
+
+        let mut records = Vec::new();
+        let mut additionals = Vec::new();
+        if !service.ports.contains_key(&port) {
+            // The service exists, but not with this port.
+            return Ok(Answer::new(records, true));
+        }
+
+        // Wildcard services are targeted through the requested host.
+        let target = if service_match.name.is_wildcard() {
+            to_fqdn(host)
+        } else {
+            service_match.name.clone()
+        };
+        if !service.vips.is_empty() {
+            records.push(srv_record(requested_name, port, target.clone()));
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                let addresses = self.get_addresses(client, &service_match.server, record_type);
+                ip_records(target.clone(), addresses, &mut additionals);
+            }
+            return Ok(Answer::new(records, true).with_additionals(additionals));
+        }
+
+        // Headless service. Use the endpoints.
+        let state = self.state.read();
+        for ep in service.endpoints.values() {
+            let target_port = ep
+                .port
+                .get(&port)
+                .or(service.ports.get(&port))
+                .copied()
+                .filter(|p| *p != 0)
+                .unwrap_or(port);
+            let hostname = state
+                .workloads
+                .find_uid(&ep.workload_uid)
+                .map(|wl| wl.hostname)
+                .filter(|h| !h.is_empty());
+            let ep_target = match (hostname, &ep.address) {
+                (Some(hostname), _) => match Name::from_str(&hostname) {
+                    Ok(name) => to_fqdn(name),
+                    Err(_) => continue,
+                },
+                (None, Some(addr)) => {
+                    let dashed = addr.address.to_string().replace(['.', ':'], "-");
+                    append_name(as_name(dashed), &target)
+                }
+                (None, None) => continue,
+            };
+            records.push(srv_record(
+                requested_name.clone(),
+                target_port,
+                ep_target.clone(),
+            ));
+            if let Some(addr) = &ep.address {
+                ip_records(ep_target, vec![addr.address], &mut additionals);
+            }
+        }
+        Ok(Answer::new(records, true).with_additionals(additionals))
+    }
+
+    /// Where the forwarder sends the requests of the client, for caching its responses.
+    fn upstream(&self, client: Option<&Workload>) -> Upstream {
+        Upstream {
+            search_domains: client
+                .map(|client| self.forwarder.search_domains(client))
+                .unwrap_or_default(),
+            name_servers: self.forwarder.name_servers(),
+        }
+    }
+
     async fn forward(
         &self,
         client: Option<&Workload>,
@@ -441,6 +633,19 @@ impl Store {
             source: client,
         });
 
+        // Serve the request from the cache, if possible.
+        if let Some(cache) = &self.cache {
+            let res = cache.get(request, &self.upstream(client));
+            self.metrics.increment(&CacheLookup {
+                request,
+                source: client,
+                hit: res.is_some(),
+            });
+            if let Some(res) = res {
+                return res;
+            }
+        }
+
         // Increment counter for forwarded requests.
         self.metrics.increment(&ForwardedRequest {
             request,
@@ -459,7 +664,12 @@ impl Store {
             );
         });
 
-        match self.forwarder.forward(client, request).await {
+        let res = self.forwarder.forward(client, request).await;
+        if let Some(cache) = &self.cache {
+            // The forwarder may have loaded new settings while forwarding, so look them up again.
+            cache.insert(request, &self.upstream(client), &res);
+        }
+        match res {
             Ok(answer) => Ok(answer),
             Err(e) => {
                 // Increment counter for forwarding failures.
@@ -478,7 +688,7 @@ impl Store {
 impl Resolver for Store {
     async fn lookup(&self, request: &Request) -> Result<Answer, LookupError> {
         // Find the client workload.
-        let client = match self.find_client(to_canonical(request.src())) {
+        let client = match self.find_client(to_canonical(request.src())).await {
             None => {
                 // TODO(nmittler): make forwarding optional here.
                 // Increment request counter.
@@ -492,15 +702,33 @@ impl Resolver for Store {
             Some(client) => client,
         };
 
-        // Make sure the request is for IP records. Anything else, we forward.
+        // Make sure the request is for IP records or a reverse lookup. Anything else, we forward.
         let record_type = request.query().query_type();
         if !is_record_type_supported(record_type) {
             return self.forward(Some(&client), request).await;
         }
 
-        // Find the service for the requested host.
         let requested_name = Name::from(request.query().name().clone());
-        let Some(service_match) = self.find_server(&client, &requested_name) else {
+        if record_type == RecordType::PTR {
+            let Some(hostname) = self.find_ptr(&client, &requested_name).await else {
+                // Not a mesh address. Forward to the upstream resolver.
+                return self.forward(Some(&client), request).await;
+            };
+            self.metrics.increment(&DnsRequest {
+                request,
+                source: Some(&client),
+            });
+            return Ok(Answer::new(
+                vec![ptr_record(requested_name, hostname)],
+                true,
+            ));
+        }
+        if record_type == RecordType::SRV {
+            return self.lookup_srv(&client, request, requested_name).await;
+        }
+
+        // Find the service for the requested host.
+        let Some(service_match) = self.fetch_server(&client, &requested_name).await else {
             // Unknown host. Forward to the upstream resolver.
             return self.forward(Some(&client), request).await;
         };
@@ -597,6 +825,101 @@ struct ServerMatch {
     server: Address,
 }
 
+/// Attempts to expand the requested hostname into one or more possible
+/// Kubernetes FQDNs.
+///
+/// The k8s FQDN forms supported by Ambient:
+///
+/// - Standard service:
+///   <service-name>.<namespace>.svc.<cluster-domain>
+/// - Pod host when sub-domain is set (e.g. when part of a statefulset):
+///   <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>
+///
+/// Everything else will not be handled directly by Ambient and will instead
+/// just be forwarded to k8s.
+fn to_kube_fqdns(
+    name: &Name,
+    domain: &Name,
+    svc_domain: &Name,
+    namespaced_domain: &Name,
+) -> Vec<Name> {
+    let mut out = Vec::new();
+
+    // Rather than just blindly adding every possible extension, only add the extensions
+    // possible given the requested hostname.
+    let iter = name.iter();
+    match iter.len() {
+        1 => {
+            // Only one label in the name. Assume the client is calling a service by name
+            // within the same namespace. Append "<ns>.svc.cluster.local".
+            out.push(append_name(name.clone(), namespaced_domain));
+        }
+        2 => {
+            // Expand <service-name>.<namespace> to
+            // <service-name>.<namespace>.svc.<cluster-domain>.
+            out.push(append_name(name.clone(), svc_domain));
+            // Expand <pod-hostname>.<pod-sub-domain> to
+            // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
+            out.push(append_name(name.clone(), namespaced_domain));
+        }
+        3 => {
+            if has_domain(name, SVC.deref()) {
+                // Expand <service-name>.<namespace>.svc to
+                // <service-name>.<namespace>.svc.<cluster-domain>.
+                out.push(append_name(name.clone(), domain));
+            }
+
+            // Expand <pod-hostname>.<pod-sub-domain>.<namespace> to
+            // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
+            out.push(append_name(name.clone(), svc_domain));
+        }
+        4 => {
+            if has_domain(name, SVC.deref()) {
+                // Expand <pod-hostname>.<pod-sub-domain>.<namespace>.svc to
+                // <pod-hostname>.<pod-sub-domain>.<namespace>.svc.<cluster-domain>.
+                out.push(append_name(name.clone(), domain));
+            }
+        }
+        _ => {
+            // Everything else is either already an FQDN or not a supported
+            // kubernetes hostname.
+        }
+    }
+
+    out
+}
+
+/// Returns the hostnames to search for when resolving `name` on behalf of a client in `namespace`,
+/// in order of preference. Like the DNS proxy, the name is first expanded into the possible
+/// Kubernetes FQDNs. Since the name has not been through a resolver yet, it is then qualified with
+/// each of the `search_domains`.
+pub fn search_names(
+    name: &Name,
+    cluster_domain: &str,
+    namespace: &str,
+    search_domains: &[Name],
+) -> Vec<Name> {
+    let domain = as_name(cluster_domain);
+    let svc_domain = append_name(as_name("svc"), &domain);
+    let namespaced_domain = append_name(as_name(namespace), &svc_domain);
+
+    let mut out = vec![name.clone()];
+    out.extend(to_kube_fqdns(
+        name,
+        &domain,
+        &svc_domain,
+        &namespaced_domain,
+    ));
+    if !name.is_fqdn() {
+        out.extend(
+            search_domains
+                .iter()
+                .filter_map(|search_domain| name.clone().append_domain(search_domain).ok()),
+        );
+    }
+    out.into_iter().unique().collect()
+}
+
 fn as_name<T: AsRef<str>>(name: T) -> Name {
     Name::from_str(name.as_ref()).unwrap()
 }
@@ -626,7 +949,60 @@ fn get_wildcards(name: &Name) -> Vec<Name> {
 }
 
 fn is_record_type_supported(record_type: RecordType) -> bool {
-    matches!(record_type, RecordType::A | RecordType::AAAA)
+    matches!(
+        record_type,
+        RecordType::A | RecordType::AAAA | RecordType::PTR | RecordType::SRV
+    )
+}
+
+/// Parses an SRV query name of the form `_<port>._tcp.<host>`, returning the port and the host.
+fn parse_srv_name(name: &Name) -> Option<(u16, Name)> {
+    let mut labels = name.iter();
+    let port = labels.next()?.strip_prefix(b"_")?;
+    let port = std::str::from_utf8(port).ok()?.parse().ok()?;
+    if !labels.next()?.eq_ignore_ascii_case(b"_tcp") || labels.len() == 0 {
+        return None;
+    }
+    let mut host = Name::from_labels(labels).ok()?;
+    host.set_fqdn(name.is_fqdn());
+    Some((port, host))
+}
+
+/// Parses the address from a reverse lookup name, either `4.3.2.1.in-addr.arpa.` for IPv4 or the
+/// reversed nibbles of the address under `ip6.arpa.` for IPv6.
+fn ptr_to_ip(name: &Name) -> Option<IpAddr> {
+    let labels: Vec<String> = name
+        .iter()
+        .map(|l| String::from_utf8_lossy(l).to_ascii_lowercase())
+        .collect();
+    match labels.as_slice() {
+        [octets @ .., in_addr, arpa] if in_addr == "in-addr" && arpa == "arpa" => {
+            if octets.len() != 4 {
+                return None;
+            }
+            let octets: Vec<&str> = octets.iter().rev().map(String::as_str).collect();
+            octets.join(".").parse().ok()
+        }
+        [nibbles @ .., ip6, arpa] if ip6 == "ip6" && arpa == "arpa" => {
+            if nibbles.len() != 32 {
+                return None;
+            }
+            let mut addr: u128 = 0;
+            for nibble in nibbles.iter().rev() {
+                if nibble.len() != 1 {
+                    return None;
+                }
+                addr = (addr << 4) | u128::from_str_radix(nibble, 16).ok()?;
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(addr)))
+        }
+        _ => None,
+    }
+}
+
+fn to_fqdn(mut name: Name) -> Name {
+    name.set_fqdn(true);
+    name
 }
 
 fn is_record_type(addr: &IpAddr, record_type: RecordType) -> bool {
@@ -644,6 +1020,15 @@ fn cname_record(name: Name, canonical_name: Name) -> Record {
     to_record(name, RData::CNAME(CNAME(canonical_name)))
 }
 
+fn srv_record(name: Name, port: u16, target: Name) -> Record {
+    // All targets are equally preferred.
+    to_record(name, RData::SRV(SRV::new(0, 1, port, target)))
+}
+
+fn ptr_record(name: Name, hostname: Name) -> Record {
+    to_record(name, RData::PTR(PTR(hostname)))
+}
+
 fn ip_records(name: Name, addrs: Vec<IpAddr>, out: &mut Vec<Record>) {
     for addr in addrs {
         match addr {
@@ -659,6 +1044,9 @@ pub trait Forwarder: Send + Sync {
     /// Returns the list of resolver search domains for the client.
     fn search_domains(&self, client: &Workload) -> Vec<Name>;
 
+    /// Returns the addresses of the upstream nameservers.
+    fn name_servers(&self) -> Vec<SocketAddr>;
+
     /// Forwards the request from the client.
     async fn forward(
         &self,
@@ -671,19 +1059,37 @@ pub trait Forwarder: Send + Sync {
 pub fn forwarder_for_mode(proxy_mode: ProxyMode) -> Result<Arc<dyn Forwarder>, Error> {
     Ok(match proxy_mode {
         ProxyMode::Shared => {
-            // TODO(https://github.com/istio/ztunnel/issues/555): Use pod settings if available.
+            // In in-pod mode, pods use a forwarder from forwarder_for_resolv_conf instead, if
+            // their settings are available.
             Arc::new(SystemForwarder::new()?)
         }
         ProxyMode::Dedicated => Arc::new(SystemForwarder::new()?),
     })
 }
 
-/// DNS forwarder that uses the system resolver config in `/etc/resolv.conf`.
+/// Creates a DNS forwarder that uses the nameservers and search domains of a pod, given the
+/// contents of its `/etc/resolv.conf`. The sockets used to reach the nameservers are created with
+/// `socket_factory`, so queries can be sent from the network namespace of the pod.
+pub fn forwarder_for_resolv_conf(
+    resolv_conf: &[u8],
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+) -> Result<Arc<dyn Forwarder>, Error> {
+    let (cfg, opts) = parse_resolv_conf(resolv_conf).map_err(|e| Error::Generic(Box::new(e)))?;
+    Ok(Arc::new(SystemForwarder::from_conf(
+        cfg,
+        opts,
+        Some(socket_factory),
+    )?))
+}
+
+/// DNS forwarder that uses the resolver config in `/etc/resolv.conf`.
 /// When running in dedicated (sidecar) proxy mode, this will be the same resolver configuration
 /// that would have been used by the client. For shared proxy mode, this will be the resolver
-/// configuration for the ztunnel DaemonSet (i.e. node-level resolver settings).
+/// configuration for the ztunnel DaemonSet (i.e. node-level resolver settings), unless it was
+/// created from the configuration of the pod.
 struct SystemForwarder {
     search_domains: Vec<Name>,
+    name_servers: Vec<SocketAddr>,
     resolver: Arc<dyn Resolver>,
 }
 
@@ -691,11 +1097,23 @@ impl SystemForwarder {
     fn new() -> Result<Self, Error> {
         // Get the resolver config from /etc/resolv.conf.
         let (cfg, opts) = read_system_conf().map_err(|e| Error::Generic(Box::new(e)))?;
+        Self::from_conf(cfg, opts, None)
+    }
 
+    fn from_conf(
+        cfg: ResolverConfig,
+        opts: ResolverOpts,
+        socket_factory: Option<Arc<dyn SocketFactory + Send + Sync>>,
+    ) -> Result<Self, Error> {
         // Extract the parts.
         let domain = cfg.domain().cloned();
         let search_domains = cfg.search().to_vec();
         let name_servers = cfg.name_servers().to_vec();
+        let name_server_addrs = name_servers
+            .iter()
+            .map(|ns| ns.socket_addr)
+            .unique()
+            .collect();
 
         // Remove the search list before passing to the resolver. The local resolver that
         // sends the original request will already have search domains applied. We want
@@ -703,12 +1121,17 @@ impl SystemForwarder {
         let cfg = ResolverConfig::from_parts(domain, vec![], name_servers);
 
         // Create the resolver.
-        let resolver = Arc::new(
-            dns::forwarder::Forwarder::new(cfg, opts).map_err(|e| Error::Generic(Box::new(e)))?,
-        );
+        let resolver = Arc::new(match socket_factory {
+            Some(socket_factory) => {
+                dns::forwarder::Forwarder::with_socket_factory(cfg, opts, socket_factory)
+            }
+            None => dns::forwarder::Forwarder::new(cfg, opts)
+                .map_err(|e| Error::Generic(Box::new(e)))?,
+        });
 
         Ok(Self {
             search_domains,
+            name_servers: name_server_addrs,
             resolver,
         })
     }
@@ -720,6 +1143,10 @@ impl Forwarder for SystemForwarder {
         self.search_domains.clone()
     }
 
+    fn name_servers(&self) -> Vec<SocketAddr> {
+        self.name_servers.clone()
+    }
+
     async fn forward(
         &self,
         _: Option<&Workload>,
@@ -743,8 +1170,8 @@ mod tests {
     use crate::metrics;
     use crate::strng;
     use crate::test_helpers::dns::{
-        a, aaaa, cname, ip, ipv4, ipv6, n, new_message, new_tcp_client, new_udp_client,
-        send_request, server_request,
+        a, aaaa, cname, ip, ipv4, ipv6, n, new_message, new_tcp_client, new_udp_client, ptr,
+        send_request, server_request, srv,
     };
     use crate::test_helpers::helpers::initialize_telemetry;
     use crate::test_helpers::{new_proxy_state, test_default_workload};
@@ -862,6 +1289,9 @@ mod tests {
                 network: NW1,
                 state,
                 forwarder,
+                cache: None,
+                on_demand_timeout: Duration::from_secs(1),
+                on_demand_misses: Default::default(),
                 metrics: test_metrics(),
             };
 
@@ -872,6 +1302,153 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_on_demand_keys() {
+        let mut client = test_default_workload();
+        client.namespace = "ns1".into();
+        let store = Store {
+            domain: as_name("cluster.local"),
+            svc_domain: as_name("svc.cluster.local"),
+            network: NW1,
+            state: state(),
+            forwarder: forwarder(),
+            cache: None,
+            on_demand_timeout: Duration::from_secs(1),
+            on_demand_misses: Default::default(),
+            metrics: test_metrics(),
+        };
+
+        let keys = store.on_demand_keys(&client, &n("svc1.ns2."));
+        assert_eq!(keys, vec![strng::new("ns2/svc1.ns2.svc.cluster.local")]);
+        let keys = store.on_demand_keys(&client, &n("svc1."));
+        assert_eq!(keys, vec![strng::new("ns1/svc1.ns1.svc.cluster.local")]);
+
+        // Hostnames outside the cluster domain are not fetched on-demand.
+        let keys = store.on_demand_keys(&client, &n("www.example.com."));
+        assert!(keys.is_empty(), "{keys:?}");
+    }
+
+    #[test]
+    fn test_on_demand_misses() {
+        let misses = OnDemandMisses::default();
+        let now = Instant::now();
+        let key = |i: usize| strng::format!("ns1/svc{i}.ns1.svc.cluster.local");
+        misses.insert([key(0)], now);
+        assert!(misses.contains(&key(0), now));
+        assert!(!misses.contains(&key(0), now + ON_DEMAND_MISS_TTL));
+        assert!(!misses.contains(&key(1), now));
+
+        // Once full, the oldest misses are dropped first.
+        let later = now + Duration::from_secs(1);
+        misses.insert((1..=MAX_ON_DEMAND_MISSES).map(key), later);
+        assert!(!misses.contains(&key(0), later));
+        assert!(misses.contains(&key(1), later));
+        assert!(misses.contains(&key(MAX_ON_DEMAND_MISSES), later));
+        assert_eq!(misses.misses.lock().unwrap().len(), MAX_ON_DEMAND_MISSES);
+    }
+
+    #[tokio::test]
+    async fn resolv_conf_forwarder() {
+        let conf = b"nameserver 10.96.0.10
+search ns1.svc.cluster.local svc.cluster.local cluster.local
+options ndots:5
+";
+        let forwarder =
+            forwarder_for_resolv_conf(conf, Arc::new(crate::proxy::DefaultSocketFactory)).unwrap();
+        let search_domains: Vec<String> = forwarder
+            .search_domains(&test_default_workload())
+            .iter()
+            .map(|d| d.to_string().trim_end_matches('.').to_string())
+            .collect();
+        assert_eq!(
+            search_domains,
+            vec![
+                "ns1.svc.cluster.local",
+                "svc.cluster.local",
+                "cluster.local"
+            ]
+        );
+        // The nameserver is used over UDP and TCP, but only listed once.
+        assert_eq!(
+            forwarder.name_servers(),
+            vec!["10.96.0.10:53".parse::<SocketAddr>().unwrap()]
+        );
+
+        assert!(forwarder_for_resolv_conf(
+            b"nameserver not-an-ip",
+            Arc::new(crate::proxy::DefaultSocketFactory)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_search_names() {
+        let search_domains = vec![n("ns1.svc.cluster.local"), n("example.com")];
+        let search = |host: &str| search_names(&n(host), "cluster.local", NS1, &search_domains);
+
+        assert_eq!(
+            search("name"),
+            vec![
+                n("name"),
+                n("name.ns1.svc.cluster.local"),
+                n("name.example.com"),
+            ]
+        );
+        assert_eq!(
+            search("name.ns2"),
+            vec![
+                n("name.ns2"),
+                n("name.ns2.svc.cluster.local"),
+                n("name.ns2.ns1.svc.cluster.local"),
+                n("name.ns2.example.com"),
+            ]
+        );
+        // Fully qualified names are never expanded.
+        assert_eq!(
+            search("name.ns2.svc.cluster.local."),
+            vec![n("name.ns2.svc.cluster.local.")]
+        );
+    }
+
+    #[test]
+    fn test_ptr_to_ip() {
+        assert_eq!(ptr_to_ip(&n("4.3.2.1.in-addr.arpa.")), Some(ip("1.2.3.4")));
+        assert_eq!(ptr_to_ip(&n("4.3.2.1.IN-ADDR.ARPA")), Some(ip("1.2.3.4")));
+        assert_eq!(
+            ptr_to_ip(&n(
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+            )),
+            Some(ip("2001:db8::1"))
+        );
+        // Partial or malformed names.
+        assert_eq!(ptr_to_ip(&n("3.2.1.in-addr.arpa.")), None);
+        assert_eq!(ptr_to_ip(&n("4.3.2.256.in-addr.arpa.")), None);
+        assert_eq!(ptr_to_ip(&n("1.0.0.2.ip6.arpa.")), None);
+        assert_eq!(
+            ptr_to_ip(&n(
+                "10.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+            )),
+            None
+        );
+        assert_eq!(ptr_to_ip(&n("www.google.com.")), None);
+    }
+
+    #[test]
+    fn test_parse_srv_name() {
+        assert_eq!(
+            parse_srv_name(&n("_80._tcp.svc1.ns1.svc.cluster.local.")),
+            Some((80, n("svc1.ns1.svc.cluster.local.")))
+        );
+        assert_eq!(
+            parse_srv_name(&n("_80._TCP.svc1.ns1")),
+            Some((80, n("svc1.ns1")))
+        );
+        assert_eq!(parse_srv_name(&n("_http._tcp.svc1.")), None);
+        assert_eq!(parse_srv_name(&n("_80._udp.svc1.")), None);
+        assert_eq!(parse_srv_name(&n("80._tcp.svc1.")), None);
+        assert_eq!(parse_srv_name(&n("_80._tcp.")), None);
+    }
+
     #[test]
     fn test_get_wildcards() {
         let actual = get_wildcards(&n("svc1."));
@@ -902,6 +1479,7 @@ mod tests {
             expect_code: ResponseCode,
             expect_authoritative: bool,
             expect_records: Vec<Record>,
+            expect_additionals: Vec<Record>,
         }
 
         impl Default for Case {
@@ -913,6 +1491,7 @@ mod tests {
                     expect_code: ResponseCode::NoError,
                     expect_authoritative: true,
                     expect_records: vec![],
+                    expect_additionals: vec![],
                 }
             }
         }
@@ -1128,6 +1707,114 @@ mod tests {
                     a(n("headless.pod0.ns1."), ipv4("30.30.30.30"))],
                 ..Default::default()
             },
+            Case {
+                name: "success: PTR for service VIP",
+                host: "9.9.9.9.in-addr.arpa.",
+                query_type: RecordType::PTR,
+                expect_records: vec![
+                    ptr(n("9.9.9.9.in-addr.arpa."), n("productpage.ns1.svc.cluster.local."))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: PTR for workload with hostname",
+                host: "30.30.30.30.in-addr.arpa.",
+                query_type: RecordType::PTR,
+                expect_records: vec![
+                    ptr(n("30.30.30.30.in-addr.arpa."), n("headless.pod0.ns1.svc.cluster.local."))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: PTR for workload without hostname uses pod fqdn",
+                host: "32.32.32.32.in-addr.arpa.",
+                query_type: RecordType::PTR,
+                expect_records: vec![
+                    ptr(n("32.32.32.32.in-addr.arpa."), n("32-32-32-32.ns1.pod.cluster.local."))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: SRV for service",
+                host: "_80._tcp.productpage.ns1.svc.cluster.local.",
+                query_type: RecordType::SRV,
+                expect_records: vec![
+                    srv(n("_80._tcp.productpage.ns1.svc.cluster.local."), 80,
+                        n("productpage.ns1.svc.cluster.local."))],
+                expect_additionals: vec![
+                    a(n("productpage.ns1.svc.cluster.local."), ipv4("9.9.9.9"))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: SRV for service with search domain",
+                host: "_80._tcp.productpage.",
+                query_type: RecordType::SRV,
+                expect_records: vec![
+                    srv(n("_80._tcp.productpage."), 80, n("productpage.ns1.svc.cluster.local."))],
+                expect_additionals: vec![
+                    a(n("productpage.ns1.svc.cluster.local."), ipv4("9.9.9.9"))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: SRV for dual stack service",
+                host: "_80._tcp.dual.localhost.",
+                query_type: RecordType::SRV,
+                expect_records: vec![
+                    srv(n("_80._tcp.dual.localhost."), 80, n("dual.localhost."))],
+                expect_additionals: vec![
+                    a(n("dual.localhost."), ipv4("2.2.2.2")),
+                    aaaa(n("dual.localhost."), ipv6("2001:db8:0:0:0:ff00:42:8329"))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: SRV for headless service returns endpoint targets",
+                host: "_80._tcp.headless.ns1.svc.cluster.local.",
+                query_type: RecordType::SRV,
+                expect_records: vec![
+                    srv(n("_80._tcp.headless.ns1.svc.cluster.local."), 80,
+                        n("headless.pod0.ns1.svc.cluster.local.")),
+                    srv(n("_80._tcp.headless.ns1.svc.cluster.local."), 80,
+                        n("headless.pod1.ns1.svc.cluster.local."))],
+                expect_additionals: vec![
+                    a(n("headless.pod0.ns1.svc.cluster.local."), ipv4("30.30.30.30")),
+                    a(n("headless.pod1.ns1.svc.cluster.local."), ipv4("31.31.31.31"))],
+                ..Default::default()
+            },
+            Case {
+                name: "success: SRV for unknown port returns empty records",
+                host: "_8080._tcp.productpage.ns1.svc.cluster.local.",
+                query_type: RecordType::SRV,
+                ..Default::default()
+            },
+            Case {
+                name: "failure: SRV for udp will forward",
+                host: "_80._udp.productpage.ns1.svc.cluster.local.",
+                query_type: RecordType::SRV,
+                expect_authoritative: false, // Forwarded.
+                expect_code: ResponseCode::NXDomain,
+                ..Default::default()
+            },
+            Case {
+                name: "failure: SRV for unknown host will forward",
+                host: "_80._tcp.unknown.ns1.svc.cluster.local.",
+                query_type: RecordType::SRV,
+                expect_authoritative: false, // Forwarded.
+                expect_code: ResponseCode::NXDomain,
+                ..Default::default()
+            },
+            Case {
+                name: "failure: PTR for VIP on another network will forward",
+                host: "20.20.20.20.in-addr.arpa.",
+                query_type: RecordType::PTR,
+                expect_authoritative: false, // Forwarded.
+                expect_code: ResponseCode::NXDomain,
+                ..Default::default()
+            },
+            Case {
+                name: "failure: PTR for unknown address will forward",
+                host: "8.8.8.8.in-addr.arpa.",
+                query_type: RecordType::PTR,
+                expect_authoritative: false, // Forwarded.
+                expect_code: ResponseCode::NXDomain,
+                ..Default::default()
+            },
         ];
 
         // Create and start the proxy.
@@ -1142,6 +1829,8 @@ mod tests {
             NW1,
             state,
             forwarder,
+            None,
+            Duration::from_secs(1),
             test_metrics(),
             drain,
             &factory,
@@ -1173,6 +1862,10 @@ mod tests {
                         sort_records(&mut actual);
                     }
                     assert_eq!(c.expect_records, actual, "{}", name);
+
+                    let mut additionals = resp.additionals().to_vec();
+                    sort_records(&mut additionals);
+                    assert_eq!(c.expect_additionals, additionals, "{}", name);
                 }
             }
         }
@@ -1191,6 +1884,9 @@ mod tests {
             network: NW1,
             state,
             forwarder,
+            cache: None,
+            on_demand_timeout: Duration::from_secs(1),
+            on_demand_misses: Default::default(),
             metrics: test_metrics(),
         };
 
@@ -1243,6 +1939,8 @@ mod tests {
             NW1,
             state,
             forwarder,
+            None,
+            Duration::from_secs(1),
             test_metrics(),
             drain,
             &factory,
@@ -1285,8 +1983,11 @@ mod tests {
             network: NW1,
             state,
             forwarder,
+            cache: None,
             domain: n("cluster.local"),
             svc_domain: n("svc.cluster.local"),
+            on_demand_timeout: Duration::from_secs(1),
+            on_demand_misses: Default::default(),
             metrics: test_metrics(),
         };
 
@@ -1436,6 +2137,8 @@ mod tests {
                 &[format!("{}/{}", NS1, kube_fqdn("headless", NS1)).as_str()],
                 &[ip("31.31.31.31")],
             ),
+            // Workload without a hostname.
+            xds_workload("pod", NS1, "", &NW1, &[], &[ip("32.32.32.32")]),
         ];
 
         new_proxy_state(&workloads, &services, &[])
@@ -1561,6 +2264,10 @@ mod tests {
             self.search_domains.clone()
         }
 
+        fn name_servers(&self) -> Vec<SocketAddr> {
+            Vec::new()
+        }
+
         async fn forward(
             &self,
             _: Option<&Workload>,
@@ -24,6 +24,7 @@ use self::config::InPodConfig;
 
 pub mod admin;
 mod config;
+mod dns_forwarder;
 pub mod metrics;
 pub mod netns;
 pub mod packet;
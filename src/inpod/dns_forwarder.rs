@@ -0,0 +1,190 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hickory_proto::rr::Name;
+use hickory_server::authority::LookupError;
+use hickory_server::server::Request;
+use tracing::{debug, warn};
+
+use crate::dns::resolver::Answer;
+use crate::dns::{forwarder_for_resolv_conf, Forwarder};
+use crate::proxy::SocketFactory;
+use crate::state::workload::Workload;
+
+use super::netns::InpodNetns;
+
+/// How long to wait before trying to read the resolv.conf of a workload again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// WorkloadForwarder forwards DNS requests with the nameservers and search domains in the
+/// `/etc/resolv.conf` of a workload. The file is read when the first request is forwarded rather
+/// than when the workload is added, since at that point typically only the pod sandbox is
+/// running. Until the file can be read, the node settings are used.
+pub struct WorkloadForwarder {
+    netns: InpodNetns,
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+    node: Arc<dyn Forwarder>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    workload: Option<Arc<dyn Forwarder>>,
+    last_attempt: Option<Instant>,
+}
+
+impl WorkloadForwarder {
+    pub fn new(
+        netns: InpodNetns,
+        socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+        node: Arc<dyn Forwarder>,
+    ) -> Self {
+        WorkloadForwarder {
+            netns,
+            socket_factory,
+            node,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the forwarder with the workload settings if already read, or else the node one.
+    fn current(&self) -> Arc<dyn Forwarder> {
+        let state = self.state.lock().unwrap();
+        state.workload.clone().unwrap_or_else(|| self.node.clone())
+    }
+
+    /// Like [WorkloadForwarder::current], but reads the workload settings first if they have not
+    /// been read yet and were not tried recently.
+    async fn load(&self) -> Arc<dyn Forwarder> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(workload) = &state.workload {
+                return workload.clone();
+            }
+            if state
+                .last_attempt
+                .is_some_and(|t| t.elapsed() < RETRY_INTERVAL)
+            {
+                return self.node.clone();
+            }
+            // Concurrent requests use the node settings while we read.
+            state.last_attempt = Some(Instant::now());
+        }
+
+        let netns = self.netns.clone();
+        // Reading the file means walking /proc, which blocks.
+        let read = tokio::task::spawn_blocking(move || netns.resolv_conf())
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        let conf = match read {
+            Ok(conf) => conf,
+            Err(e) => {
+                debug!(inode=?self.netns.workload_inode(), "failed to read resolv.conf, using the node DNS config: {e}");
+                return self.node.clone();
+            }
+        };
+        match forwarder_for_resolv_conf(&conf, self.socket_factory.clone()) {
+            Ok(workload) => {
+                self.state.lock().unwrap().workload = Some(workload.clone());
+                workload
+            }
+            Err(e) => {
+                warn!(inode=?self.netns.workload_inode(), "invalid resolv.conf, using the node DNS config: {e}");
+                self.node.clone()
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Forwarder for WorkloadForwarder {
+    fn search_domains(&self, client: &Workload) -> Vec<Name> {
+        self.current().search_domains(client)
+    }
+
+    fn name_servers(&self) -> Vec<SocketAddr> {
+        self.current().name_servers()
+    }
+
+    async fn forward(
+        &self,
+        client: Option<&Workload>,
+        request: &Request,
+    ) -> Result<Answer, LookupError> {
+        self.load().await.forward(client, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inpod::test_helpers::new_netns;
+    use crate::test_helpers::dns::{a_request, n, socket_addr};
+    use crate::test_helpers::test_default_workload;
+    use hickory_proto::op::ResponseCode;
+    use hickory_server::server::Protocol;
+
+    struct NodeForwarder;
+
+    #[async_trait::async_trait]
+    impl Forwarder for NodeForwarder {
+        fn search_domains(&self, _: &Workload) -> Vec<Name> {
+            vec![n("node.local")]
+        }
+
+        fn name_servers(&self) -> Vec<SocketAddr> {
+            vec![socket_addr("10.0.0.1:53")]
+        }
+
+        async fn forward(&self, _: Option<&Workload>, _: &Request) -> Result<Answer, LookupError> {
+            Err(LookupError::ResponseCode(ResponseCode::Refused))
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_node_settings() {
+        if !crate::test_helpers::can_run_privilged_test() {
+            eprintln!("This test requires root; skipping");
+            return;
+        }
+        // No process runs in a new network namespace, so its resolv.conf cannot be read.
+        let netns = InpodNetns::new(Arc::new(InpodNetns::current().unwrap()), new_netns()).unwrap();
+        let forwarder = WorkloadForwarder::new(
+            netns,
+            Arc::new(crate::proxy::DefaultSocketFactory),
+            Arc::new(NodeForwarder),
+        );
+        let req = a_request(
+            n("www.example.com."),
+            socket_addr("1.1.1.1:80"),
+            Protocol::Udp,
+        );
+        assert!(matches!(
+            forwarder.forward(None, &req).await,
+            Err(LookupError::ResponseCode(ResponseCode::Refused))
+        ));
+        assert_eq!(
+            forwarder.search_domains(&test_default_workload()),
+            vec![n("node.local")]
+        );
+        assert_eq!(forwarder.name_servers(), vec![socket_addr("10.0.0.1:53")]);
+        // The read was attempted, and is not tried again right away.
+        assert!(forwarder.state.lock().unwrap().last_attempt.is_some());
+        assert!(forwarder.state.lock().unwrap().workload.is_none());
+    }
+}
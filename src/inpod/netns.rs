@@ -67,6 +67,44 @@ impl InpodNetns {
         self.inner.netns_inode
     }
 
+    /// Reads `/etc/resolv.conf` of the workload. The network namespace does not give access to the
+    /// filesystem of the workload, so this finds a process running in the network namespace and
+    /// reads the file from its root. This requires access to the processes of the host, and
+    /// blocks, so it should not be called from the async runtime.
+    pub fn resolv_conf(&self) -> std::io::Result<Vec<u8>> {
+        use std::os::unix::fs::MetadataExt;
+        let mut last_err = None;
+        for entry in std::fs::read_dir("/proc")? {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            // Only process directories are numeric.
+            let is_pid = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+            if !is_pid {
+                continue;
+            }
+            let proc = entry.path();
+            match std::fs::metadata(proc.join("ns/net")) {
+                Ok(ns) if ns.ino() == self.inner.netns_inode => {}
+                _ => continue,
+            }
+            // The process may exit while we read; try the next one if so.
+            match std::fs::read(proc.join("root/etc/resolv.conf")) {
+                Ok(conf) => return Ok(conf),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no process found in the workload network namespace",
+            )
+        }))
+    }
+
     pub fn run<F, T>(&self, f: F) -> std::io::Result<T>
     where
         F: FnOnce() -> T,
@@ -22,6 +22,7 @@ use crate::proxyfactory::ProxyFactory;
 use crate::state::WorkloadInfo;
 
 use super::config::InPodConfig;
+use super::dns_forwarder::WorkloadForwarder;
 
 use super::netns::InpodNetns;
 use super::WorkloadUid;
@@ -244,12 +245,29 @@ impl WorkloadProxyManagerState {
         let workload_netns_inode = netns.workload_inode();
         let (drain_tx, drain_rx) = drain::channel();
 
+        let dns_netns = netns.clone();
+        let socket_factory: Arc<dyn crate::proxy::SocketFactory + Send + Sync> =
+            Arc::from(self.inpod_config.socket_factory(netns));
+        // DNS requests of the workload are forwarded with its own resolver settings, once they
+        // can be read.
+        let dns_forwarder: Option<Arc<dyn crate::dns::Forwarder>> =
+            if self.proxy_gen.dns_proxy_enabled() {
+                Some(Arc::new(WorkloadForwarder::new(
+                    dns_netns,
+                    socket_factory.clone(),
+                    self.proxy_gen.dns_forwarder()?,
+                )))
+            } else {
+                None
+            };
+
         let proxies = self
             .proxy_gen
             .new_proxies_from_factory(
                 Some(drain_rx),
                 workload_info.clone(),
-                Arc::from(self.inpod_config.socket_factory(netns)),
+                socket_factory,
+                dns_forwarder,
             )
             .await?;
 
@@ -20,6 +20,7 @@ use std::time::Duration;
 use std::{fmt, io};
 
 use drain::Watch;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 
 use rand::Rng;
 
@@ -35,12 +36,14 @@ use crate::identity::{Identity, SecretManager};
 use crate::proxy::connection_manager::{ConnectionManager, PolicyWatcher};
 use crate::proxy::inbound_passthrough::InboundPassthrough;
 use crate::proxy::outbound::Outbound;
+use crate::proxy::rate_limit::RateLimiter;
 use crate::proxy::socks5::Socks5;
 use crate::rbac::Connection;
 use crate::state::service::{endpoint_uid, Service, ServiceDescription};
 use crate::state::workload::address::Address;
 use crate::state::workload::{network_addr, GatewayAddress, Workload};
 use crate::state::{DemandProxyState, WorkloadInfo};
+use crate::strng::{self, Strng};
 use crate::{config, identity, socket, tls};
 
 pub mod connection_manager;
@@ -51,6 +54,7 @@ mod inbound_passthrough;
 pub mod metrics;
 mod outbound;
 pub mod pool;
+pub mod rate_limit;
 mod socks5;
 mod util;
 
@@ -108,6 +112,7 @@ pub(super) struct ProxyInputs {
     metrics: Arc<Metrics>,
     socket_factory: Arc<dyn SocketFactory + Send + Sync>,
     proxy_workload_info: Option<Arc<WorkloadInfo>>,
+    rate_limiter: RateLimiter,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -122,8 +127,9 @@ impl ProxyInputs {
         proxy_workload_info: Option<WorkloadInfo>,
     ) -> Self {
         Self {
+            rate_limiter: RateLimiter::new(cfg.inbound_connection_rate_limit),
             cfg,
-            state,
+            state: state.with_connection_manager(connection_manager.clone()),
             cert_manager,
             metrics,
             connection_manager,
@@ -145,11 +151,13 @@ impl Proxy {
         let metrics = Arc::new(metrics);
         let socket_factory = Arc::new(DefaultSocketFactory);
 
+        let connection_manager = ConnectionManager::new(cfg.connection_limits);
         let pi = ProxyInputs {
+            rate_limiter: RateLimiter::new(cfg.inbound_connection_rate_limit),
             cfg,
-            state,
+            state: state.with_connection_manager(connection_manager.clone()),
             cert_manager,
-            connection_manager: ConnectionManager::default(),
+            connection_manager,
             metrics,
             hbone_port: 0,
             socket_factory,
@@ -239,12 +247,27 @@ pub enum Error {
     #[error("connection failed: {0}")]
     ConnectionFailed(io::Error),
 
+    #[error("connection to {0} timed out after {1:?}")]
+    ConnectTimeout(SocketAddr, Duration),
+
     #[error("connection closed due to policy change")]
     AuthorizationPolicyLateRejection,
 
     #[error("connection closed due to policy rejection")]
     AuthorizationPolicyRejection,
 
+    #[error("connection rate limit exceeded")]
+    RateLimited,
+
+    #[error("maximum concurrent connections to {0} exceeded")]
+    UpstreamOverflow(Strng),
+
+    #[error("connection idle for {0:?}")]
+    IdleTimeout(Duration),
+
+    #[error("connection exceeded max duration of {0:?}")]
+    MaxConnectionDuration(Duration),
+
     #[error("pool is already connecting")]
     WorkloadHBONEPoolAlreadyConnecting,
 
@@ -293,12 +316,21 @@ pub enum Error {
     #[error("invalid source: {0}, should match {1:?}")]
     MismatchedSource(IpAddr, Arc<WorkloadInfo>),
 
+    #[error("socks5 user {0} does not match source identity {1}")]
+    MismatchedSocks5User(Strng, Identity),
+
+    #[error("invalid socks5 credentials: {0}")]
+    InvalidSocks5Credentials(String),
+
     #[error("unknown waypoint: {0}")]
     UnknownWaypoint(String),
 
     #[error("unknown destination: {0}")]
     UnknownDestination(IpAddr),
 
+    #[error("unknown destination hostname: {0}")]
+    UnknownHostname(String),
+
     #[error("no valid routing destination for workload: {0}")]
     NoValidDestination(Box<Workload>),
 
@@ -441,11 +473,27 @@ pub fn get_original_src_from_stream(stream: &TcpStream) -> Option<IpAddr> {
         .map_or(None, |sa| Some(socket::to_canonical(sa).ip()))
 }
 
-const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+// How long to wait for a connection attempt before also trying the next address, per RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
 
 pub async fn freebind_connect(
     local: Option<IpAddr>,
     addr: SocketAddr,
+    connect_timeout: Duration,
+    socket_factory: &(dyn SocketFactory + Send + Sync),
+) -> io::Result<TcpStream> {
+    freebind_connect_racing(local, &[addr], connect_timeout, socket_factory).await
+}
+
+/// Connects to the first address that accepts a connection, "Happy Eyeballs" style (RFC 8305).
+/// Addresses are tried in order, alternating between address families starting with the family
+/// of the first address. A new attempt starts whenever one fails or CONNECTION_ATTEMPT_DELAY
+/// passes without a connection, and the first attempt to succeed wins. The whole race is bounded
+/// by `connect_timeout`.
+pub async fn freebind_connect_racing(
+    local: Option<IpAddr>,
+    addrs: &[SocketAddr],
+    connect_timeout: Duration,
     socket_factory: &(dyn SocketFactory + Send + Sync),
 ) -> io::Result<TcpStream> {
     async fn connect(
@@ -494,12 +542,49 @@ pub async fn freebind_connect(
             }
         }
     }
+    let race = async {
+        let mut addrs = interleave_families(addrs).into_iter().peekable();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_err = None;
+        loop {
+            if let Some(addr) = addrs.next() {
+                attempts.push(connect(local, addr, socket_factory));
+            }
+            if attempts.is_empty() {
+                return Err(last_err.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+                }));
+            }
+            tokio::select! {
+                Some(res) = attempts.next() => match res {
+                    Ok(stream) => return Ok(stream),
+                    Err(err) => {
+                        trace!("connection attempt failed: {err}");
+                        last_err = Some(err);
+                    }
+                },
+                _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if addrs.peek().is_some() => {}
+            }
+        }
+    };
     // Wrap the entire connect function in a timeout
-    timeout(CONNECTION_TIMEOUT, connect(local, addr, socket_factory))
+    timeout(connect_timeout, race)
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
 }
 
+// interleave_families orders addresses alternating between IPv4 and IPv6, starting with the
+// family of the first address and otherwise keeping their order.
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return Vec::new();
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv4() == first.is_ipv4());
+    itertools::interleave(preferred, other).collect()
+}
+
 // guess_inbound_service selects an upstream service for inbound metrics.
 // There may be many services for a single workload. We find the the first one with an applicable port
 // as a best guess.
@@ -542,6 +627,14 @@ pub fn guess_inbound_service(
         .map(|s| ServiceDescription::from(s.as_ref()))
 }
 
+/// kube_namespace extracts the namespace from a Kubernetes service FQDN, such as
+/// `name.namespace.svc.cluster.local`.
+fn kube_namespace(hostname: &str) -> Option<Strng> {
+    let mut labels = hostname.split('.');
+    let (_name, namespace, svc) = (labels.next()?, labels.next()?, labels.next()?);
+    (svc == "svc" && !namespace.is_empty()).then(|| strng::new(namespace))
+}
+
 // Checks that the source identiy and address match the upstream's waypoint
 async fn check_from_waypoint(
     state: &DemandProxyState,
@@ -660,6 +753,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn interleave_address_families() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:80",
+            "1.1.1.1:80",
+            "1.1.1.2:80",
+            "[::2]:80",
+            "[::3]:80",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let got: Vec<String> = interleave_families(&addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                "[::1]:80",
+                "1.1.1.1:80",
+                "[::2]:80",
+                "1.1.1.2:80",
+                "[::3]:80"
+            ]
+        );
+        assert!(interleave_families(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn connect_racing_falls_back() {
+        // Grab a free port and close it, so connecting to it is refused.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+        let connect_timeout = Duration::from_secs(10);
+
+        let stream = freebind_connect_racing(
+            None,
+            &[closed, open],
+            connect_timeout,
+            &DefaultSocketFactory,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+
+        let err = freebind_connect_racing(None, &[closed], connect_timeout, &DefaultSocketFactory)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let err = freebind_connect_racing(None, &[], connect_timeout, &DefaultSocketFactory)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
     // private helpers
     fn mock_wokload_with_gateway(gw: Option<GatewayAddress>) -> Workload {
         Workload {
@@ -687,6 +841,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            connection_rate_limit: None,
         }
     }
 
@@ -716,6 +871,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            connection_rate_limit: None,
         }
     }
 
@@ -12,17 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::proxy::metrics::ConnectionResult;
 use crate::proxy::{error, Error};
 
+use crate::rbac::Connection;
+use crate::state::workload::Workload;
 use crate::state::DemandProxyState;
 use crate::state::ProxyRbacContext;
+use crate::strng::{self, Strng};
 use drain;
 use serde::{Serialize, Serializer};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -52,10 +56,37 @@ impl ConnectionDrain {
     }
 }
 
+/// Caps on concurrent connections, applied separately to inbound and outbound connections.
+/// Unset caps are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionLimits {
+    /// The maximum number of concurrent connections to each destination workload.
+    pub max_connections_per_destination: Option<usize>,
+    /// The maximum number of concurrent connections from each source to each destination workload.
+    pub max_connections_per_source: Option<usize>,
+}
+
+/// The number of open connections to a destination workload.
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DestinationLoad {
+    total: usize,
+    by_source: HashMap<Strng, usize>,
+}
+
+type ConnectionLoad = Arc<RwLock<HashMap<Strng, DestinationLoad>>>;
+
 #[derive(Clone)]
 pub struct ConnectionManager {
     drains: Arc<RwLock<HashMap<InboundConnection, ConnectionDrain>>>,
     outbound_connections: Arc<RwLock<HashSet<OutboundConnection>>>,
+    // number of outbound connections per actual destination IP, used for load balancing
+    outbound_counts: Arc<RwLock<HashMap<IpAddr, usize>>>,
+    limits: ConnectionLimits,
+    // number of connections per destination workload and source, used to enforce `limits`
+    inbound_load: ConnectionLoad,
+    outbound_load: ConnectionLoad,
 }
 
 impl std::fmt::Debug for ConnectionManager {
@@ -66,10 +97,7 @@ impl std::fmt::Debug for ConnectionManager {
 
 impl Default for ConnectionManager {
     fn default() -> Self {
-        ConnectionManager {
-            drains: Arc::new(RwLock::new(HashMap::new())),
-            outbound_connections: Arc::new(RwLock::new(HashSet::new())),
-        }
+        ConnectionManager::new(ConnectionLimits::default())
     }
 }
 
@@ -104,6 +132,32 @@ impl Drop for ConnectionGuard {
     }
 }
 
+/// ConnectionSlot counts a connection against the connection limits until it is dropped.
+pub struct ConnectionSlot {
+    load: ConnectionLoad,
+    src: Strng,
+    dst: Strng,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut load = self.load.write().expect("mutex");
+        let Entry::Occupied(mut dst) = load.entry(self.dst.clone()) else {
+            return;
+        };
+        dst.get_mut().total -= 1;
+        if let Entry::Occupied(mut src) = dst.get_mut().by_source.entry(self.src.clone()) {
+            *src.get_mut() -= 1;
+            if *src.get() == 0 {
+                src.remove();
+            }
+        }
+        if dst.get().total == 0 {
+            dst.remove();
+        }
+    }
+}
+
 pub struct OutboundConnectionGuard {
     cm: ConnectionManager,
     conn: OutboundConnection,
@@ -140,6 +194,82 @@ pub struct InboundConnection {
 }
 
 impl ConnectionManager {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        ConnectionManager {
+            drains: Arc::new(RwLock::new(HashMap::new())),
+            outbound_connections: Arc::new(RwLock::new(HashSet::new())),
+            outbound_counts: Arc::new(RwLock::new(HashMap::new())),
+            limits,
+            inbound_load: Default::default(),
+            outbound_load: Default::default(),
+        }
+    }
+
+    /// Returns a new manager that tracks its own connections, but counts them against the same
+    /// connection limits as this one. This lets the limits apply across several proxies, such as
+    /// the per-pod proxies in in-pod mode.
+    pub fn with_shared_limits(&self) -> Self {
+        ConnectionManager {
+            inbound_load: self.inbound_load.clone(),
+            outbound_load: self.outbound_load.clone(),
+            ..ConnectionManager::new(self.limits)
+        }
+    }
+
+    /// Counts a new inbound connection to the destination workload `dst`, or fails if that would
+    /// exceed the connection limits. Sources are told apart by identity, or by address if they
+    /// have none.
+    pub fn reserve_inbound(
+        &self,
+        conn: &Connection,
+        dst: &Workload,
+    ) -> Result<ConnectionSlot, Error> {
+        let src = match &conn.src_identity {
+            Some(id) => strng::new(id.to_string()),
+            None => strng::new(conn.src.ip().to_string()),
+        };
+        self.reserve(&self.inbound_load, src, dst.uid.clone())
+    }
+
+    /// Counts a new outbound connection from the source workload `src` to the destination workload
+    /// `dst`, or fails if that would exceed the connection limits.
+    pub fn reserve_outbound(
+        &self,
+        src: &Workload,
+        dst: &Workload,
+    ) -> Result<ConnectionSlot, Error> {
+        self.reserve(&self.outbound_load, src.uid.clone(), dst.uid.clone())
+    }
+
+    fn reserve(
+        &self,
+        load: &ConnectionLoad,
+        src: Strng,
+        dst: Strng,
+    ) -> Result<ConnectionSlot, Error> {
+        let mut guard = load.write().expect("mutex");
+        let dst_load = guard.entry(dst.clone()).or_default();
+        let src_count = dst_load.by_source.get(&src).copied().unwrap_or_default();
+        let over = |count: usize, max: Option<usize>| max.is_some_and(|max| count >= max);
+        if over(dst_load.total, self.limits.max_connections_per_destination)
+            || over(src_count, self.limits.max_connections_per_source)
+        {
+            if dst_load.total == 0 {
+                // Only possible with a limit of 0; do not keep the empty entry.
+                guard.remove(&dst);
+            }
+            debug!(%src, %dst, "connection limit exceeded");
+            return Err(Error::UpstreamOverflow(dst));
+        }
+        dst_load.total += 1;
+        *dst_load.by_source.entry(src.clone()).or_default() += 1;
+        Ok(ConnectionSlot {
+            load: load.clone(),
+            src,
+            dst,
+        })
+    }
+
     pub fn track_outbound(
         &self,
         src: SocketAddr,
@@ -152,10 +282,19 @@ impl ConnectionManager {
             actual_dst,
         };
 
-        self.outbound_connections
+        if self
+            .outbound_connections
             .write()
             .expect("mutex")
-            .insert(c.clone());
+            .insert(c.clone())
+        {
+            *self
+                .outbound_counts
+                .write()
+                .expect("mutex")
+                .entry(actual_dst.ip())
+                .or_default() += 1;
+        }
 
         OutboundConnectionGuard {
             cm: self.clone(),
@@ -163,11 +302,15 @@ impl ConnectionManager {
         }
     }
 
+    /// Checks the connection is allowed by authorization policies, and starts tracking it so it can
+    /// be closed if policies change. The policies that matched are recorded in `state` and `result`,
+    /// along with AUDIT and dry-run policy results.
     pub async fn assert_rbac(
         &self,
         state: &DemandProxyState,
         ctx: &ProxyRbacContext,
         dest_service: Option<String>,
+        result: &ConnectionResult,
     ) -> Result<ConnectionGuard, Error> {
         // Register before our initial assert. This prevents a race if policy changes between assert() and
         // track()
@@ -180,7 +323,10 @@ impl ConnectionManager {
             debug_assert!(false, "failed to track {conn:?}");
             return Err(Error::AuthorizationPolicyRejection);
         };
-        if !state.assert_rbac(ctx).await {
+        let decision = state.evaluate_rbac(ctx, conn.dest_service.as_deref()).await;
+        state.record_policy_matches(&decision);
+        result.record_rbac(&decision);
+        if !decision.allowed {
             self.release(&conn);
             return Err(Error::AuthorizationPolicyRejection);
         }
@@ -224,7 +370,26 @@ impl ConnectionManager {
     }
 
     fn release_outbound(&self, c: &OutboundConnection) {
-        self.outbound_connections.write().expect("mutex").remove(c);
+        if !self.outbound_connections.write().expect("mutex").remove(c) {
+            return;
+        }
+        let mut counts = self.outbound_counts.write().expect("mutex");
+        if let Entry::Occupied(mut count) = counts.entry(c.actual_dst.ip()) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    // get the number of outbound connections currently open to the given destination IP
+    pub fn outbound_count(&self, ip: IpAddr) -> usize {
+        self.outbound_counts
+            .read()
+            .expect("mutex")
+            .get(&ip)
+            .copied()
+            .unwrap_or_default()
     }
 
     // signal all connections listening to this channel to take action (typically terminate traffic)
@@ -246,9 +411,13 @@ impl ConnectionManager {
 }
 
 #[derive(serde::Serialize)]
-struct ConnectionManagerDump {
+#[serde(rename_all = "camelCase")]
+struct ConnectionManagerDump<'a> {
     inbound: Vec<InboundConnectionDump>,
     outbound: Vec<OutboundConnection>,
+    // Counts per destination workload, and source, that connection limits apply to
+    inbound_counts: &'a HashMap<Strng, DestinationLoad>,
+    outbound_counts: &'a HashMap<Strng, DestinationLoad>,
 }
 
 impl Serialize for ConnectionManager {
@@ -275,7 +444,14 @@ impl Serialize for ConnectionManager {
             .iter()
             .cloned()
             .collect();
-        let dump = ConnectionManagerDump { inbound, outbound };
+        let inbound_load = self.inbound_load.read().expect("mutex");
+        let outbound_load = self.outbound_load.read().expect("mutex");
+        let dump = ConnectionManagerDump {
+            inbound,
+            outbound,
+            inbound_counts: &inbound_load,
+            outbound_counts: &outbound_load,
+        };
         dump.serialize(serializer)
     }
 }
@@ -309,7 +485,7 @@ impl PolicyWatcher {
                 _ = policies_changed.changed() => {
                     let connections = self.connection_manager.connections();
                     for conn in connections {
-                        if !self.state.assert_rbac(&conn.ctx).await {
+                        if !self.state.evaluate_rbac(&conn.ctx, conn.dest_service.as_deref()).await.allowed {
                             self.connection_manager.close(&conn).await;
                             info!("connection {} closed because it's no longer allowed after a policy update", conn.ctx);
                         }
@@ -328,12 +504,17 @@ mod tests {
     use std::sync::{Arc, RwLock};
     use std::time::Duration;
 
+    use crate::proxy::Error;
     use crate::rbac::Connection;
+    use crate::state::workload::Workload;
     use crate::state::{DemandProxyState, ProxyState};
+    use crate::test_helpers;
     use crate::xds::istio::security::{Action, Authorization, Scope};
     use crate::xds::ProxyStateUpdateMutator;
 
-    use super::{ConnectionGuard, ConnectionManager, InboundConnection, PolicyWatcher};
+    use super::{
+        ConnectionGuard, ConnectionLimits, ConnectionManager, InboundConnection, PolicyWatcher,
+    };
 
     #[tokio::test]
     async fn test_connection_manager_close() {
@@ -599,6 +780,7 @@ mod tests {
             scope: Scope::Global as i32,
             namespace: "default".to_string(),
             rules: vec![],
+            dry_run: false,
         };
 
         // spawn an assertion that our connection close is received
@@ -619,6 +801,67 @@ mod tests {
         tx.drain().await;
     }
 
+    #[test]
+    fn test_connection_limits() {
+        let cm = ConnectionManager::new(ConnectionLimits {
+            max_connections_per_destination: Some(3),
+            max_connections_per_source: Some(2),
+        });
+        let dst = Workload {
+            uid: "dst".into(),
+            ..test_helpers::test_default_workload()
+        };
+        let conn = |src: &str| Connection {
+            src_identity: None,
+            src: src.parse().unwrap(),
+            dst_network: "".into(),
+            dst: "127.0.0.2:8080".parse().unwrap(),
+        };
+        let a = conn("127.0.0.1:1234");
+        // The source port does not matter.
+        let a2 = conn("127.0.0.1:5678");
+        let b = conn("127.0.0.3:1234");
+
+        let s1 = cm.reserve_inbound(&a, &dst).unwrap();
+        let s2 = cm.reserve_inbound(&a2, &dst).unwrap();
+        assert!(matches!(
+            cm.reserve_inbound(&a, &dst),
+            Err(Error::UpstreamOverflow(_))
+        ));
+        let s3 = cm.reserve_inbound(&b, &dst).unwrap();
+        // The destination is full, even for sources under their own limit.
+        assert!(cm.reserve_inbound(&b, &dst).is_err());
+        // Outbound connections are counted separately.
+        let src = Workload {
+            uid: "src".into(),
+            ..test_helpers::test_default_workload()
+        };
+        let _out = cm.reserve_outbound(&src, &dst).unwrap();
+
+        let dump = serde_json::to_value(&cm).unwrap();
+        assert_eq!(
+            dump["inboundCounts"],
+            serde_json::json!({"dst": {"total": 3, "bySource": {"127.0.0.1": 2, "127.0.0.3": 1}}})
+        );
+        assert_eq!(
+            dump["outboundCounts"],
+            serde_json::json!({"dst": {"total": 1, "bySource": {"src": 1}}})
+        );
+
+        // Managers with shared limits count against the same destination.
+        let shared = cm.with_shared_limits();
+        assert!(shared.reserve_inbound(&b, &dst).is_err());
+
+        // Closing a connection frees its slot.
+        drop(s1);
+        let s1 = cm.reserve_inbound(&a, &dst).unwrap();
+        drop((s1, s2, s3));
+        assert_eq!(
+            serde_json::to_value(&cm).unwrap()["inboundCounts"],
+            serde_json::json!({})
+        );
+    }
+
     // small helper to assert that the Watches are working in a timely manner
     async fn assert_close(c: Watch) {
         let result = tokio::time::timeout(Duration::from_secs(1), c.signaled()).await;
@@ -26,6 +26,7 @@ use h2::client::{Connection, SendRequest};
 use h2::{Reason, SendStream};
 use http::Request;
 use std::io::Cursor;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
@@ -44,6 +45,14 @@ use tracing::{debug, error, trace, warn};
 pub struct H2Stream {
     read: H2StreamReadHalf,
     write: H2StreamWriteHalf,
+    local_addr: SocketAddr,
+}
+
+impl H2Stream {
+    // local_addr returns the local address of the connection carrying the stream.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
 }
 
 pub struct H2StreamReadHalf {
@@ -63,7 +72,7 @@ impl crate::copy::BufferedSplitter for H2Stream {
     type R = H2StreamReadHalf;
     type W = H2StreamWriteHalf;
     fn split_into_buffered_reader(self) -> (H2StreamReadHalf, H2StreamWriteHalf) {
-        let H2Stream { read, write } = self;
+        let H2Stream { read, write, .. } = self;
         (read, write)
     }
 }
@@ -230,6 +239,7 @@ pub async fn spawn_connection(
     s: TlsStream<TcpStream>,
     driver_drain: Receiver<bool>,
 ) -> Result<H2ConnectClient, Error> {
+    let local_addr = s.get_ref().0.local_addr()?;
     let mut builder = h2::client::Builder::new();
     builder
         .initial_window_size(cfg.window_size)
@@ -264,6 +274,7 @@ pub async fn spawn_connection(
         sender: send_req,
         stream_count: Arc::new(AtomicU16::new(0)),
         max_allowed_streams,
+        local_addr,
     };
     Ok(c)
 }
@@ -274,6 +285,7 @@ pub struct H2ConnectClient {
     sender: SendRequest<SendBuf>,
     pub max_allowed_streams: u16,
     stream_count: Arc<AtomicU16>,
+    local_addr: SocketAddr,
 }
 
 impl H2ConnectClient {
@@ -326,7 +338,11 @@ impl H2ConnectClient {
             half_dropped: dropped,
             active_count: self.stream_count.clone(),
         };
-        let h2 = H2Stream { read, write };
+        let h2 = H2Stream {
+            read,
+            write,
+            local_addr: self.local_addr,
+        };
         Ok(h2)
     }
 
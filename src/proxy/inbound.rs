@@ -49,7 +49,7 @@ use crate::state::workload::address::Address;
 use crate::state::workload::application_tunnel::Protocol as AppProtocol;
 use crate::{assertions, copy, proxy, strng, tls};
 
-use crate::state::workload::{self, NetworkAddress, Workload};
+use crate::state::workload::{self, network_addr, NamespacedHostname, NetworkAddress, Workload};
 use crate::state::DemandProxyState;
 use crate::strng::Strng;
 use crate::tls::TlsError;
@@ -226,7 +226,7 @@ impl Inbound {
             return StatusCode::NOT_FOUND;
         }
         let start = Instant::now();
-        let Ok(hbone_addr) = req.uri().to_string().as_str().parse::<SocketAddr>() else {
+        let Ok(hbone_addr) = HboneAddress::try_from(req.uri()) else {
             metrics::log_early_deny(
                 conn.src,
                 conn.dst,
@@ -235,6 +235,14 @@ impl Inbound {
             );
             return StatusCode::BAD_REQUEST;
         };
+        // Resolve hostname targets to a concrete address, so policy is applied to the real target.
+        let hbone_addr = match Self::resolve_hbone_addr(&pi.state, &conn, hbone_addr).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                metrics::log_early_deny(conn.src, conn.dst, Reporter::destination, e);
+                return StatusCode::BAD_REQUEST;
+            }
+        };
 
         // Determine the next hop.
         let (upstream_addr, inbound_protocol, upstream, upstream_service) =
@@ -309,6 +317,10 @@ impl Inbound {
         };
         let ds =
             proxy::guess_inbound_service(&rbac_ctx.conn, &for_host, upstream_service, &upstream);
+        let rate_limited =
+            !pi.rate_limiter
+                .allow(rbac_ctx.conn.src_identity.as_ref(), source_ip, &upstream);
+        let connection_slot = connection_manager.reserve_inbound(&rbac_ctx.conn, &upstream);
         let result_tracker = Arc::new(metrics::ConnectionResult::new(
             rbac_ctx.conn.src,
             rbac_ctx.conn.dst,
@@ -321,12 +333,31 @@ impl Inbound {
                 destination: Some(upstream),
                 connection_security_policy: metrics::SecurityPolicy::mutual_tls,
                 destination_service: ds,
+                source_user: None,
+                connect_retries: 0,
             },
             pi.metrics.clone(),
         ));
 
+        if rate_limited {
+            Arc::into_inner(result_tracker)
+                .expect("arc is not shared yet")
+                .record_with_flag(Err(Error::RateLimited), metrics::ResponseFlags::RateLimited);
+            return StatusCode::TOO_MANY_REQUESTS;
+        }
+        // Counts the connection against the connection limits until it is done.
+        let connection_slot = match connection_slot {
+            Ok(slot) => slot,
+            Err(e) => {
+                Arc::into_inner(result_tracker)
+                    .expect("arc is not shared yet")
+                    .record_with_flag(Err(e), metrics::ResponseFlags::UpstreamOverflow);
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+        };
+
         let conn_guard = match connection_manager
-            .assert_rbac(&pi.state, &rbac_ctx, for_host)
+            .assert_rbac(&pi.state, &rbac_ctx, for_host, &result_tracker)
             .await
         {
             Ok(cg) => cg,
@@ -348,12 +379,17 @@ impl Inbound {
         };
 
         let orig_src = enable_original_source.then_some(source_ip);
-        let stream = super::freebind_connect(orig_src, upstream_addr, pi.socket_factory.as_ref())
-            .await
-            .and_then(|s| {
-                s.set_nodelay(true)?;
-                Ok(s)
-            });
+        let stream = super::freebind_connect(
+            orig_src,
+            upstream_addr,
+            pi.cfg.connect_timeout,
+            pi.socket_factory.as_ref(),
+        )
+        .await
+        .and_then(|s| {
+            s.set_nodelay(true)?;
+            Ok(s)
+        });
         let mut stream = match stream {
             Err(err) => {
                 result_tracker.record(Err(err));
@@ -363,6 +399,7 @@ impl Inbound {
         };
         debug!("connected to: {upstream_addr}");
 
+        let timeouts = pi.cfg.connection_timeouts;
         tokio::task::spawn(
             (async move {
                 let send = async {
@@ -376,6 +413,7 @@ impl Inbound {
                                         &mut ::hyper_util::rt::TokioIo::new(upgraded),
                                         &mut stream,
                                         &result_tracker,
+                                        timeouts,
                                     )
                                     .instrument(trace_span!("hbone server"))
                                     .await
@@ -392,6 +430,7 @@ impl Inbound {
                                         &mut ::hyper_util::rt::TokioIo::new(upgraded),
                                         &mut stream,
                                         &result_tracker,
+                                        timeouts,
                                     )
                                     .instrument(trace_span!("hbone server"))
                                     .await
@@ -402,6 +441,7 @@ impl Inbound {
                     }
                 };
                 let res = conn_guard.handle_connection(send).await;
+                drop(connection_slot);
                 result_tracker.record(res);
             })
             .in_current_span(),
@@ -412,6 +452,70 @@ impl Inbound {
         StatusCode::OK
     }
 
+    /// resolve_hbone_addr turns the HBONE target into a socket address. Hostname targets are
+    /// looked up as a service or workload; services are resolved to the endpoint we are serving
+    /// (or, for sandwich waypoints, the service VIP) with the service port translated.
+    async fn resolve_hbone_addr(
+        state: &DemandProxyState,
+        conn: &Connection,
+        hbone_addr: HboneAddress,
+    ) -> Result<SocketAddr, Error> {
+        let (hostname, port) = match hbone_addr {
+            HboneAddress::SocketAddr(addr) => return Ok(addr),
+            HboneAddress::SvcHostname(hostname, port) => (hostname, port),
+        };
+        // Hostnames are scoped by namespace. Prefer the namespace from a Kubernetes FQDN, otherwise
+        // scope the lookup to the client's namespace.
+        let namespace = match proxy::kube_namespace(&hostname) {
+            Some(ns) => ns,
+            None => match &conn.src_identity {
+                Some(Identity::Spiffe { namespace, .. }) => namespace.clone(),
+                None => return Err(Error::UnknownHostname(hostname.to_string())),
+            },
+        };
+        let name = NamespacedHostname {
+            namespace,
+            hostname: hostname.clone(),
+        };
+        let dst_ip = conn.dst.ip();
+        match state.fetch_hostname(&name).await {
+            Some(Address::Workload(wl)) => {
+                // Prefer the address the connection was sent to, if the workload has it.
+                let ip = if wl.workload_ips.contains(&dst_ip) {
+                    Some(dst_ip)
+                } else {
+                    wl.workload_ips.first().copied()
+                };
+                ip.map(|ip| SocketAddr::new(ip, port))
+                    .ok_or_else(|| Error::UnknownHostname(hostname.to_string()))
+            }
+            Some(Address::Service(svc)) => {
+                let dst = network_addr(conn.dst_network.clone(), dst_ip);
+                if let Some(ep) = svc
+                    .endpoints
+                    .values()
+                    .find(|ep| ep.address.as_ref() == Some(&dst))
+                {
+                    // We are serving an endpoint of the service; target it directly.
+                    let target_port = ep
+                        .port
+                        .get(&port)
+                        .or_else(|| svc.ports.get(&port))
+                        .copied()
+                        .unwrap_or(port);
+                    return Ok(SocketAddr::new(dst_ip, target_port));
+                }
+                // Otherwise, address the service itself. This is the case for sandwiched waypoints.
+                svc.vips
+                    .iter()
+                    .find(|vip| vip.network == conn.dst_network)
+                    .map(|vip| SocketAddr::new(vip.address, port))
+                    .ok_or_else(|| Error::UnknownHostname(hostname.to_string()))
+            }
+            None => Err(Error::UnknownHostname(hostname.to_string())),
+        }
+    }
+
     async fn find_inbound_upstream(
         state: &DemandProxyState,
         conn: &Connection,
@@ -474,8 +578,7 @@ impl Inbound {
         let lookup = || -> Option<Option<(Workload, Vec<Arc<Service>>)>> {
             let state = state.read();
 
-            // TODO Allow HBONE address to be a hostname. We have to respect rules about
-            // hostname scoping. Can we use the client's namespace here to do that?
+            // Hostname HBONE addresses are already resolved by resolve_hbone_addr.
             let hbone_target = state.find_address(hbone_dst);
 
             // We can only sandwich a Workload waypoint
@@ -538,6 +641,41 @@ impl Inbound {
     }
 }
 
+/// HboneAddress is the target of an HBONE CONNECT request, as sent in the authority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum HboneAddress {
+    SocketAddr(SocketAddr),
+    SvcHostname(Strng, u16),
+}
+
+impl Display for HboneAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HboneAddress::SocketAddr(addr) => write!(f, "{addr}"),
+            HboneAddress::SvcHostname(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+impl TryFrom<&http::Uri> for HboneAddress {
+    type Error = Error;
+
+    fn try_from(uri: &http::Uri) -> Result<Self, Self::Error> {
+        if let Ok(addr) = uri.to_string().parse::<SocketAddr>() {
+            return Ok(HboneAddress::SocketAddr(addr));
+        }
+        let Some(authority) = uri.authority() else {
+            return Err(Error::ConnectAddress(uri.to_string()));
+        };
+        match (authority.host(), authority.port_u16()) {
+            (host, Some(port)) if !host.is_empty() => {
+                Ok(HboneAddress::SvcHostname(strng::new(host), port))
+            }
+            _ => Err(Error::ConnectAddress(uri.to_string())),
+        }
+    }
+}
+
 struct OptionDisplay<'a, T>(&'a Option<T>);
 
 impl<'a, T: Display> Display for OptionDisplay<'a, T> {
@@ -602,12 +740,13 @@ pub fn parse_forwarded_host<T>(req: &Request<T>) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::Inbound;
+    use super::{HboneAddress, Inbound};
     use crate::strng;
 
     use std::{
         net::SocketAddr,
         sync::{Arc, RwLock},
+        time::Duration,
     };
 
     use crate::{
@@ -625,9 +764,21 @@ mod tests {
         test_helpers,
     };
 
+    use bytes::Bytes;
+    use futures_util::FutureExt;
     use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+    use http_body_util::Empty;
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response, StatusCode};
+    use prometheus_client::registry::Registry;
     use test_case::test_case;
 
+    use crate::config;
+    use crate::identity;
+    use crate::proxy::connection_manager::ConnectionManager;
+    use crate::proxy::{self, ProxyInputs};
+    use crate::state::workload::RateLimit;
+
     const CLIENT_POD_IP: &str = "10.0.0.1";
 
     const SERVER_POD_IP: &str = "10.0.0.2";
@@ -692,6 +843,153 @@ mod tests {
         }
     }
 
+    #[test_case(SERVER_POD_IP, "server.default.svc.cluster.local", Some((SERVER_POD_IP, TARGET_PORT)); "service hostname to endpoint")]
+    #[test_case(WAYPOINT_POD_IP, "server.default.svc.cluster.local", Some((SERVER_SVC_IP, TARGET_PORT)); "service hostname via sandwich waypoint")]
+    #[test_case(SERVER_POD_IP, "server.other.svc.cluster.local", None; "service hostname in wrong namespace")]
+    #[test_case(SERVER_POD_IP, "server", None; "short hostname without identity")]
+    #[tokio::test]
+    async fn test_resolve_hbone_addr(
+        connection_dst: &str,
+        hostname: &str,
+        want: Option<(&str, u16)>,
+    ) {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{connection_dst}:15008").parse().unwrap(),
+        };
+        let res = Inbound::resolve_hbone_addr(
+            &state,
+            &conn,
+            HboneAddress::SvcHostname(hostname.into(), TARGET_PORT),
+        )
+        .await;
+        match want {
+            Some((ip, port)) => {
+                assert_eq!(
+                    res.expect("resolved hostname"),
+                    SocketAddr::new(ip.parse().unwrap(), port)
+                )
+            }
+            None => {
+                res.expect_err("did not resolve hostname");
+            }
+        }
+    }
+
+    #[test]
+    fn test_hbone_address_parse() {
+        let parse = |s: &str| HboneAddress::try_from(&s.parse::<http::Uri>().unwrap()).ok();
+        assert_eq!(
+            parse("10.0.0.1:8080"),
+            Some(HboneAddress::SocketAddr("10.0.0.1:8080".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("[::1]:8080"),
+            Some(HboneAddress::SocketAddr("[::1]:8080".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("server.default.svc.cluster.local:8080"),
+            Some(HboneAddress::SvcHostname(
+                "server.default.svc.cluster.local".into(),
+                8080
+            ))
+        );
+        assert_eq!(parse("server.default.svc.cluster.local"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_connect_rate_limited() {
+        let state = test_state(Waypoint::None).expect("state setup");
+        let cfg = Arc::new(config::Config {
+            inbound_connection_rate_limit: Some(RateLimit {
+                connections_per_second: 1,
+                burst: 1,
+            }),
+            ..test_helpers::test_config()
+        });
+        let mut registry = Registry::default();
+        let metrics = Arc::new(proxy::Metrics::new(crate::metrics::sub_registry(
+            &mut registry,
+        )));
+        let pi = Arc::new(ProxyInputs::new(
+            cfg,
+            identity::mock::new_secret_manager(Duration::from_secs(10)),
+            ConnectionManager::default(),
+            state.clone(),
+            metrics,
+            Arc::new(proxy::DefaultSocketFactory),
+            None,
+        ));
+        // Use up the only token of the client, so the connection is rate limited.
+        let server = state
+            .read()
+            .workloads
+            .find_uid(&strng::new("cluster1//v1/Pod/default/server"))
+            .unwrap();
+        assert!(pi
+            .rate_limiter
+            .allow(None, CLIENT_POD_IP.parse().unwrap(), &server));
+
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".into(),
+            dst: format!("{SERVER_POD_IP}:15008").parse().unwrap(),
+        };
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(
+            hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+                .serve_connection(
+                    hyper_util::rt::TokioIo::new(server_io),
+                    service_fn(move |req| {
+                        Inbound::serve_connect(
+                            pi.clone(),
+                            conn.clone(),
+                            false,
+                            req,
+                            Default::default(),
+                            ConnectionManager::default(),
+                        )
+                        .map(|status| {
+                            Ok::<_, hyper::Error>(
+                                Response::builder()
+                                    .status(status)
+                                    .body(Empty::<Bytes>::new())
+                                    .unwrap(),
+                            )
+                        })
+                    }),
+                ),
+        );
+        let (mut sender, client_conn) = hyper::client::conn::http2::handshake(
+            hyper_util::rt::TokioExecutor::new(),
+            hyper_util::rt::TokioIo::new(client_io),
+        )
+        .await
+        .unwrap();
+        tokio::spawn(client_conn);
+        let req = Request::builder()
+            .method(Method::CONNECT)
+            .uri(format!("{SERVER_POD_IP}:{TARGET_PORT}"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &registry).unwrap();
+        assert!(
+            encoded
+                .lines()
+                .any(|l| l.starts_with("istio_tcp_connections_closed_total{")
+                    && l.contains(r#"response_flags="RL""#)),
+            "{encoded}"
+        );
+    }
+
     fn test_state(server_waypoint: Waypoint) -> anyhow::Result<state::DemandProxyState> {
         let mut state = state::ProxyState::default();
 
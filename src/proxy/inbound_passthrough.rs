@@ -192,6 +192,9 @@ impl InboundPassthrough {
             ..Default::default()
         };
         let ds = proxy::guess_inbound_service(&rbac_ctx.conn, &None, upstream_service, &upstream);
+        // Plaintext connections have no identity, so they are limited by source address.
+        let rate_limited = !pi.rate_limiter.allow(None, source_addr.ip(), &upstream);
+        let connection_slot = connection_manager.reserve_inbound(&rbac_ctx.conn, &upstream);
         let result_tracker = Arc::new(metrics::ConnectionResult::new(
             source_addr,
             dest_addr,
@@ -204,12 +207,31 @@ impl InboundPassthrough {
                 destination: Some(upstream),
                 connection_security_policy: metrics::SecurityPolicy::unknown,
                 destination_service: ds,
+                source_user: None,
+                connect_retries: 0,
             },
             pi.metrics,
         ));
 
+        if rate_limited {
+            Arc::into_inner(result_tracker)
+                .expect("arc is not shared yet")
+                .record_with_flag(Err(Error::RateLimited), metrics::ResponseFlags::RateLimited);
+            return;
+        }
+        // Counts the connection against the connection limits until it is done.
+        let _connection_slot = match connection_slot {
+            Ok(slot) => slot,
+            Err(e) => {
+                Arc::into_inner(result_tracker)
+                    .expect("arc is not shared yet")
+                    .record_with_flag(Err(e), metrics::ResponseFlags::UpstreamOverflow);
+                return;
+            }
+        };
+
         let conn_guard = match connection_manager
-            .assert_rbac(&pi.state, &rbac_ctx, None)
+            .assert_rbac(&pi.state, &rbac_ctx, None, &result_tracker)
             .await
         {
             Ok(cg) => cg,
@@ -231,16 +253,109 @@ impl InboundPassthrough {
             let result_tracker = result_tracker.clone();
             trace!(%source_addr, %dest_addr, component="inbound plaintext", "connecting...");
 
-            let mut outbound =
-                super::freebind_connect(orig_src, dest_addr, pi.socket_factory.as_ref())
-                    .await
-                    .map_err(Error::ConnectionFailed)?;
+            let mut outbound = super::freebind_connect(
+                orig_src,
+                dest_addr,
+                pi.cfg.connect_timeout,
+                pi.socket_factory.as_ref(),
+            )
+            .await
+            .map_err(Error::ConnectionFailed)?;
 
             trace!(%source_addr, destination=%dest_addr, component="inbound plaintext", "connected");
-            copy::copy_bidirectional(&mut inbound_stream, &mut outbound, &result_tracker).await
+            copy::copy_bidirectional(
+                &mut inbound_stream,
+                &mut outbound,
+                &result_tracker,
+                pi.cfg.connection_timeouts,
+            )
+            .await
         };
 
         let res = conn_guard.handle_connection(send).await;
         result_tracker.record(res);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+    use crate::config;
+    use crate::identity;
+    use crate::state::workload::RateLimit;
+    use crate::test_helpers;
+    use crate::xds::istio::workload::Workload as XdsWorkload;
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let state = test_helpers::new_proxy_state(
+            &[XdsWorkload {
+                uid: "cluster1//v1/Pod/default/server".to_string(),
+                name: "server".to_string(),
+                namespace: "default".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                ..Default::default()
+            }],
+            &[],
+            &[],
+        );
+        let cfg = Arc::new(config::Config {
+            inbound_connection_rate_limit: Some(RateLimit {
+                connections_per_second: 1,
+                burst: 1,
+            }),
+            local_ip: None,
+            ..test_helpers::test_config()
+        });
+        let mut registry = Registry::default();
+        let metrics = Arc::new(metrics::Metrics::new(crate::metrics::sub_registry(
+            &mut registry,
+        )));
+        let pi = ProxyInputs::new(
+            cfg,
+            identity::mock::new_secret_manager(Duration::from_secs(10)),
+            ConnectionManager::default(),
+            state.clone(),
+            metrics,
+            Arc::new(proxy::DefaultSocketFactory),
+            None,
+        );
+
+        let listener = TcpListener::bind("127.0.0.2:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, source_addr) = listener.accept().await.unwrap();
+        // Use up the only token of the client, so the connection is rate limited.
+        let server = state
+            .read()
+            .workloads
+            .find_uid(&strng::new("cluster1//v1/Pod/default/server"))
+            .unwrap();
+        assert!(pi.rate_limiter.allow(None, source_addr.ip(), &server));
+
+        InboundPassthrough::proxy_inbound_plaintext(
+            pi,
+            source_addr,
+            stream,
+            Default::default(),
+            ConnectionManager::default(),
+        )
+        .await;
+
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &registry).unwrap();
+        assert!(
+            encoded
+                .lines()
+                .any(|l| l.starts_with("istio_tcp_connections_closed_total{")
+                    && l.contains(r#"response_flags="RL""#)),
+            "{encoded}"
+        );
+    }
+}
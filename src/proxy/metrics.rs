@@ -14,8 +14,8 @@
 
 use std::fmt::Write;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{atomic, Arc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{atomic, Arc, OnceLock};
 use std::time::Instant;
 
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
@@ -23,13 +23,16 @@ use prometheus_client::metrics::counter::{Atomic, Counter};
 use prometheus_client::metrics::family::Family;
 use prometheus_client::registry::Registry;
 
+use itertools::Itertools;
 use tracing::event;
 
 use crate::identity::Identity;
 use crate::metrics::{DefaultedUnknown, DeferRecorder, Deferred, IncrementRecorder};
 
+use crate::state::outlier::OutlierDetector;
 use crate::state::service::ServiceDescription;
 use crate::state::workload::Workload;
+use crate::state::RbacDecision;
 use crate::strng::{RichStrng, Strng};
 
 pub struct Metrics {
@@ -41,6 +44,10 @@ pub struct Metrics {
     // on-demand DNS is not a part of DNS proxy, but part of ztunnel proxy itself
     pub on_demand_dns: Family<OnDemandDnsLabels, Counter>,
     pub on_demand_dns_cache_misses: Family<OnDemandDnsLabels, Counter>,
+
+    pub outlier_ejections: Family<OutlierEjectionLabels, Counter>,
+    pub authorization_dry_run: Family<DryRunLabels, Counter>,
+    pub authorization_policy_matches: Family<PolicyMatchLabels, Counter>,
 }
 
 impl Metrics {
@@ -97,17 +104,35 @@ pub enum ResponseFlags {
     None,
     // connection denied due to policy
     AuthorizationPolicyDenied,
+    // connection denied due to the connection rate limit
+    RateLimited,
+    // connection denied due to the concurrent connection limits
+    UpstreamOverflow,
+    // connection closed after no bytes were exchanged for the idle timeout
+    StreamIdleTimeout,
+    // connection closed after reaching the max connection duration
+    MaxDurationTimeout,
 }
 
-impl EncodeLabelValue for ResponseFlags {
-    fn encode(&self, writer: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+impl ResponseFlags {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            ResponseFlags::None => writer.write_str("-"),
-            ResponseFlags::AuthorizationPolicyDenied => writer.write_str("DENY"),
+            ResponseFlags::None => "-",
+            ResponseFlags::AuthorizationPolicyDenied => "DENY",
+            ResponseFlags::RateLimited => "RL",
+            ResponseFlags::UpstreamOverflow => "UO",
+            ResponseFlags::StreamIdleTimeout => "SI",
+            ResponseFlags::MaxDurationTimeout => "DT",
         }
     }
 }
 
+impl EncodeLabelValue for ResponseFlags {
+    fn encode(&self, writer: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        writer.write_str(self.as_str())
+    }
+}
+
 #[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum SecurityPolicy {
     #[default]
@@ -133,6 +158,10 @@ pub struct ConnectionOpen {
     pub destination: Option<Workload>,
     pub destination_service: Option<ServiceDescription>,
     pub connection_security_policy: SecurityPolicy,
+    /// The user the source authenticated as, if any. This is only set for SOCKS5.
+    pub source_user: Option<Strng>,
+    /// The number of failed connection attempts to other upstreams before this one.
+    pub connect_retries: u32,
 }
 
 impl CommonTrafficLabels {
@@ -195,6 +224,7 @@ impl From<ConnectionOpen> for CommonTrafficLabels {
             request_protocol: RequestProtocol::tcp,
             response_flags: ResponseFlags::None,
             connection_security_policy: c.connection_security_policy,
+            connect_retries: c.connect_retries,
             ..CommonTrafficLabels::new()
                 // Intentionally before with_source; source is more reliable
                 .with_derived_source(c.derived_source.as_ref())
@@ -234,6 +264,7 @@ pub struct CommonTrafficLabels {
     request_protocol: RequestProtocol,
     response_flags: ResponseFlags,
     connection_security_policy: SecurityPolicy,
+    connect_retries: u32,
 }
 
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
@@ -252,6 +283,72 @@ pub struct OnDemandDnsLabels {
     hostname: DefaultedUnknown<RichStrng>,
 }
 
+#[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct OutlierEjectionLabels {
+    destination_service: DefaultedUnknown<RichStrng>,
+    destination_service_namespace: DefaultedUnknown<RichStrng>,
+    destination_workload: DefaultedUnknown<RichStrng>,
+    destination_workload_namespace: DefaultedUnknown<RichStrng>,
+}
+
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum AuthorizationDecision {
+    #[default]
+    allow,
+    deny,
+}
+
+impl From<bool> for AuthorizationDecision {
+    fn from(allowed: bool) -> Self {
+        if allowed {
+            AuthorizationDecision::allow
+        } else {
+            AuthorizationDecision::deny
+        }
+    }
+}
+
+#[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct DryRunLabels {
+    destination_workload: DefaultedUnknown<RichStrng>,
+    destination_workload_namespace: DefaultedUnknown<RichStrng>,
+    // The enforced decision
+    decision: AuthorizationDecision,
+    // The decision if dry-run policies were enforced
+    dry_run_decision: AuthorizationDecision,
+}
+
+#[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct PolicyMatchLabels {
+    policy_namespace: RichStrng,
+    policy_name: RichStrng,
+    // The decision made for the connection; for dry-run policies, the dry-run decision
+    decision: AuthorizationDecision,
+}
+
+impl PolicyMatchLabels {
+    fn new(key: &Strng, allowed: bool) -> Self {
+        // Policy keys are namespace/name.
+        let (namespace, name) = key.split_once('/').unwrap_or(("", key.as_str()));
+        PolicyMatchLabels {
+            policy_namespace: namespace.into(),
+            policy_name: name.into(),
+            decision: allowed.into(),
+        }
+    }
+}
+
+impl From<&CommonTrafficLabels> for OutlierEjectionLabels {
+    fn from(tl: &CommonTrafficLabels) -> Self {
+        OutlierEjectionLabels {
+            destination_service: tl.destination_service.clone(),
+            destination_service_namespace: tl.destination_service_namespace.clone(),
+            destination_workload: tl.destination_workload.clone(),
+            destination_workload_namespace: tl.destination_workload_namespace.clone(),
+        }
+    }
+}
+
 impl OnDemandDnsLabels {
     pub fn new() -> Self {
         Default::default()
@@ -314,6 +411,25 @@ impl Metrics {
             "The total number of cache misses for requests on-demand DNS (unstable)",
             on_demand_dns_cache_misses.clone(),
         );
+        let outlier_ejections = Family::default();
+        registry.register(
+            "outlier_ejections",
+            "The total number of times a service endpoint was ejected after consecutive connection failures",
+            outlier_ejections.clone(),
+        );
+
+        let authorization_dry_run = Family::default();
+        registry.register(
+            "authorization_dry_run",
+            "The total number of connections evaluated against dry-run authorization policies, by enforced and dry-run decision (unstable)",
+            authorization_dry_run.clone(),
+        );
+        let authorization_policy_matches = Family::default();
+        registry.register(
+            "authorization_policy_matches",
+            "The total number of connections matched by each authorization policy, by decision (unstable)",
+            authorization_policy_matches.clone(),
+        );
 
         Self {
             connection_opens,
@@ -322,6 +438,9 @@ impl Metrics {
             sent_bytes,
             on_demand_dns,
             on_demand_dns_cache_misses,
+            outlier_ejections,
+            authorization_dry_run,
+            authorization_policy_matches,
         }
     }
 }
@@ -330,6 +449,8 @@ impl Metrics {
 pub struct ConnectionResult {
     // Src address and name
     src: (SocketAddr, Option<RichStrng>),
+    // Src authenticated user
+    src_user: Option<Strng>,
     // Dst address and name
     dst: (SocketAddr, Option<RichStrng>),
     hbone_target: Option<SocketAddr>,
@@ -350,6 +471,17 @@ pub struct ConnectionResult {
     recv: AtomicU64,
     // recv_metric records the number of bytes received on this connection to the aggregated metric counter
     recv_metric: Counter,
+
+    // outlier, if set, is fed the result of the connection to the given endpoint
+    outlier: Option<(OutlierDetector, Strng)>,
+    // endpoint_healthy records whether connecting to the endpoint showed it to be healthy
+    endpoint_healthy: OnceLock<bool>,
+
+    // audited records whether any AUDIT authorization policy matched this connection
+    audited: AtomicBool,
+
+    // close_reason, if set, is why ztunnel closed an established connection, such as a timeout
+    close_reason: OnceLock<ResponseFlags>,
 }
 
 // log_early_deny allows logging a connection is denied before we have enough information to emit proper
@@ -423,6 +555,7 @@ impl ConnectionResult {
             dst,
             conn.destination.as_ref().map(|wl| wl.name.clone().into()),
         );
+        let src_user = conn.source_user.clone();
         let tl = CommonTrafficLabels::from(conn);
         metrics.connection_opens.get_or_create(&tl).inc();
 
@@ -439,6 +572,7 @@ impl ConnectionResult {
             src.workload = src.1.as_deref().map(display),
             src.namespace = tl.source_workload_namespace.display(),
             src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
+            src.user = src_user.as_deref().map(display),
 
             dst.addr = %dst.0,
             dst.hbone_addr = hbone_target.map(display),
@@ -466,6 +600,7 @@ impl ConnectionResult {
         let recv = atomic::AtomicU64::new(0);
         Self {
             src,
+            src_user,
             dst,
             hbone_target,
             start,
@@ -476,7 +611,106 @@ impl ConnectionResult {
             sent_metric,
             recv,
             recv_metric,
+            outlier: None,
+            endpoint_healthy: OnceLock::new(),
+            audited: AtomicBool::new(false),
+            close_reason: OnceLock::new(),
+        }
+    }
+
+    /// Records the parts of an authorization decision that are reported rather than enforced: the
+    /// policies that matched, matching AUDIT policies, and the outcome of dry-run policies.
+    pub fn record_rbac(&self, decision: &RbacDecision) {
+        let tl = &self.tl;
+        let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
+        let dry_run_matched = decision
+            .dry_run
+            .iter()
+            .flat_map(|d| d.matched.iter().map(|k| (k, d.allowed)));
+        for (key, allowed) in decision
+            .matched
+            .iter()
+            .map(|k| (k, decision.allowed))
+            .chain(dry_run_matched)
+        {
+            self.metrics
+                .authorization_policy_matches
+                .get_or_create(&PolicyMatchLabels::new(key, allowed))
+                .inc();
+        }
+        if let Some(dry_run) = &decision.dry_run {
+            self.metrics
+                .authorization_dry_run
+                .get_or_create(&DryRunLabels {
+                    destination_workload: tl.destination_workload.clone(),
+                    destination_workload_namespace: tl.destination_workload_namespace.clone(),
+                    decision: decision.allowed.into(),
+                    dry_run_decision: dry_run.allowed.into(),
+                })
+                .inc();
+            if dry_run.allowed != decision.allowed {
+                event!(
+                    target: "access",
+                    parent: None,
+                    tracing::Level::INFO,
+
+                    src.addr = %self.src.0,
+                    src.workload = self.src.1.as_deref().map(display),
+                    src.namespace = tl.source_workload_namespace.display(),
+                    src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
+
+                    dst.addr = %self.dst.0,
+                    dst.service = tl.destination_service.display(),
+                    dst.workload = self.dst.1.as_deref().map(display),
+                    dst.namespace = tl.destination_workload_namespace.display(),
+
+                    decision = ?AuthorizationDecision::from(decision.allowed),
+                    dry_run_decision = ?AuthorizationDecision::from(dry_run.allowed),
+                    dry_run_policies = dry_run.matched.iter().join(","),
+
+                    "dry-run authorization decision differs"
+                );
+            }
         }
+
+        if decision.audited.is_empty() {
+            return;
+        }
+        self.audited.store(true, Ordering::SeqCst);
+        for m in &decision.audited {
+            event!(
+                target: "access",
+                parent: None,
+                tracing::Level::INFO,
+
+                src.addr = %self.src.0,
+                src.workload = self.src.1.as_deref().map(display),
+                src.namespace = tl.source_workload_namespace.display(),
+                src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
+
+                dst.addr = %self.dst.0,
+                dst.service = tl.destination_service.display(),
+                dst.workload = self.dst.1.as_deref().map(display),
+                dst.namespace = tl.destination_workload_namespace.display(),
+
+                policy = m.policy.as_str(),
+                rule = m.rule,
+
+                "audit policy matched"
+            );
+        }
+    }
+
+    /// Report the result of this connection to the outlier detector, for the given endpoint.
+    pub fn with_outlier_detection(mut self, detector: OutlierDetector, endpoint: Strng) -> Self {
+        self.outlier = Some((detector, endpoint));
+        self
+    }
+
+    /// Records whether connecting to the endpoint showed it to be healthy. This is what is reported
+    /// to the outlier detector; connections that never got this far are not reported at all.
+    pub fn set_endpoint_healthy(&self, healthy: bool) {
+        let _ = self.endpoint_healthy.set(healthy);
     }
 
     pub fn increment_send(&self, res: u64) {
@@ -489,6 +723,12 @@ impl ConnectionResult {
         self.recv_metric.inc_by(res);
     }
 
+    /// Records why ztunnel closed this connection. It is reported as the response flag when the
+    /// connection is recorded. Only the first reason is kept.
+    pub fn set_close_reason(&self, flag: ResponseFlags) {
+        let _ = self.close_reason.set(flag);
+    }
+
     pub fn record_with_flag<E: std::error::Error>(
         mut self,
         res: Result<(), E>,
@@ -501,7 +741,18 @@ impl ConnectionResult {
     // Record our final result.
     // Ideally, we would save and report from the increment_ functions instead of requiring a report here.
     pub fn record<E: std::error::Error>(&self, res: Result<(), E>) {
-        let tl = &self.tl;
+        let close_reason = self.close_reason.get();
+        let closed_tl;
+        let tl = match close_reason {
+            Some(flag) => {
+                closed_tl = CommonTrafficLabels {
+                    response_flags: *flag,
+                    ..self.tl.clone()
+                };
+                &closed_tl
+            }
+            None => &self.tl,
+        };
 
         // Unconditionally record the connection was closed
         self.metrics.connection_close.get_or_create(tl).inc();
@@ -514,6 +765,17 @@ impl ConnectionResult {
         );
         let dur = format!("{}ms", self.start.elapsed().as_millis());
 
+        if let (Some((detector, endpoint)), Some(healthy)) =
+            (&self.outlier, self.endpoint_healthy.get())
+        {
+            if detector.record(endpoint, *healthy) {
+                self.metrics
+                    .outlier_ejections
+                    .get_or_create(&OutlierEjectionLabels::from(tl))
+                    .inc();
+            }
+        }
+
         // We use our own macro to allow setting the level dynamically
         access_log!(
             res,
@@ -522,6 +784,7 @@ impl ConnectionResult {
             src.workload = self.src.1.as_deref().map(display),
             src.namespace = tl.source_workload_namespace.display(),
             src.identity = tl.source_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
+            src.user = self.src_user.as_deref().map(display),
 
             dst.addr = %self.dst.0,
             dst.hbone_addr = self.hbone_target.map(display),
@@ -541,6 +804,8 @@ impl ConnectionResult {
             bytes_sent = if tl.reporter == Reporter::source {bytes.0} else {bytes.1},
             bytes_recv = if tl.reporter == Reporter::source {bytes.1} else {bytes.0},
             duration = dur,
+            audit = self.audited.load(Ordering::SeqCst).then_some(true),
+            reason = close_reason.map(ResponseFlags::as_str),
         );
     }
 }
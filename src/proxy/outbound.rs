@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -20,7 +21,9 @@ use std::time::Instant;
 
 use drain::Watch;
 
+use futures::future::BoxFuture;
 use hyper::header::FORWARDED;
+use hyper::StatusCode;
 
 use tokio::net::{TcpListener, TcpStream};
 
@@ -145,6 +148,12 @@ impl Outbound {
     }
 }
 
+/// ConnectReply reports the outcome of connecting to the upstream to the client, before any data
+/// is proxied. On success, it is given the local address of the upstream connection. This is used
+/// by SOCKS5, where the client waits for a reply once the connection is established.
+pub(super) type ConnectReply =
+    for<'a> fn(&'a mut TcpStream, Result<SocketAddr, &'a Error>) -> BoxFuture<'a, io::Result<()>>;
+
 pub(super) struct OutboundConnection {
     pub(super) pi: Arc<ProxyInputs>,
     pub(super) id: TraceParent,
@@ -156,7 +165,7 @@ impl OutboundConnection {
         let source_addr =
             socket::to_canonical(source_stream.peer_addr().expect("must receive peer addr"));
         let dst_addr = socket::orig_dst_addr_or_default(&source_stream);
-        self.proxy_to(source_stream, source_addr, dst_addr, false)
+        self.proxy_to(source_stream, source_addr, dst_addr, false, None, None)
             .await;
     }
 
@@ -165,6 +174,11 @@ impl OutboundConnection {
     //
     // If `out_drain` is none, will only resolve when the outbound stream is terminated.
     //
+    // If `source_user` is set, the source workload's identity must match it.
+    //
+    // If `connect_reply` is set, it is called once the upstream connection is established or has
+    // failed.
+    //
     // If using `proxy_to` in `tokio::spawn` tasks, it is recommended to use a drain, to guarantee termination
     // and prevent "zombie" outbound tasks.
     pub async fn proxy_to_cancellable(
@@ -173,6 +187,8 @@ impl OutboundConnection {
         remote_addr: SocketAddr,
         orig_dst_addr: SocketAddr,
         block_passthrough: bool,
+        source_user: Option<Strng>,
+        connect_reply: Option<ConnectReply>,
         out_drain: Option<Watch>,
     ) {
         match out_drain {
@@ -181,12 +197,19 @@ impl OutboundConnection {
                         _ = drain.signaled() => {
                             info!("drain signaled");
                         }
-                        res = self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough) => res
+                        res = self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough, source_user, connect_reply) => res
                 }
             }
             None => {
-                self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough)
-                    .await;
+                self.proxy_to(
+                    stream,
+                    remote_addr,
+                    orig_dst_addr,
+                    block_passthrough,
+                    source_user,
+                    connect_reply,
+                )
+                .await;
             }
         }
     }
@@ -197,6 +220,8 @@ impl OutboundConnection {
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
         block_passthrough: bool,
+        source_user: Option<Strng>,
+        connect_reply: Option<ConnectReply>,
     ) {
         let start = Instant::now();
 
@@ -206,12 +231,15 @@ impl OutboundConnection {
             && Some(dest_addr.ip()) == self.pi.cfg.local_ip
             && !self.pi.cfg.inpod_enabled
         {
-            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, Error::SelfCall);
+            let err = Error::SelfCall;
+            send_connect_reply(connect_reply, &mut source_stream, Err(&err)).await;
+            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
             return;
         }
-        let req = match Box::pin(self.build_request(source_addr.ip(), dest_addr)).await {
+        let req = match Box::pin(self.build_request(source_addr.ip(), dest_addr, &[])).await {
             Ok(req) => req,
             Err(err) => {
+                send_connect_reply(connect_reply, &mut source_stream, Err(&err)).await;
                 metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
                 return;
             }
@@ -220,66 +248,243 @@ impl OutboundConnection {
             "request from {} to {} via {} type {:#?}",
             req.source.name, dest_addr, req.gateway, req.request_type
         );
+        if let Some(user) = &source_user {
+            // Make sure the authenticated user cannot pose as another workload.
+            let source_identity = req.source.identity();
+            if user.as_str() != source_identity.to_string() {
+                let err = Error::MismatchedSocks5User(user.clone(), source_identity);
+                send_connect_reply(connect_reply, &mut source_stream, Err(&err)).await;
+                metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+                return;
+            }
+        }
         if block_passthrough && req.destination_workload.is_none() {
             // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
             // domains. But for socks5
-            metrics::log_early_deny(
+            let err = Error::UnknownDestination(req.destination.ip());
+            send_connect_reply(connect_reply, &mut source_stream, Err(&err)).await;
+            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+            return;
+        }
+        let mut req = req;
+        let mut start = start;
+        let mut tried = Vec::new();
+        loop {
+            // TODO: should we use the original address or the actual address? Both seems nice!
+            let _conn_guard =
+                self.pi
+                    .connection_manager
+                    .track_outbound(source_addr, dest_addr, req.gateway);
+
+            let result_tracker = Box::new(self.connection_result(
                 source_addr,
-                dest_addr,
-                Reporter::source,
-                Error::UnknownDestination(req.destination.ip()),
-            );
+                start,
+                &req,
+                source_user.clone(),
+                tried.len() as u32,
+            ));
+            // Counts the connection against the connection limits until it is done. Connections to
+            // destinations outside the mesh are not limited.
+            let _connection_slot = match req
+                .destination_workload
+                .as_ref()
+                .map(|dst| {
+                    self.pi
+                        .connection_manager
+                        .reserve_outbound(&req.source, dst)
+                })
+                .transpose()
+            {
+                Ok(slot) => slot,
+                Err(e) => {
+                    send_connect_reply(connect_reply, &mut source_stream, Err(&e)).await;
+                    result_tracker
+                        .record_with_flag(Err(e), metrics::ResponseFlags::UpstreamOverflow);
+                    return;
+                }
+            };
+
+            let upstream = match self.connect(&source_stream, source_addr, &req).await {
+                Ok(upstream) => {
+                    result_tracker.set_endpoint_healthy(true);
+                    upstream
+                }
+                Err(err) => {
+                    if let Some(healthy) = endpoint_health(&err) {
+                        result_tracker.set_endpoint_healthy(healthy);
+                    }
+                    // Nothing was read from the client yet, so we can still try another upstream.
+                    tried.push(req.gateway.ip());
+                    let next = self
+                        .retry_request(source_addr, dest_addr, &req, &tried, &err)
+                        .await;
+                    if next.is_none() {
+                        send_connect_reply(connect_reply, &mut source_stream, Err(&err)).await;
+                    }
+                    result_tracker.record(Err(err));
+                    match next {
+                        Some(next) => {
+                            req = next;
+                            start = Instant::now();
+                            continue;
+                        }
+                        None => return,
+                    }
+                }
+            };
+
+            if let Some(reply) = connect_reply {
+                let res = match upstream.local_addr() {
+                    Ok(bound) => reply(&mut source_stream, Ok(bound)).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    result_tracker.record(Err(e.into()));
+                    return;
+                }
+            }
+
+            let res = match upstream {
+                UpstreamConnection::Hbone(upgraded) => {
+                    copy::copy_bidirectional(
+                        source_stream,
+                        upgraded,
+                        &result_tracker,
+                        self.pi.cfg.connection_timeouts,
+                    )
+                    .await
+                }
+                UpstreamConnection::Tcp(mut outbound) => {
+                    copy::copy_bidirectional(
+                        &mut source_stream,
+                        &mut outbound,
+                        &result_tracker,
+                        self.pi.cfg.connection_timeouts,
+                    )
+                    .await
+                }
+            };
+            result_tracker.record(res);
             return;
         }
-        // TODO: should we use the original address or the actual address? Both seems nice!
-        let _conn_guard =
-            self.pi
-                .connection_manager
-                .track_outbound(source_addr, dest_addr, req.gateway);
+    }
 
-        let metrics = self.pi.metrics.clone();
+    fn connection_result(
+        &self,
+        source_addr: SocketAddr,
+        start: Instant,
+        req: &Request,
+        source_user: Option<Strng>,
+        connect_retries: u32,
+    ) -> ConnectionResult {
         let hbone_target = if req.protocol == Protocol::HBONE {
             Some(req.destination)
         } else {
             None
         };
-        let result_tracker = Box::new(ConnectionResult::new(
+        let result_tracker = ConnectionResult::new(
             source_addr,
             req.gateway,
             hbone_target,
             start,
-            Self::conn_metrics_from_request(&req),
-            metrics,
-        ));
-
-        let res = match req.protocol {
-            Protocol::HBONE => {
-                self.proxy_to_hbone(source_stream, source_addr, &req, &result_tracker)
-                    .await
-            }
-            Protocol::TCP => {
-                self.proxy_to_tcp(&mut source_stream, &req, &result_tracker)
-                    .await
+            Self::conn_metrics_from_request(req, source_user, connect_retries),
+            self.pi.metrics.clone(),
+        );
+        // Results are attributed to the workload we dial. If the gateway is not one of its
+        // addresses, such as a gateway in front of the workload, a failure says nothing about the
+        // workload itself.
+        match &req.destination_workload {
+            Some(wl) if wl.workload_ips.contains(&req.gateway.ip()) => {
+                let detector = self.pi.state.read().load_balancing.outlier_detector.clone();
+                result_tracker.with_outlier_detection(detector, wl.uid.clone())
             }
-        };
-        result_tracker.record(res)
+            _ => result_tracker,
+        }
     }
 
-    async fn proxy_to_hbone(
+    /// Connects to the upstream of the request, without sending any data yet.
+    async fn connect(
         &mut self,
-        stream: TcpStream,
+        stream: &TcpStream,
         remote_addr: SocketAddr,
         req: &Request,
-        connection_stats: &ConnectionResult,
-    ) -> Result<(), Error> {
-        debug!(
-            "proxy to {} using HBONE via {} type {:#?}",
-            req.destination, req.gateway, req.request_type
-        );
-
-        let upgraded = Box::pin(self.build_hbone_request(remote_addr, &req)).await?;
+    ) -> Result<UpstreamConnection, Error> {
+        let connect_timeout = self.pi.cfg.connect_timeout;
+        let connect = async {
+            match req.protocol {
+                Protocol::HBONE => {
+                    debug!(
+                        "proxy to {} using HBONE via {} type {:#?}",
+                        req.destination, req.gateway, req.request_type
+                    );
+                    let upgraded = Box::pin(self.build_hbone_request(remote_addr, &req)).await?;
+                    Ok(UpstreamConnection::Hbone(upgraded))
+                }
+                Protocol::TCP => {
+                    debug!(
+                        "Proxying to {} using TCP via {} type {:?}",
+                        req.destination, req.gateway, req.request_type
+                    );
+                    // Create a TCP connection to upstream
+                    let local = if self.pi.cfg.enable_original_source.unwrap_or_default() {
+                        super::get_original_src_from_stream(stream)
+                    } else {
+                        None
+                    };
+                    let addrs: Vec<_> = std::iter::once(req.gateway)
+                        .chain(req.fallback_gateways.iter().copied())
+                        .collect();
+                    let outbound = super::freebind_connect_racing(
+                        local,
+                        &addrs,
+                        connect_timeout,
+                        self.pi.socket_factory.as_ref(),
+                    )
+                    .await?;
+                    Ok(UpstreamConnection::Tcp(outbound))
+                }
+            }
+        };
+        tokio::time::timeout(connect_timeout, connect)
+            .await
+            .map_err(|_| Error::ConnectTimeout(req.gateway, connect_timeout))?
+    }
 
-        copy::copy_bidirectional(stream, upgraded, connection_stats).await
+    /// Builds a request to a different upstream than the ones in `tried`, if the request can be retried.
+    /// Only failures of the endpoint to accept the connection, `err`, are retried; rejections by
+    /// policy or rate limit are not.
+    async fn retry_request(
+        &self,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        req: &Request,
+        tried: &[IpAddr],
+        err: &Error,
+    ) -> Option<Box<Request>> {
+        if req.request_type == RequestType::Passthrough
+            || endpoint_health(err) != Some(false)
+            || tried.len() > self.pi.cfg.connect_retries as usize
+        {
+            return None;
+        }
+        match Box::pin(self.build_request(source_addr.ip(), dest_addr, tried)).await {
+            // If there is no other upstream, we may fall back to passthrough; never retry that.
+            Ok(next)
+                if next.request_type != RequestType::Passthrough
+                    && !tried.contains(&next.gateway.ip()) =>
+            {
+                debug!(
+                    "retrying connection to {} via {} after failing to connect to {}",
+                    dest_addr, next.gateway, req.gateway
+                );
+                Some(next)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                debug!("no other upstream to retry {dest_addr}: {err}");
+                None
+            }
+        }
     }
 
     async fn build_hbone_request(
@@ -333,30 +538,11 @@ impl OutboundConnection {
         Ok(upgraded)
     }
 
-    async fn proxy_to_tcp(
-        &mut self,
-        stream: &mut TcpStream,
+    fn conn_metrics_from_request(
         req: &Request,
-        connection_stats: &ConnectionResult,
-    ) -> Result<(), Error> {
-        debug!(
-            "Proxying to {} using TCP via {} type {:?}",
-            req.destination, req.gateway, req.request_type
-        );
-        // Create a TCP connection to upstream
-        let local = if self.pi.cfg.enable_original_source.unwrap_or_default() {
-            super::get_original_src_from_stream(stream)
-        } else {
-            None
-        };
-        let mut outbound =
-            super::freebind_connect(local, req.gateway, self.pi.socket_factory.as_ref()).await?;
-
-        // Proxying data between downstream and upstream
-        copy::copy_bidirectional(stream, &mut outbound, connection_stats).await
-    }
-
-    fn conn_metrics_from_request(req: &Request) -> ConnectionOpen {
+        source_user: Option<Strng>,
+        connect_retries: u32,
+    ) -> ConnectionOpen {
         ConnectionOpen {
             reporter: Reporter::source,
             derived_source: None,
@@ -368,19 +554,31 @@ impl OutboundConnection {
                 metrics::SecurityPolicy::unknown
             },
             destination_service: req.destination_service.clone(),
+            source_user,
+            connect_retries,
         }
     }
 
+    /// Builds the request for a connection from downstream to target. Addresses in `excluded` are not
+    /// picked as the upstream.
     async fn build_request(
         &self,
         downstream: IpAddr,
         target: SocketAddr,
+        excluded: &[IpAddr],
     ) -> Result<Box<Request>, Error> {
+        let excluding;
+        let state = if excluded.is_empty() {
+            &self.pi.state
+        } else {
+            excluding = self.pi.state.excluding(excluded);
+            &excluding
+        };
         let downstream_network_addr = NetworkAddress {
             network: strng::new(&self.pi.cfg.network),
             address: downstream,
         };
-        let source_workload = match self.pi.state.fetch_workload(&downstream_network_addr).await {
+        let source_workload = match state.fetch_workload(&downstream_network_addr).await {
             Some(wl) => wl,
             None => return Err(Error::UnknownSource(downstream)),
         };
@@ -393,9 +591,7 @@ impl OutboundConnection {
 
         // If this is to-service traffic check for a service waypoint
         // Capture result of whether or not this is svc addressed
-        let svc_addressed = if let Some(Address::Service(s)) = self
-            .pi
-            .state
+        let svc_addressed = if let Some(Address::Service(s)) = state
             .fetch_destination(&Destination::Address(NetworkAddress {
                 network: strng::new(&self.pi.cfg.network),
                 address: target.ip(),
@@ -404,31 +600,19 @@ impl OutboundConnection {
         {
             // if we have a waypoint for this svc, use it; otherwise route traffic normally
             if let Some(wp) = s.waypoint.clone() {
-                let waypoint_vip = match wp.destination {
-                    Destination::Address(a) => a.address,
-                    Destination::Hostname(_) => {
-                        return Err(proxy::Error::UnknownWaypoint(
-                            "hostname lookup not supported yet".to_string(),
-                        ));
-                    }
-                };
-                let waypoint_vip = SocketAddr::new(waypoint_vip, wp.hbone_mtls_port);
-                let waypoint_us = self
-                    .pi
-                    .state
-                    .fetch_upstream(self.pi.cfg.network.clone(), &source_workload, waypoint_vip)
+                let waypoint_us = state
+                    .fetch_gateway_upstream(&wp, &source_workload)
                     .await
                     .ok_or(proxy::Error::UnknownWaypoint(
                         "unable to determine waypoint upstream".to_string(),
                     ))?;
 
                 let waypoint_workload = waypoint_us.workload;
-                let waypoint_ip = self
-                    .pi
-                    .state
+                let waypoint_ip = state
                     .pick_workload_destination(
                         &waypoint_workload,
                         &source_workload,
+                        downstream,
                         self.pi.metrics.clone(),
                     )
                     .await?; // if we can't load balance just return the error
@@ -443,6 +627,7 @@ impl OutboundConnection {
                     destination_service: Some(ServiceDescription::from(&*s)),
                     expected_identity: Some(id),
                     gateway: waypoint_socket_address,
+                    fallback_gateways: vec![],
                     request_type: RequestType::ToServerWaypoint,
                     upstream_sans: waypoint_us.sans,
                 }));
@@ -455,9 +640,7 @@ impl OutboundConnection {
         };
 
         // TODO: we want a single lock for source and upstream probably...?
-        let us = match self
-            .pi
-            .state
+        let us = match state
             .fetch_upstream(source_workload.network.clone(), &source_workload, target)
             .await
         {
@@ -472,20 +655,24 @@ impl OutboundConnection {
                     destination_service: None,
                     expected_identity: None,
                     gateway: target,
+                    fallback_gateways: vec![],
                     request_type: RequestType::Passthrough,
                     upstream_sans: vec![],
                 }));
             }
         };
 
-        let workload_ip = self
-            .pi
-            .state
-            .pick_workload_destination(&us.workload, &source_workload, self.pi.metrics.clone())
+        let workload_ip = state
+            .pick_workload_destination(
+                &us.workload,
+                &source_workload,
+                downstream,
+                self.pi.metrics.clone(),
+            )
             .await?;
 
         let from_waypoint = proxy::check_from_waypoint(
-            &self.pi.state,
+            state,
             &us.workload,
             Some(&source_workload.identity()),
             &downstream_network_addr.address,
@@ -496,21 +683,18 @@ impl OutboundConnection {
         // Don't traverse waypoint if traffic was addressed to a service which did not have a waypoint
         if !from_waypoint && !svc_addressed {
             // For case upstream server has enabled waypoint
-            match self
-                .pi
-                .state
+            match state
                 .fetch_waypoint(&us.workload, &source_workload, workload_ip)
                 .await
             {
                 Ok(None) => {} // workload doesn't have a waypoint; this is fine
                 Ok(Some(waypoint_us)) => {
                     let waypoint_workload = waypoint_us.workload;
-                    let waypoint_ip = self
-                        .pi
-                        .state
+                    let waypoint_ip = state
                         .pick_workload_destination(
                             &waypoint_workload,
                             &source_workload,
+                            downstream,
                             self.pi.metrics.clone(),
                         )
                         .await?;
@@ -526,6 +710,7 @@ impl OutboundConnection {
                         destination_service: us.destination_service.clone(),
                         expected_identity: Some(id),
                         gateway: waypoint_socket_address,
+                        fallback_gateways: vec![],
                         request_type: RequestType::ToServerWaypoint,
                         upstream_sans: us.sans,
                     }));
@@ -541,6 +726,19 @@ impl OutboundConnection {
             Protocol::TCP => SocketAddr::from((workload_ip, us.port)),
         };
 
+        // For plain TCP, the workload's addresses of the other family are raced against the
+        // selected one, in case it is unreachable.
+        let fallback_gateways = match us.workload.protocol {
+            Protocol::HBONE => vec![],
+            Protocol::TCP => us
+                .workload
+                .workload_ips
+                .iter()
+                .filter(|ip| ip.is_ipv4() != workload_ip.is_ipv4() && !excluded.contains(ip))
+                .map(|ip| SocketAddr::new(*ip, us.port))
+                .collect(),
+        };
+
         // For case no waypoint for both side and direct to remote node proxy
         Ok(Box::new(Request {
             protocol: us.workload.protocol,
@@ -550,6 +748,7 @@ impl OutboundConnection {
             destination_service: us.destination_service.clone(),
             expected_identity: Some(us.workload.identity()),
             gateway: gw_addr,
+            fallback_gateways,
             request_type: RequestType::Direct,
             upstream_sans: us.sans,
         }))
@@ -579,11 +778,57 @@ struct Request {
     // in the case of proxies along the path.
     expected_identity: Option<Identity>,
     gateway: SocketAddr,
+    // Addresses to race against the gateway when connecting over TCP, for dual-stack destinations.
+    fallback_gateways: Vec<SocketAddr>,
     request_type: RequestType,
 
     upstream_sans: Vec<Strng>,
 }
 
+enum UpstreamConnection {
+    Hbone(H2Stream),
+    Tcp(TcpStream),
+}
+
+impl UpstreamConnection {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            UpstreamConnection::Hbone(upgraded) => Ok(upgraded.local_addr()),
+            UpstreamConnection::Tcp(stream) => stream.local_addr(),
+        }
+    }
+}
+
+// endpoint_health returns what a failure to connect says about the health of the endpoint.
+// Endpoints that cannot be reached, or fail the HBONE CONNECT, are unhealthy. Endpoints that reject
+// the CONNECT by policy or rate limit are healthy. Other failures, such as failing to fetch our own
+// certificate, are not the endpoint's doing.
+fn endpoint_health(err: &Error) -> Option<bool> {
+    match err {
+        Error::Io(_)
+        | Error::ConnectionFailed(_)
+        | Error::ConnectTimeout(..)
+        | Error::Http2Handshake(_) => Some(false),
+        Error::HttpStatus(code) => {
+            Some(*code == StatusCode::UNAUTHORIZED || *code == StatusCode::TOO_MANY_REQUESTS)
+        }
+        _ => None,
+    }
+}
+
+// send_connect_reply reports a failure or success to the client, if it asked for a reply.
+async fn send_connect_reply(
+    connect_reply: Option<ConnectReply>,
+    stream: &mut TcpStream,
+    res: Result<SocketAddr, &Error>,
+) {
+    if let Some(reply) = connect_reply {
+        if let Err(e) = reply(stream, res).await {
+            debug!("failed to send connect reply: {e}");
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 enum RequestType {
     /// ToServerWaypoint refers to requests targeting a server waypoint proxy
@@ -619,6 +864,37 @@ mod tests {
         xds: XdsAddressType,
         expect: Option<ExpectedRequest<'_>>,
     ) {
+        run_build_request_excluding(from, to, xds, &[], expect).await
+    }
+
+    async fn run_build_request_excluding(
+        from: &str,
+        to: &str,
+        xds: XdsAddressType,
+        excluded: &[IpAddr],
+        expect: Option<ExpectedRequest<'_>>,
+    ) {
+        let outbound = test_outbound(xds);
+        let req = outbound
+            .build_request(from.parse().unwrap(), to.parse().unwrap(), excluded)
+            .await
+            .ok();
+        if let Some(r) = req {
+            assert_eq!(
+                expect,
+                Some(ExpectedRequest {
+                    protocol: r.protocol,
+                    destination: &r.destination.to_string(),
+                    gateway: &r.gateway.to_string(),
+                    request_type: r.request_type,
+                })
+            );
+        } else {
+            assert_eq!(expect, None);
+        }
+    }
+
+    fn test_outbound(xds: XdsAddressType) -> OutboundConnection {
         let cfg = Arc::new(Config {
             local_node: Some("local-node".to_string()),
             ..crate::config::parse_config().unwrap()
@@ -638,16 +914,43 @@ mod tests {
             addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
             node: "local-node".to_string(),
             service_account: "waypoint-sa".to_string(),
+            services: std::collections::HashMap::from([(
+                "ns/waypoint.ns.svc.cluster.local".to_string(),
+                xds::istio::workload::PortList {
+                    ports: vec![Port {
+                        service_port: 15008,
+                        target_port: 15008,
+                    }],
+                },
+            )]),
+            ..Default::default()
+        };
+        let waypoint_svc = XdsService {
+            name: "waypoint".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "waypoint.ns.svc.cluster.local".to_string(),
+            addresses: vec![XdsNetworkAddress {
+                network: "".to_string(),
+                address: vec![127, 0, 0, 11],
+            }],
+            ports: vec![Port {
+                service_port: 15008,
+                target_port: 15008,
+            }],
             ..Default::default()
         };
         let state = match xds {
-            XdsAddressType::Workload(wl) => new_proxy_state(&[source, waypoint, wl], &[], &[]),
-            XdsAddressType::Service(svc) => new_proxy_state(&[source, waypoint], &[svc], &[]),
+            XdsAddressType::Workload(wl) => {
+                new_proxy_state(&[source, waypoint, wl], &[waypoint_svc], &[])
+            }
+            XdsAddressType::Service(svc) => {
+                new_proxy_state(&[source, waypoint], &[waypoint_svc, svc], &[])
+            }
         };
 
         let sock_fact = std::sync::Arc::new(crate::proxy::DefaultSocketFactory);
         let cert_mgr = identity::mock::new_secret_manager(Duration::from_secs(10));
-        let outbound = OutboundConnection {
+        OutboundConnection {
             pi: Arc::new(ProxyInputs {
                 cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
                 state,
@@ -657,27 +960,10 @@ mod tests {
                 socket_factory: sock_fact.clone(),
                 proxy_workload_info: None,
                 connection_manager: ConnectionManager::default(),
+                rate_limiter: Default::default(),
             }),
             id: TraceParent::new(),
             pool: pool::WorkloadHBONEPool::new(cfg, sock_fact, cert_mgr.clone()),
-        };
-
-        let req = outbound
-            .build_request(from.parse().unwrap(), to.parse().unwrap())
-            .await
-            .ok();
-        if let Some(r) = req {
-            assert_eq!(
-                expect,
-                Some(ExpectedRequest {
-                    protocol: r.protocol,
-                    destination: &r.destination.to_string(),
-                    gateway: &r.gateway.to_string(),
-                    request_type: r.request_type,
-                })
-            );
-        } else {
-            assert_eq!(expect, None);
         }
     }
 
@@ -725,6 +1011,94 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_excluded_dest() {
+        let wl = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/test-tcp".to_string(),
+            name: "test-tcp".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![
+                Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                Bytes::copy_from_slice(&[127, 0, 0, 3]),
+            ],
+            tunnel_protocol: XdsProtocol::None as i32,
+            node: "remote-node".to_string(),
+            ..Default::default()
+        };
+        run_build_request_excluding(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(wl.clone()),
+            &["127.0.0.2".parse().unwrap()],
+            Some(ExpectedRequest {
+                protocol: Protocol::TCP,
+                destination: "127.0.0.3:80",
+                gateway: "127.0.0.3:80",
+                request_type: RequestType::Direct,
+            }),
+        )
+        .await;
+        // No address left to pick
+        run_build_request_excluding(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(wl),
+            &["127.0.0.2".parse().unwrap(), "127.0.0.3".parse().unwrap()],
+            None,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn retry_only_connect_failures() {
+        let outbound = test_outbound(XdsAddressType::Workload(XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/test-tcp".to_string(),
+            name: "test-tcp".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![
+                Bytes::copy_from_slice(&[127, 0, 0, 2]),
+                Bytes::copy_from_slice(&[127, 0, 0, 3]),
+            ],
+            tunnel_protocol: XdsProtocol::None as i32,
+            node: "remote-node".to_string(),
+            ..Default::default()
+        }));
+        let source: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let dest: SocketAddr = "127.0.0.2:80".parse().unwrap();
+        let req = outbound
+            .build_request(source.ip(), dest, &[])
+            .await
+            .unwrap();
+        let tried = [req.gateway.ip()];
+
+        let refused = Error::Io(std::io::ErrorKind::ConnectionRefused.into());
+        let next = outbound
+            .retry_request(source, dest, &req, &tried, &refused)
+            .await
+            .expect("connect failures are retried");
+        assert_ne!(next.gateway.ip(), req.gateway.ip());
+
+        // The endpoint was reached and rejected the connection; another one would do the same.
+        for code in [StatusCode::UNAUTHORIZED, StatusCode::TOO_MANY_REQUESTS] {
+            let rejected = Error::HttpStatus(code);
+            assert!(outbound
+                .retry_request(source, dest, &req, &tried, &rejected)
+                .await
+                .is_none());
+        }
+        // Local failures are not the endpoint's doing.
+        assert!(outbound
+            .retry_request(
+                source,
+                dest,
+                &req,
+                &tried,
+                &Error::UnknownSource(source.ip())
+            )
+            .await
+            .is_none());
+    }
+
     #[tokio::test]
     async fn build_request_known_dest_remote_node_hbone() {
         run_build_request(
@@ -910,6 +1284,71 @@ mod tests {
         .await;
     }
 
+    fn hostname_waypoint() -> xds::istio::workload::GatewayAddress {
+        xds::istio::workload::GatewayAddress {
+            destination: Some(
+                xds::istio::workload::gateway_address::Destination::Hostname(
+                    xds::istio::workload::NamespacedHostname {
+                        namespace: "ns".to_string(),
+                        hostname: "waypoint.ns.svc.cluster.local".to_string(),
+                    },
+                ),
+            ),
+            hbone_mtls_port: 15008,
+            hbone_single_tls_port: 15003,
+        }
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/default/my-pod".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                waypoint: Some(hostname_waypoint()),
+                ..Default::default()
+            }),
+            // Should resolve the waypoint service and use it
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.2:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_svc_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.3:80",
+            XdsAddressType::Service(XdsService {
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                waypoint: Some(hostname_waypoint()),
+                ..Default::default()
+            }),
+            // Should resolve the waypoint service and use it
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.3:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
     #[derive(PartialEq, Debug)]
     struct ExpectedRequest<'a> {
         protocol: Protocol,
@@ -92,8 +92,13 @@ impl ConnSpawner {
             .then_some(key.src);
         let cert = self.cert_manager.fetch_certificate(&key.src_id).await?;
         let connector = cert.outbound_connector(key.dst_id.clone())?;
-        let tcp_stream =
-            super::freebind_connect(local, key.dst, self.socket_factory.as_ref()).await?;
+        let tcp_stream = super::freebind_connect(
+            local,
+            key.dst,
+            self.cfg.connect_timeout,
+            self.socket_factory.as_ref(),
+        )
+        .await?;
         tcp_stream.set_nodelay(true)?;
         let tls_stream = connector.connect(tcp_stream).await?;
         trace!("connector connected, handshaking");
@@ -0,0 +1,281 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use keyed_priority_queue::KeyedPriorityQueue;
+use tracing::debug;
+
+use crate::identity::Identity;
+use crate::state::workload::{RateLimit, Workload};
+use crate::strng::Strng;
+
+/// The maximum number of buckets kept. Once reached, the least recently used bucket is dropped to
+/// make room for a new one, even if it has not refilled yet: letting the least active source start
+/// over is better than denying every new source.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Who a connection is from, for the purpose of rate limiting.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+enum Source {
+    Identity(Identity),
+    // Plaintext connections have no identity, so they are limited by address.
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+struct BucketKey {
+    destination: Strng,
+    source: Source,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            limit,
+            tokens: capacity(limit),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.connections_per_second as f64)
+            .min(capacity(self.limit));
+        self.updated = now;
+    }
+}
+
+/// The buckets, and the order they were last used in.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    lru: KeyedPriorityQueue<BucketKey, Reverse<Instant>>,
+}
+
+impl Buckets {
+    /// Makes room for a new bucket, dropping the least recently used one if needed.
+    fn make_room(&mut self) {
+        if self.buckets.len() < MAX_BUCKETS {
+            return;
+        }
+        if let Some((key, _)) = self.lru.pop() {
+            self.buckets.remove(&key);
+        }
+    }
+}
+
+fn capacity(limit: RateLimit) -> f64 {
+    // A bucket that cannot hold a single token would deny everything.
+    limit.burst.max(1) as f64
+}
+
+/// RateLimiter limits the rate of new inbound connections with a token bucket per source and
+/// destination workload. Sources are identified by their identity, or by their address if they
+/// have none.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    /// The limit for workloads that do not set their own.
+    default_limit: Option<RateLimit>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(default_limit: Option<RateLimit>) -> Self {
+        RateLimiter {
+            default_limit,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Returns whether a new connection from the source to the workload is allowed, counting it
+    /// against the limit if so.
+    pub fn allow(&self, src_identity: Option<&Identity>, src: IpAddr, dst: &Workload) -> bool {
+        self.allow_at(src_identity, src, dst, Instant::now())
+    }
+
+    fn allow_at(
+        &self,
+        src_identity: Option<&Identity>,
+        src: IpAddr,
+        dst: &Workload,
+        now: Instant,
+    ) -> bool {
+        let Some(limit) = dst.connection_rate_limit.or(self.default_limit) else {
+            return true;
+        };
+        let source = match src_identity {
+            Some(id) => Source::Identity(id.clone()),
+            None => Source::Ip(src),
+        };
+        let key = BucketKey {
+            destination: dst.uid.clone(),
+            source,
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.buckets.contains_key(&key) {
+            buckets.make_room();
+        }
+        buckets.lru.push(key.clone(), Reverse(now));
+        let bucket = buckets
+            .buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::new(limit, now));
+        // The limit may have changed since the bucket was created.
+        bucket.limit = limit;
+        bucket.refill(now);
+        if bucket.tokens < 1.0 {
+            debug!(src=%src, dst=%dst.uid, "connection rate limit exceeded");
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_helpers;
+
+    fn workload(uid: &str, limit: Option<RateLimit>) -> Workload {
+        Workload {
+            uid: uid.into(),
+            connection_rate_limit: limit,
+            ..test_helpers::test_default_workload()
+        }
+    }
+
+    fn limit(connections_per_second: u32, burst: u32) -> Option<RateLimit> {
+        Some(RateLimit {
+            connections_per_second,
+            burst,
+        })
+    }
+
+    fn identity(sa: &str) -> Identity {
+        Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: sa.into(),
+        }
+    }
+
+    #[test]
+    fn unlimited() {
+        let rl = RateLimiter::default();
+        let dst = workload("dst", None);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert!(rl.allow_at(None, "127.0.0.1".parse().unwrap(), &dst, now));
+        }
+    }
+
+    #[test]
+    fn burst_and_refill() {
+        let rl = RateLimiter::default();
+        let dst = workload("dst", limit(2, 3));
+        let src = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(rl.allow_at(None, src, &dst, now));
+        }
+        assert!(!rl.allow_at(None, src, &dst, now));
+        // 2 per second, so one token every 500ms.
+        let later = now + Duration::from_millis(400);
+        assert!(!rl.allow_at(None, src, &dst, later));
+        let later = now + Duration::from_millis(500);
+        assert!(rl.allow_at(None, src, &dst, later));
+        assert!(!rl.allow_at(None, src, &dst, later));
+        // Refills up to the burst only.
+        let later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(rl.allow_at(None, src, &dst, later));
+        }
+        assert!(!rl.allow_at(None, src, &dst, later));
+    }
+
+    #[test]
+    fn keyed_by_source_and_destination() {
+        let rl = RateLimiter::new(limit(1, 1));
+        let dst = workload("dst", None);
+        let other_dst = workload("other", None);
+        let ip = "127.0.0.1".parse().unwrap();
+        let other_ip = "127.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(rl.allow_at(None, ip, &dst, now));
+        assert!(!rl.allow_at(None, ip, &dst, now));
+        // Each destination has its own limit.
+        assert!(rl.allow_at(None, ip, &other_dst, now));
+        // Without an identity, sources are told apart by address.
+        assert!(rl.allow_at(None, other_ip, &dst, now));
+
+        // With one, the address does not matter.
+        let id = identity("client");
+        assert!(rl.allow_at(Some(&id), ip, &dst, now));
+        assert!(!rl.allow_at(Some(&id), other_ip, &dst, now));
+        assert!(rl.allow_at(Some(&identity("other")), ip, &dst, now));
+    }
+
+    #[test]
+    fn workload_limit_overrides_default() {
+        let rl = RateLimiter::new(limit(1, 1));
+        let dst = workload("dst", limit(1, 5));
+        let src = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert!(rl.allow_at(None, src, &dst, now));
+        }
+        assert!(!rl.allow_at(None, src, &dst, now));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_bucket() {
+        let rl = RateLimiter::new(limit(1, 1));
+        let dst = workload("dst", None);
+        let ip = |i: u32| IpAddr::from(std::net::Ipv4Addr::from(i));
+        let now = Instant::now();
+        for i in 0..MAX_BUCKETS as u32 {
+            assert!(rl.allow_at(None, ip(i), &dst, now));
+        }
+        // Every bucket is drained, but an unrelated new source is still allowed.
+        let new = ip(MAX_BUCKETS as u32);
+        assert!(rl.allow_at(None, new, &dst, now));
+        assert!(!rl.allow_at(None, new, &dst, now));
+        // Sources that were kept are still limited.
+        assert!(!rl.allow_at(None, ip(1), &dst, now));
+        let buckets = rl.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), MAX_BUCKETS);
+        assert_eq!(buckets.lru.len(), MAX_BUCKETS);
+        // ip(0) was the least recently used, so it was dropped.
+        assert!(!buckets.buckets.contains_key(&BucketKey {
+            destination: dst.uid.clone(),
+            source: Source::Ip(ip(0)),
+        }));
+    }
+}
@@ -16,24 +16,36 @@ use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder};
 use drain::Watch;
 
-use std::net::{IpAddr, SocketAddr};
+use futures::future::BoxFuture;
+use hickory_proto::rr::Name;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use tracing::{debug, error, info};
 
 use crate::proxy::outbound::OutboundConnection;
 use crate::proxy::{util, Error, ProxyInputs, TraceParent};
-use crate::socket;
+use crate::state::workload::address::Address;
+use crate::state::workload::{network_addr, NamespacedHostname, Workload};
+use crate::strng::Strng;
+use crate::{dns, proxy, socket, strng};
 
 pub(super) struct Socks5 {
     pi: ProxyInputs,
     listener: TcpListener,
     drain: Watch,
+    credentials: Option<Arc<Credentials>>,
 }
 
+/// Credentials maps SOCKS5 usernames to passwords. Usernames are workload identities.
+type Credentials = HashMap<String, String>;
+
 impl Socks5 {
     pub(super) async fn new(pi: ProxyInputs, drain: Watch) -> Result<Socks5, Error> {
         let listener: TcpListener = pi
@@ -41,9 +53,23 @@ impl Socks5 {
             .tcp_bind(pi.cfg.socks5_addr.unwrap())
             .map_err(|e| Error::Bind(pi.cfg.socks5_addr.unwrap(), e))?;
 
+        let credentials = match &pi.cfg.socks5_credentials {
+            Some(source) => {
+                let creds = source
+                    .read_to_string()
+                    .await
+                    .map_err(|e| Error::InvalidSocks5Credentials(e.to_string()))?;
+                let creds: Credentials = serde_yaml::from_str(&creds)
+                    .map_err(|e| Error::InvalidSocks5Credentials(e.to_string()))?;
+                Some(Arc::new(creds))
+            }
+            None => None,
+        };
+
         info!(
             address=%listener.local_addr().expect("local_addr available"),
             component="socks5",
+            authenticated=credentials.is_some(),
             "listener established",
         );
 
@@ -51,6 +77,7 @@ impl Socks5 {
             pi,
             listener,
             drain,
+            credentials,
         })
     }
 
@@ -67,6 +94,7 @@ impl Socks5 {
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
                 let stream_drain = inner_drain.clone();
+                let credentials = self.credentials.clone();
                 // TODO creating a new HBONE pool for SOCKS5 here may not be ideal,
                 // but ProxyInfo is overloaded and only `outbound` should ever use the pool.
                 let pool = crate::proxy::pool::WorkloadHBONEPool::new(
@@ -83,7 +111,9 @@ impl Socks5 {
                             pool,
                         };
                         tokio::spawn(async move {
-                            if let Err(err) = handle(oc, stream, stream_drain, inpod).await {
+                            if let Err(err) =
+                                handle(oc, stream, stream_drain, inpod, credentials).await
+                            {
                                 log::error!("handshake error: {}", err);
                             }
                         });
@@ -110,14 +140,17 @@ impl Socks5 {
 
 // handle will process a SOCKS5 connection. This supports a minimal subset of the protocol,
 // sufficient to integrate with common clients:
-// - only unauthenticated requests
-// - only CONNECT, with IPv4 or IPv6
+// - only unauthenticated requests, or username/password (RFC 1929) if credentials are configured
+// - only CONNECT, with IPv4, IPv6, or a domain name
 async fn handle(
     mut oc: OutboundConnection,
     mut stream: TcpStream,
     out_drain: Watch,
     is_inpod: bool,
+    credentials: Option<Arc<Credentials>>,
 ) -> Result<(), anyhow::Error> {
+    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+
     // Version(5), Number of auth methods
     let mut version = [0u8; 2];
     stream.read_exact(&mut version).await?;
@@ -136,13 +169,30 @@ async fn handle(
     let mut methods = vec![0u8; nmethods as usize];
     stream.read_exact(&mut methods).await?;
 
-    // Client must include 'unauthenticated' (0).
-    if !methods.into_iter().any(|x| x == 0) {
-        return Err(anyhow::anyhow!("unsupported auth method"));
-    }
+    let user = match credentials {
+        Some(credentials) => {
+            // Client must include 'username/password' (2).
+            if !methods.into_iter().any(|x| x == 2) {
+                // No acceptable methods
+                stream.write_all(&[0x05, 0xff]).await?;
+                return Err(anyhow::anyhow!("unsupported auth method"));
+            }
 
-    // Select 'unauthenticated' (0).
-    stream.write_all(&[0x05, 0x00]).await?;
+            // Select 'username/password' (2).
+            stream.write_all(&[0x05, 0x02]).await?;
+            Some(authenticate(&mut stream, &credentials).await?)
+        }
+        None => {
+            // Client must include 'unauthenticated' (0).
+            if !methods.into_iter().any(|x| x == 0) {
+                return Err(anyhow::anyhow!("unsupported auth method"));
+            }
+
+            // Select 'unauthenticated' (0).
+            stream.write_all(&[0x05, 0x00]).await?;
+            None
+        }
+    };
 
     // Version(5), Command - only support CONNECT (1)
     let mut version_command = [0u8; 2];
@@ -164,27 +214,23 @@ async fn handle(
     let mut atyp = [0u8];
     stream.read_exact(&mut atyp).await?;
 
-    let ip;
-
-    match atyp[0] {
+    let host = match atyp[0] {
         0x01 => {
             let mut hostb = [0u8; 4];
             stream.read_exact(&mut hostb).await?;
-            ip = IpAddr::V4(hostb.into());
+            Host::Ip(IpAddr::V4(hostb.into()))
         }
         0x04 => {
             let mut hostb = [0u8; 16];
             stream.read_exact(&mut hostb).await?;
-            ip = IpAddr::V6(hostb.into());
+            Host::Ip(IpAddr::V6(hostb.into()))
         }
         0x03 => {
             let mut domain_length = [0u8];
             stream.read_exact(&mut domain_length).await?;
             let mut domain = vec![0u8; domain_length[0] as usize];
             stream.read_exact(&mut domain).await?;
-            // TODO: DNS lookup, if we want to integrate with HTTP-based apps without
-            // a DNS server.
-            return Err(anyhow::anyhow!("unsupported host"));
+            Host::Domain(String::from_utf8(domain).map_err(|_| anyhow::anyhow!("invalid domain"))?)
         }
         _ => {
             return Err(anyhow::anyhow!("unsupported host"));
@@ -195,18 +241,18 @@ async fn handle(
     stream.read_exact(&mut port).await?;
     let port = BigEndian::read_u16(&port);
 
-    let host = SocketAddr::new(ip, port);
-
-    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+    let ip = match host {
+        Host::Ip(ip) => ip,
+        Host::Domain(domain) => match resolve_domain(&oc.pi, remote_addr.ip(), &domain).await {
+            Some(ip) => ip,
+            None => {
+                send_reply(&mut stream, REPLY_HOST_UNREACHABLE, UNSPECIFIED_ADDR).await?;
+                return Err(anyhow::anyhow!("unable to resolve host {domain}"));
+            }
+        },
+    };
 
-    // Send dummy values - the client generally ignores it.
-    let buf = [
-        0x05u8, // versuib
-        0x00, 0x00, // success, rsv
-        0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
-        0x00, 0x00, // port
-    ];
-    stream.write_all(&buf).await?;
+    let host = SocketAddr::new(ip, port);
 
     info!("accepted connection from {remote_addr} to {host}");
     // For inpod, we want this `spawn` to guaranteed-terminate when we drain - the workload is gone.
@@ -217,8 +263,190 @@ async fn handle(
             true => Some(out_drain),
             false => None,
         };
-        oc.proxy_to_cancellable(stream, remote_addr, host, true, drain)
-            .await;
+        oc.proxy_to_cancellable(
+            stream,
+            remote_addr,
+            host,
+            true,
+            user,
+            Some(connect_reply),
+            drain,
+        )
+        .await;
     });
     Ok(())
 }
+
+// authenticate runs the username/password sub-negotiation from RFC 1929, returning the
+// authenticated user.
+async fn authenticate(
+    stream: &mut TcpStream,
+    credentials: &Credentials,
+) -> Result<Strng, anyhow::Error> {
+    // Version(1), username length
+    let mut version = [0u8; 2];
+    stream.read_exact(&mut version).await?;
+
+    if version[0] != 0x01 {
+        return Err(anyhow::anyhow!("unsupported auth version"));
+    }
+
+    let mut username = vec![0u8; version[1] as usize];
+    stream.read_exact(&mut username).await?;
+
+    let mut password_length = [0u8];
+    stream.read_exact(&mut password_length).await?;
+    let mut password = vec![0u8; password_length[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    let Ok(username) = String::from_utf8(username) else {
+        // Failure; identities are always valid UTF-8.
+        stream.write_all(&[0x01, 0x01]).await?;
+        return Err(anyhow::anyhow!("authentication failed for non UTF-8 user"));
+    };
+    let valid = credentials
+        .get(&username)
+        .is_some_and(|expected| constant_time_eq(expected.as_bytes(), &password));
+    if !valid {
+        // Failure
+        stream.write_all(&[0x01, 0x01]).await?;
+        return Err(anyhow::anyhow!(
+            "authentication failed for user {username:?}"
+        ));
+    }
+
+    // Success
+    stream.write_all(&[0x01, 0x00]).await?;
+    Ok(strng::new(username))
+}
+
+// constant_time_eq compares a and b without short-circuiting on the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+enum Host {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+// Reply codes from RFC 1928.
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+
+// The bound address reported when there is no connection.
+const UNSPECIFIED_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+// connect_reply replies to the CONNECT request once the upstream connection is established or has
+// failed. On success, the bound address is the local address of the upstream connection.
+fn connect_reply<'a>(
+    stream: &'a mut TcpStream,
+    res: Result<SocketAddr, &'a Error>,
+) -> BoxFuture<'a, io::Result<()>> {
+    Box::pin(async move {
+        match res {
+            Ok(bound) => send_reply(stream, REPLY_SUCCEEDED, bound).await,
+            Err(err) => send_reply(stream, reply_code(err), UNSPECIFIED_ADDR).await,
+        }
+    })
+}
+
+// reply_code maps a failure to connect to the closest RFC 1928 reply code.
+fn reply_code(err: &Error) -> u8 {
+    match err {
+        Error::Io(e) | Error::ConnectionFailed(e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused => REPLY_CONNECTION_REFUSED,
+            io::ErrorKind::TimedOut => REPLY_HOST_UNREACHABLE,
+            _ => match e.raw_os_error() {
+                Some(libc::EHOSTUNREACH) => REPLY_HOST_UNREACHABLE,
+                Some(libc::ENETUNREACH) => REPLY_NETWORK_UNREACHABLE,
+                _ => REPLY_GENERAL_FAILURE,
+            },
+        },
+        Error::ConnectTimeout(..) => REPLY_HOST_UNREACHABLE,
+        // The destination rejected the HBONE CONNECT.
+        Error::HttpStatus(_) => REPLY_CONNECTION_REFUSED,
+        Error::SelfCall
+        | Error::MismatchedSocks5User(..)
+        | Error::UnknownDestination(_)
+        | Error::UpstreamOverflow(_) => REPLY_NOT_ALLOWED,
+        _ => REPLY_GENERAL_FAILURE,
+    }
+}
+
+// send_reply writes a reply with the given status and bound address.
+async fn send_reply(stream: &mut TcpStream, status: u8, bound: SocketAddr) -> io::Result<()> {
+    let bound = socket::to_canonical(bound);
+    let mut buf = vec![
+        0x05u8, // version
+        status, 0x00, // status, rsv
+    ];
+    match bound.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(0x01);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(0x04);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&bound.port().to_be_bytes());
+    stream.write_all(&buf).await
+}
+
+// resolve_domain resolves a domain requested by the client at `source`. Hostnames known to the mesh
+// are preferred, searched for the same way as the DNS proxy would for the client. Anything else is
+// resolved with the system resolver.
+async fn resolve_domain(pi: &ProxyInputs, source: IpAddr, domain: &str) -> Option<IpAddr> {
+    let name = Name::from_str(domain).ok()?;
+    let source_workload = pi
+        .state
+        .fetch_workload(&network_addr(strng::new(&pi.cfg.network), source))
+        .await;
+    if let Some(source_workload) = source_workload {
+        if let Some(ip) = find_mesh_hostname(pi, &source_workload, &name) {
+            debug!(%domain, %ip, "resolved mesh hostname");
+            return Some(ip);
+        }
+    }
+    let ip = pi.state.resolve_hostname(domain).await.into_iter().next();
+    debug!(%domain, ?ip, "resolved hostname with system resolver");
+    ip
+}
+
+fn find_mesh_hostname(pi: &ProxyInputs, source_workload: &Workload, name: &Name) -> Option<IpAddr> {
+    let candidates = dns::search_names(
+        name,
+        &pi.cfg.cluster_domain,
+        &source_workload.namespace,
+        pi.cfg.dns_resolver_cfg.search(),
+    );
+    let state = pi.state.read();
+    for mut candidate in candidates {
+        candidate.set_fqdn(false);
+        let hostname = strng::new(candidate.to_string());
+        let namespace =
+            proxy::kube_namespace(&hostname).unwrap_or_else(|| source_workload.namespace.clone());
+        let ip = match state.find_hostname(&NamespacedHostname {
+            namespace,
+            hostname,
+        }) {
+            Some(Address::Service(svc)) => svc
+                .vips
+                .iter()
+                .find(|vip| vip.network == source_workload.network)
+                .map(|vip| vip.address),
+            Some(Address::Workload(wl)) => wl.workload_ips.first().copied(),
+            None => None,
+        };
+        if ip.is_some() {
+            return ip;
+        }
+    }
+    None
+}
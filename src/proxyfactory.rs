@@ -20,6 +20,7 @@ use std::sync::Arc;
 use tracing::error;
 
 use crate::dns;
+use crate::dns::cache::ResponseCache;
 
 use crate::proxy::connection_manager::ConnectionManager;
 use crate::proxy::{Error, Metrics};
@@ -34,6 +35,10 @@ pub struct ProxyFactory {
     cert_manager: Arc<SecretManager>,
     proxy_metrics: Option<Arc<Metrics>>,
     dns_metrics: Option<Arc<dns::Metrics>>,
+    // Shared by all DNS proxies created by this factory.
+    dns_cache: Option<Arc<ResponseCache>>,
+    // Connection limits are enforced across all proxies created by this factory.
+    connection_limits: ConnectionManager,
     drain: Watch,
 }
 
@@ -65,19 +70,46 @@ impl ProxyFactory {
             }
         };
 
+        let dns_cache = (config.dns_proxy && config.dns_cache_max_entries > 0)
+            .then(|| Arc::new(ResponseCache::new(config.dns_cache_max_entries)));
+
+        let connection_limits = ConnectionManager::new(config.connection_limits);
+
         Ok(ProxyFactory {
             config,
             state,
             cert_manager,
             proxy_metrics,
             dns_metrics,
+            dns_cache,
+            connection_limits,
             drain,
         })
     }
 
+    /// Whether the proxies created by this factory include a DNS proxy.
+    pub fn dns_proxy_enabled(&self) -> bool {
+        self.config.dns_proxy
+    }
+
+    /// The DNS forwarder for the proxy mode, used by DNS proxies that are not given their own.
+    pub fn dns_forwarder(&self) -> Result<Arc<dyn dns::Forwarder>, Error> {
+        dns::forwarder_for_mode(self.config.proxy_mode)
+    }
+
+    /// The cache of upstream DNS responses, if the DNS proxy and caching are enabled.
+    pub fn dns_cache(&self) -> Option<Arc<ResponseCache>> {
+        self.dns_cache.clone()
+    }
+
     pub async fn new_proxies(&self) -> Result<ProxyResult, Error> {
-        self.new_proxies_from_factory(None, None, Arc::new(crate::proxy::DefaultSocketFactory))
-            .await
+        self.new_proxies_from_factory(
+            None,
+            None,
+            Arc::new(crate::proxy::DefaultSocketFactory),
+            None,
+        )
+        .await
     }
 
     pub async fn new_proxies_from_factory(
@@ -85,13 +117,15 @@ impl ProxyFactory {
         proxy_drain: Option<Watch>,
         proxy_workload_info: Option<WorkloadInfo>,
         socket_factory: Arc<dyn crate::proxy::SocketFactory + Send + Sync>,
+        // If unset, the DNS forwarder for the proxy mode is used.
+        dns_forwarder: Option<Arc<dyn dns::Forwarder>>,
     ) -> Result<ProxyResult, Error> {
         let mut result: ProxyResult = Default::default();
         let drain = proxy_drain.unwrap_or_else(|| self.drain.clone());
 
         // Optionally create the HBONE proxy.
         if self.config.proxy {
-            let cm = ConnectionManager::default();
+            let cm = self.connection_limits.with_shared_limits();
             let pi = crate::proxy::ProxyInputs::new(
                 self.config.clone(),
                 self.cert_manager.clone(),
@@ -113,7 +147,12 @@ impl ProxyFactory {
                     self.config.dns_proxy_addr,
                     self.config.network.clone(),
                     self.state.clone(),
-                    dns::forwarder_for_mode(self.config.proxy_mode)?,
+                    match dns_forwarder {
+                        Some(forwarder) => forwarder,
+                        None => self.dns_forwarder()?,
+                    },
+                    self.dns_cache.clone(),
+                    self.config.dns_on_demand_timeout,
                     self.dns_metrics.clone().unwrap(),
                     drain,
                     socket_factory.as_ref(),
@@ -26,10 +26,12 @@ use xds::istio::security::StringMatch as XdsStringMatch;
 
 use crate::identity::Identity;
 
-use crate::state::workload::{byte_to_ip, WorkloadError};
+use crate::state::workload::{byte_to_ip, is_default, WorkloadError};
 use crate::strng::Strng;
 use crate::{strng, xds};
 
+pub mod ext_authz;
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Authorization {
@@ -38,6 +40,18 @@ pub struct Authorization {
     pub scope: RbacScope,
     pub action: RbacAction,
     pub rules: Vec<Vec<Vec<RbacMatch>>>,
+    /// If set, the policy is evaluated but not enforced.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dry_run: bool,
+}
+
+/// An AUDIT policy that matched a connection.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct AuditMatch {
+    /// The key of the policy.
+    pub policy: Strng,
+    /// The index of the rule that matched.
+    pub rule: usize,
 }
 
 #[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd, serde::Serialize)]
@@ -48,6 +62,19 @@ pub struct Connection {
     pub dst_network: Strng,
 }
 
+/// Attributes of the source workload and destination service of a connection, as known from the
+/// proxy state. Unknown attributes are empty.
+#[derive(Debug, Default, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionAttributes {
+    pub src_service_account: Strng,
+    pub src_canonical_name: Strng,
+    pub src_canonical_revision: Strng,
+    pub src_cluster_id: Strng,
+    /// The hostname of the destination service.
+    pub dst_service: Strng,
+}
+
 struct OptionDisplay<'a, T>(&'a Option<T>);
 
 impl<'a, T: Display> Display for OptionDisplay<'a, T> {
@@ -80,27 +107,37 @@ impl Authorization {
         res.into()
     }
 
-    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
+    /// Returns whether the policy matches the connection, without any workload or service
+    /// attributes known.
     pub fn matches(&self, conn: &Connection) -> bool {
-        let id = conn
-            .src_identity
-            .as_ref()
-            .map(|i| i.to_strng())
-            .unwrap_or_default();
-        let ns = conn
-            .src_identity
-            .as_ref()
-            .map(|i| match i {
-                Identity::Spiffe { namespace, .. } => namespace.to_owned(), // may be more clear if we use to_owned() to denote change from borrowed to owned
-            })
-            .unwrap_or_default();
+        self.matches_with(conn, &ConnectionAttributes::default())
+    }
+
+    pub fn matches_with(&self, conn: &Connection, attrs: &ConnectionAttributes) -> bool {
+        self.matched_rule(conn, attrs).is_some()
+    }
+
+    /// Returns whether any rule matches on `ConnectionAttributes`, so they must be resolved to
+    /// evaluate the policy.
+    pub fn uses_connection_attributes(&self) -> bool {
+        self.rules
+            .iter()
+            .flatten()
+            .flatten()
+            .any(RbacMatch::uses_connection_attributes)
+    }
+
+    /// Returns the index of the first rule that matches the connection, if any.
+    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
+    pub fn matched_rule(&self, conn: &Connection, attrs: &ConnectionAttributes) -> Option<usize> {
+        let (id, ns) = Self::source_identity(conn);
         if self.rules.is_empty() {
             trace!(matches = false, "empty rules");
-            return false;
+            return None;
         }
         // An Authorization Policy can have multiple rules
         // If ANY rule matches it's a match...
-        for rule in self.rules.iter() {
+        for (idx, rule) in self.rules.iter().enumerate() {
             // Rule typically has 1-3 clauses (from,to,when)
             // If ALL clauses match, it is a match...
             let mut rule_match = true;
@@ -113,40 +150,7 @@ impl Authorization {
                         trace!(matches = false, "empty clause");
                         continue;
                     }
-                    // We need ALL of these to match. Within each type, ANY must match
-                    let mut m = true;
-                    m &= Self::matches_internal(
-                        "destination_ip",
-                        &mg.destination_ips,
-                        &mg.not_destination_ips,
-                        |i| i.contains(&conn.dst.ip()),
-                    );
-                    m &= Self::matches_internal(
-                        "source_ips",
-                        &mg.source_ips,
-                        &mg.not_source_ips,
-                        |i| i.contains(&conn.src.ip()),
-                    );
-                    m &= Self::matches_internal(
-                        "destination_ports",
-                        &mg.destination_ports,
-                        &mg.not_destination_ports,
-                        |p| *p == conn.dst.port(),
-                    );
-                    m &= Self::matches_internal(
-                        "principals",
-                        &mg.principals,
-                        &mg.not_principals,
-                        |p| p.matches_principal(&id),
-                    );
-                    m &= Self::matches_internal(
-                        "namespaces",
-                        &mg.namespaces,
-                        &mg.not_namespaces,
-                        |p| p.matches(&ns),
-                    );
-
-                    if m {
+                    if Self::matches_group(mg, conn, attrs, &id, &ns, &mut |_, _| {}) {
                         clause_match = true;
                         break;
                     }
@@ -166,10 +170,166 @@ impl Authorization {
             }
             trace!(matches = rule_match, "rule");
             if rule_match {
-                return true;
+                return Some(idx);
             }
         }
-        false
+        None
+    }
+
+    /// Evaluates the policy against the connection like `matched_rule`, but without short
+    /// circuiting, recording which rules, clauses and match fields passed or failed.
+    pub fn explain(&self, conn: &Connection, attrs: &ConnectionAttributes) -> PolicyExplanation {
+        let (id, ns) = Self::source_identity(conn);
+        let rules: Vec<RuleExplanation> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let clauses: Vec<ClauseExplanation> = rule
+                    .iter()
+                    .map(|clause| {
+                        let matches: Vec<MatchExplanation> = clause
+                            .iter()
+                            .map(|mg| {
+                                let mut explanation = MatchExplanation::default();
+                                let matched = Self::matches_group(
+                                    mg,
+                                    conn,
+                                    attrs,
+                                    &id,
+                                    &ns,
+                                    &mut |field, matched| {
+                                        if matched {
+                                            explanation.passed.push(field)
+                                        } else {
+                                            explanation.failed.push(field)
+                                        }
+                                    },
+                                );
+                                // An empty match never matches.
+                                explanation.matched = matched && !mg.is_empty();
+                                explanation
+                            })
+                            .collect();
+                        ClauseExplanation {
+                            matched: matches.is_empty() || matches.iter().any(|m| m.matched),
+                            matches,
+                        }
+                    })
+                    .collect();
+                RuleExplanation {
+                    matched: clauses.iter().all(|c| c.matched),
+                    clauses,
+                }
+            })
+            .collect();
+        PolicyExplanation {
+            policy: self.to_key(),
+            action: self.action,
+            dry_run: self.dry_run,
+            matched_rule: rules.iter().position(|r| r.matched),
+            rules,
+        }
+    }
+
+    // Returns the source identity and namespace of the connection, or empty strings for plaintext.
+    fn source_identity(conn: &Connection) -> (Strng, Strng) {
+        let id = conn
+            .src_identity
+            .as_ref()
+            .map(|i| i.to_strng())
+            .unwrap_or_default();
+        let ns = conn
+            .src_identity
+            .as_ref()
+            .map(|i| match i {
+                Identity::Spiffe { namespace, .. } => namespace.to_owned(), // may be more clear if we use to_owned() to denote change from borrowed to owned
+            })
+            .unwrap_or_default();
+        (id, ns)
+    }
+
+    // Checks a single match group. `report` is called with each declared field, and whether it matched.
+    fn matches_group(
+        mg: &RbacMatch,
+        conn: &Connection,
+        attrs: &ConnectionAttributes,
+        id: &Strng,
+        ns: &Strng,
+        report: &mut dyn FnMut(&'static str, bool),
+    ) -> bool {
+        // We need ALL of these to match. Within each type, ANY must match
+        let mut m = true;
+        m &= Self::matches_internal(
+            "destination_ips",
+            &mg.destination_ips,
+            &mg.not_destination_ips,
+            |i| i.contains(&conn.dst.ip()),
+            report,
+        );
+        m &= Self::matches_internal(
+            "source_ips",
+            &mg.source_ips,
+            &mg.not_source_ips,
+            |i| i.contains(&conn.src.ip()),
+            report,
+        );
+        m &= Self::matches_internal(
+            "destination_ports",
+            &mg.destination_ports,
+            &mg.not_destination_ports,
+            |p| *p == conn.dst.port(),
+            report,
+        );
+        m &= Self::matches_internal(
+            "principals",
+            &mg.principals,
+            &mg.not_principals,
+            |p| p.matches_principal(id),
+            report,
+        );
+        m &= Self::matches_internal(
+            "namespaces",
+            &mg.namespaces,
+            &mg.not_namespaces,
+            |p| p.matches(ns),
+            report,
+        );
+        m &= Self::matches_internal(
+            "service_accounts",
+            &mg.service_accounts,
+            &mg.not_service_accounts,
+            |p| p.matches(&attrs.src_service_account),
+            report,
+        );
+        m &= Self::matches_internal(
+            "canonical_names",
+            &mg.canonical_names,
+            &mg.not_canonical_names,
+            |p| p.matches(&attrs.src_canonical_name),
+            report,
+        );
+        m &= Self::matches_internal(
+            "canonical_revisions",
+            &mg.canonical_revisions,
+            &mg.not_canonical_revisions,
+            |p| p.matches(&attrs.src_canonical_revision),
+            report,
+        );
+        m &= Self::matches_internal(
+            "cluster_ids",
+            &mg.cluster_ids,
+            &mg.not_cluster_ids,
+            |p| p.matches(&attrs.src_cluster_id),
+            report,
+        );
+        m &= Self::matches_internal(
+            "destination_services",
+            &mg.destination_services,
+            &mg.not_destination_services,
+            |p| p.matches(&attrs.dst_service),
+            report,
+        );
+        m
     }
 
     #[instrument(name= "match", level = "trace", skip_all, fields(%desc))]
@@ -178,6 +338,7 @@ impl Authorization {
         positive: &Vec<T>,
         negative: &Vec<T>,
         mut predicate: impl FnMut(&T) -> bool,
+        report: &mut dyn FnMut(&'static str, bool),
     ) -> bool {
         let pm = if positive.is_empty() {
             trace!(matches = true, "type" = "positive", "no match declared");
@@ -195,10 +356,51 @@ impl Authorization {
             trace!(%matches, "type"="negative", "{negative:?}");
             matches
         };
+        if !positive.is_empty() || !negative.is_empty() {
+            report(desc, pm && nm);
+        }
         pm && nm
     }
 }
 
+/// How a policy was evaluated against a connection.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyExplanation {
+    pub policy: Strng,
+    pub action: RbacAction,
+    #[serde(skip_serializing_if = "is_default")]
+    pub dry_run: bool,
+    /// The index of the first rule that matched, if any. The policy applies if this is set.
+    pub matched_rule: Option<usize>,
+    pub rules: Vec<RuleExplanation>,
+}
+
+/// A rule matches if all of its clauses match.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleExplanation {
+    pub matched: bool,
+    pub clauses: Vec<ClauseExplanation>,
+}
+
+/// A clause matches if any of its matches do, or if it is empty.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClauseExplanation {
+    pub matched: bool,
+    pub matches: Vec<MatchExplanation>,
+}
+
+/// A match matches if all of its declared fields do.
+#[derive(Debug, Clone, Default, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchExplanation {
+    pub matched: bool,
+    pub passed: Vec<&'static str>,
+    pub failed: Vec<&'static str>,
+}
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone, Default, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RbacMatch {
@@ -222,9 +424,42 @@ pub struct RbacMatch {
     pub destination_ports: Vec<u16>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub not_destination_ports: Vec<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub service_accounts: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_service_accounts: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub canonical_names: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_canonical_names: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub canonical_revisions: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_canonical_revisions: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub cluster_ids: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_cluster_ids: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub destination_services: Vec<StringMatch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_destination_services: Vec<StringMatch>,
 }
 
 impl RbacMatch {
+    fn uses_connection_attributes(&self) -> bool {
+        !(self.service_accounts.is_empty()
+            && self.not_service_accounts.is_empty()
+            && self.canonical_names.is_empty()
+            && self.not_canonical_names.is_empty()
+            && self.canonical_revisions.is_empty()
+            && self.not_canonical_revisions.is_empty()
+            && self.cluster_ids.is_empty()
+            && self.not_cluster_ids.is_empty()
+            && self.destination_services.is_empty()
+            && self.not_destination_services.is_empty())
+    }
+
     fn is_empty(&self) -> bool {
         self.namespaces.is_empty()
             && self.not_namespaces.is_empty()
@@ -236,6 +471,16 @@ impl RbacMatch {
             && self.not_destination_ips.is_empty()
             && self.destination_ports.is_empty()
             && self.not_destination_ports.is_empty()
+            && self.service_accounts.is_empty()
+            && self.not_service_accounts.is_empty()
+            && self.canonical_names.is_empty()
+            && self.not_canonical_names.is_empty()
+            && self.canonical_revisions.is_empty()
+            && self.not_canonical_revisions.is_empty()
+            && self.cluster_ids.is_empty()
+            && self.not_cluster_ids.is_empty()
+            && self.destination_services.is_empty()
+            && self.not_destination_services.is_empty()
     }
 }
 
@@ -289,6 +534,11 @@ impl From<xds::istio::security::Scope> for RbacScope {
 pub enum RbacAction {
     Allow,
     Deny,
+    /// Matches are only reported; they never change whether a connection is allowed.
+    Audit,
+    /// Matches are sent to the external authorization server, which decides whether the
+    /// connection is allowed.
+    Custom,
 }
 
 impl From<xds::istio::security::Action> for RbacAction {
@@ -296,6 +546,8 @@ impl From<xds::istio::security::Action> for RbacAction {
         match value {
             xds::istio::security::Action::Allow => RbacAction::Allow,
             xds::istio::security::Action::Deny => RbacAction::Deny,
+            xds::istio::security::Action::Audit => RbacAction::Audit,
+            xds::istio::security::Action::Custom => RbacAction::Custom,
         }
     }
 }
@@ -325,6 +577,7 @@ impl TryFrom<XdsRbac> for Authorization {
             scope: RbacScope::from(xds::istio::security::Scope::try_from(resource.scope)?),
             action: RbacAction::from(xds::istio::security::Action::try_from(resource.action)?),
             rules,
+            dry_run: resource.dry_run,
         })
     }
 }
@@ -376,6 +629,52 @@ impl TryFrom<&Match> for RbacMatch {
                 .iter()
                 .map(|p| *p as u16)
                 .collect(),
+            service_accounts: resource
+                .service_accounts
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            not_service_accounts: resource
+                .not_service_accounts
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            canonical_names: resource
+                .canonical_names
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            not_canonical_names: resource
+                .not_canonical_names
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            canonical_revisions: resource
+                .canonical_revisions
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            not_canonical_revisions: resource
+                .not_canonical_revisions
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            cluster_ids: resource.cluster_ids.iter().filter_map(From::from).collect(),
+            not_cluster_ids: resource
+                .not_cluster_ids
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            destination_services: resource
+                .destination_services
+                .iter()
+                .filter_map(From::from)
+                .collect(),
+            not_destination_services: resource
+                .not_destination_services
+                .iter()
+                .filter_map(From::from)
+                .collect(),
         })
     }
 }
@@ -433,6 +732,7 @@ mod tests {
             scope: RbacScope::Global,
             action: RbacAction::Allow,
             rules,
+            dry_run: false,
         }
     }
 
@@ -660,6 +960,82 @@ mod tests {
         &tls_conn() => false,
         &tls_conn_alt() => true);
 
+    #[test]
+    fn rbac_workload_attributes() {
+        let attrs = ConnectionAttributes {
+            src_service_account: "account".into(),
+            src_canonical_name: "app".into(),
+            src_canonical_revision: "v1".into(),
+            src_cluster_id: "cluster".into(),
+            dst_service: "svc.namespace.svc.cluster.local".into(),
+        };
+        let matchers = [
+            RbacMatch {
+                service_accounts: vec![StringMatch::Exact("account".into())],
+                ..Default::default()
+            },
+            RbacMatch {
+                canonical_names: vec![StringMatch::Exact("app".into())],
+                ..Default::default()
+            },
+            RbacMatch {
+                canonical_revisions: vec![StringMatch::Prefix("v".into())],
+                ..Default::default()
+            },
+            RbacMatch {
+                cluster_ids: vec![StringMatch::Exact("cluster".into())],
+                ..Default::default()
+            },
+            RbacMatch {
+                destination_services: vec![StringMatch::Suffix(
+                    ".namespace.svc.cluster.local".into(),
+                )],
+                ..Default::default()
+            },
+        ];
+        for m in matchers {
+            let pol = allow_policy("attrs", vec![vec![vec![m.clone()]]]);
+            assert!(pol.uses_connection_attributes(), "{m:?}");
+            assert!(pol.matches_with(&plaintext_conn(), &attrs), "{m:?}");
+            // Unknown attributes never match
+            assert!(!pol.matches(&plaintext_conn()), "{m:?}");
+        }
+
+        let pol = allow_policy(
+            "attrs",
+            vec![vec![vec![RbacMatch {
+                not_canonical_revisions: vec![StringMatch::Exact("v1".into())],
+                not_destination_services: vec![StringMatch::Exact("other".into())],
+                ..Default::default()
+            }]]],
+        );
+        assert!(!pol.matches_with(&plaintext_conn(), &attrs));
+        assert!(pol.matches_with(
+            &plaintext_conn(),
+            &ConnectionAttributes {
+                src_canonical_revision: "v2".into(),
+                ..attrs.clone()
+            }
+        ));
+        assert!(!pol.matches_with(
+            &plaintext_conn(),
+            &ConnectionAttributes {
+                src_canonical_revision: "v2".into(),
+                dst_service: "other".into(),
+                ..attrs
+            }
+        ));
+
+        let pol = allow_policy(
+            "no-attrs",
+            vec![vec![vec![RbacMatch {
+                namespaces: vec![StringMatch::Exact("default".into())],
+                ..Default::default()
+            }]]],
+        );
+        assert!(!pol.uses_connection_attributes());
+    }
+
     #[test_case(StringMatch::Exact("foo".into()), "foo", true; "exact match")]
     #[test_case(StringMatch::Exact("foo".into()), "not", false; "exact mismatch")]
     #[test_case(StringMatch::Exact("foo".into()), "", false; "exact empty mismatch")]
@@ -0,0 +1,337 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use keyed_priority_queue::KeyedPriorityQueue;
+use tracing::{debug, instrument, warn};
+
+use crate::config::RootCert;
+use crate::identity::Identity;
+use crate::rbac::Connection;
+use crate::strng::Strng;
+use crate::tls::{self, TlsGrpcChannel};
+use crate::xds::service::auth::v3::attribute_context::Peer;
+use crate::xds::service::auth::v3::authorization_client::AuthorizationClient;
+use crate::xds::service::auth::v3::{Address, AttributeContext, CheckRequest, SocketAddress};
+
+/// The maximum number of decisions kept in the cache. Once reached, the decision closest to
+/// expiring is dropped to make room for a new one.
+const MAX_CACHE_ENTRIES: usize = 10_000;
+
+/// ExtAuthzConfig configures the external authorization server used by CUSTOM policies.
+#[derive(serde::Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtAuthzConfig {
+    /// Address of the server implementing the Envoy ext_authz gRPC API.
+    pub address: String,
+    /// Root cert for TLS verification of the server.
+    pub root_cert: RootCert,
+    /// How long to wait for a decision.
+    pub timeout: Duration,
+    /// If true, connections are allowed when the server cannot be reached or does not respond in
+    /// time. Otherwise, they are denied.
+    pub fail_open: bool,
+    /// How long a decision is reused for the same source and destination. If 0, decisions are
+    /// not cached.
+    pub cache_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+struct CacheKey {
+    src_identity: Option<Identity>,
+    // The source port is not part of the key; it is ephemeral and would make the cache useless.
+    src: IpAddr,
+    dst: SocketAddr,
+    dst_network: Strng,
+}
+
+impl From<&Connection> for CacheKey {
+    fn from(conn: &Connection) -> Self {
+        CacheKey {
+            src_identity: conn.src_identity.clone(),
+            src: conn.src.ip(),
+            dst: conn.dst,
+            dst_network: conn.dst_network.clone(),
+        }
+    }
+}
+
+/// A cached decision. Decisions are ordered by expiry first, so the cache pops the one that
+/// expires soonest.
+#[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+struct Decision {
+    expiry: Reverse<Instant>,
+    allowed: bool,
+}
+
+/// ExtAuthz is a client for an external authorization server, consulted when a CUSTOM policy
+/// matches a connection.
+#[derive(Clone, Debug)]
+pub struct ExtAuthz {
+    client: AuthorizationClient<TlsGrpcChannel>,
+    cfg: ExtAuthzConfig,
+    cache: Arc<Mutex<KeyedPriorityQueue<CacheKey, Decision>>>,
+}
+
+impl ExtAuthz {
+    pub async fn new(cfg: ExtAuthzConfig) -> Result<ExtAuthz, tls::Error> {
+        let svc = tls::grpc_tls_connector(cfg.address.clone(), cfg.root_cert.clone()).await?;
+        Ok(ExtAuthz {
+            client: AuthorizationClient::new(svc),
+            cfg,
+            cache: Default::default(),
+        })
+    }
+
+    /// Asks the external authorization server whether the connection is allowed.
+    /// `dst_identity` is the identity of the destination workload, if known.
+    #[instrument(level = "debug", skip_all, fields(%conn))]
+    pub async fn check(&self, conn: &Connection, dst_identity: Option<&Identity>) -> bool {
+        let key = CacheKey::from(conn);
+        if let Some(allowed) = self.cached(&key) {
+            debug!(allowed, "using cached ext_authz decision");
+            return allowed;
+        }
+
+        let req = CheckRequest {
+            attributes: Some(AttributeContext {
+                source: Some(peer(conn.src, conn.src_identity.as_ref())),
+                destination: Some(peer(conn.dst, dst_identity)),
+                context_extensions: HashMap::from([(
+                    "network".to_string(),
+                    conn.dst_network.to_string(),
+                )]),
+            }),
+        };
+        let resp = tokio::time::timeout(self.cfg.timeout, self.client.clone().check(req)).await;
+        let allowed = match resp {
+            Ok(Ok(resp)) => {
+                let status = resp.into_inner().status.unwrap_or_default();
+                // google.rpc.Code OK is 0; anything else is a denial.
+                let allowed = status.code == 0;
+                debug!(
+                    allowed,
+                    code = status.code,
+                    message = status.message,
+                    "ext_authz decision"
+                );
+                allowed
+            }
+            Ok(Err(e)) => {
+                warn!(
+                    fail_open = self.cfg.fail_open,
+                    "ext_authz check failed: {e}"
+                );
+                return self.cfg.fail_open;
+            }
+            Err(_) => {
+                warn!(
+                    fail_open = self.cfg.fail_open,
+                    "ext_authz check timed out after {:?}", self.cfg.timeout
+                );
+                return self.cfg.fail_open;
+            }
+        };
+        // Only definitive answers are cached; failures are retried on the next connection.
+        self.store(key, allowed);
+        allowed
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<bool> {
+        let cache = self.cache.lock().unwrap();
+        match cache.get_priority(key) {
+            Some(d) if d.expiry.0 > Instant::now() => Some(d.allowed),
+            _ => None,
+        }
+    }
+
+    fn store(&self, key: CacheKey, allowed: bool) {
+        if self.cfg.cache_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        while cache.peek().is_some_and(|(_, d)| d.expiry.0 <= now) {
+            cache.pop();
+        }
+        if cache.len() >= MAX_CACHE_ENTRIES && cache.get_priority(&key).is_none() {
+            // Every decision has the same TTL, so the one expiring soonest is the oldest.
+            cache.pop();
+        }
+        cache.push(
+            key,
+            Decision {
+                expiry: Reverse(now + self.cfg.cache_ttl),
+                allowed,
+            },
+        );
+    }
+}
+
+fn peer(addr: SocketAddr, identity: Option<&Identity>) -> Peer {
+    Peer {
+        address: Some(Address {
+            socket_address: Some(SocketAddress {
+                address: addr.ip().to_string(),
+                port_value: addr.port() as u32,
+            }),
+        }),
+        principal: identity.map(|i| i.to_string()).unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strng;
+    use crate::test_helpers::ext_authz::AuthzServer;
+    use crate::xds::service::auth::v3::{CheckResponse, Status};
+
+    fn conn() -> Connection {
+        Connection {
+            src_identity: Some(Identity::Spiffe {
+                trust_domain: "cluster.local".into(),
+                namespace: "default".into(),
+                service_account: "src".into(),
+            }),
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst_network: strng::new("nw"),
+            dst: "127.0.0.2:8080".parse().unwrap(),
+        }
+    }
+
+    fn response(code: i32) -> Result<CheckResponse, tonic::Status> {
+        Ok(CheckResponse {
+            status: Some(Status {
+                code,
+                message: String::new(),
+            }),
+        })
+    }
+
+    #[tokio::test]
+    async fn check_request() {
+        let (mut server, client) = AuthzServer::spawn(Duration::from_secs(1), false).await;
+        server.response.send(response(0)).unwrap();
+        let dst_identity = Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: "dst".into(),
+        };
+        assert!(client.check(&conn(), Some(&dst_identity)).await);
+
+        let attrs = server.requests.recv().await.unwrap().attributes.unwrap();
+        let src = attrs.source.unwrap();
+        assert_eq!(src.principal, "spiffe://cluster.local/ns/default/sa/src");
+        let src_addr = src.address.unwrap().socket_address.unwrap();
+        assert_eq!(src_addr.address, "127.0.0.1");
+        assert_eq!(src_addr.port_value, 12345);
+        let dst = attrs.destination.unwrap();
+        assert_eq!(dst.principal, "spiffe://cluster.local/ns/default/sa/dst");
+        let dst_addr = dst.address.unwrap().socket_address.unwrap();
+        assert_eq!(dst_addr.address, "127.0.0.2");
+        assert_eq!(dst_addr.port_value, 8080);
+        assert_eq!(attrs.context_extensions.get("network").unwrap(), "nw");
+    }
+
+    #[tokio::test]
+    async fn check_denied() {
+        // PERMISSION_DENIED
+        let (server, client) = AuthzServer::spawn(Duration::from_secs(1), false).await;
+        server.response.send(response(7)).unwrap();
+        assert!(!client.check(&conn(), None).await);
+    }
+
+    #[tokio::test]
+    async fn check_cached() {
+        let (mut server, client) = AuthzServer::spawn(Duration::from_secs(1), false).await;
+        server.response.send(response(0)).unwrap();
+        assert!(client.check(&conn(), None).await);
+        // The server changes its mind, but the cached decision is used; the source port is ignored.
+        server.response.send(response(7)).unwrap();
+        let other_port = Connection {
+            src: "127.0.0.1:23456".parse().unwrap(),
+            ..conn()
+        };
+        assert!(client.check(&other_port, None).await);
+        server.requests.recv().await.unwrap();
+        assert!(server.requests.try_recv().is_err());
+
+        // A different destination is not cached.
+        let other_dst = Connection {
+            dst: "127.0.0.2:9090".parse().unwrap(),
+            ..conn()
+        };
+        assert!(!client.check(&other_dst, None).await);
+    }
+
+    #[tokio::test]
+    async fn cache_evicts_oldest() {
+        let (_server, client) = AuthzServer::spawn(Duration::from_secs(1), false).await;
+        let key = |port: u16| CacheKey {
+            dst: SocketAddr::new("127.0.0.2".parse().unwrap(), port),
+            ..CacheKey::from(&conn())
+        };
+        client.store(key(0), true);
+        std::thread::sleep(Duration::from_millis(1));
+        for port in 1..MAX_CACHE_ENTRIES as u16 {
+            client.store(key(port), true);
+        }
+        client.store(key(u16::MAX), false);
+        assert_eq!(client.cache.lock().unwrap().len(), MAX_CACHE_ENTRIES);
+        // Only the oldest decision is dropped.
+        assert_eq!(client.cached(&key(0)), None);
+        assert_eq!(client.cached(&key(1)), Some(true));
+        assert_eq!(client.cached(&key(u16::MAX)), Some(false));
+    }
+
+    #[tokio::test]
+    async fn check_failure() {
+        let (server, client) = AuthzServer::spawn(Duration::from_secs(1), false).await;
+        server
+            .response
+            .send(Err(tonic::Status::unavailable("down")))
+            .unwrap();
+        assert!(!client.check(&conn(), None).await);
+
+        let (server, client) = AuthzServer::spawn(Duration::from_secs(1), true).await;
+        server
+            .response
+            .send(Err(tonic::Status::unavailable("down")))
+            .unwrap();
+        assert!(client.check(&conn(), None).await);
+        // Failures are not cached.
+        server.response.send(response(7)).unwrap();
+        assert!(!client.check(&conn(), None).await);
+    }
+
+    #[tokio::test]
+    async fn check_timeout() {
+        let (server, client) = AuthzServer::spawn(Duration::from_millis(10), false).await;
+        server.response.send(response(0)).unwrap();
+        server.delay.send(Duration::from_secs(1)).unwrap();
+        assert!(!client.check(&conn(), None).await);
+
+        let (server, client) = AuthzServer::spawn(Duration::from_millis(10), true).await;
+        server.response.send(response(7)).unwrap();
+        server.delay.send(Duration::from_secs(1)).unwrap();
+        assert!(client.check(&conn(), None).await);
+    }
+}
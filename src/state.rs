@@ -14,37 +14,43 @@
 
 use crate::identity::SecretManager;
 use crate::proxy;
+use crate::proxy::connection_manager::ConnectionManager;
 use crate::proxy::{Error, OnDemandDnsLabels};
-use crate::rbac::Authorization;
-use crate::state::policy::PolicyStore;
-use crate::state::service::{Endpoint, LoadBalancerMode, LoadBalancerScopes, ServiceStore};
+use crate::rbac::ext_authz::ExtAuthz;
+use crate::state::outlier::{OutlierDetectionConfig, OutlierDetector};
+use crate::state::policy::{Candidates, PolicyDump, PolicyStore};
+use crate::state::service::{
+    Endpoint, LoadBalancerMode, LoadBalancerScopes, LoadBalancerStrategy, ServiceStore,
+};
 use crate::state::service::{Service, ServiceDescription};
 use crate::state::workload::{
-    address::Address, gatewayaddress::Destination, network_addr, NamespacedHostname,
-    NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
+    address::Address, gatewayaddress::Destination, network_addr, GatewayAddress,
+    NamespacedHostname, NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
 };
 use crate::strng::Strng;
-use crate::tls;
 use crate::xds::istio::security::Authorization as XdsAuthorization;
 use crate::xds::istio::workload::Address as XdsAddress;
 use crate::xds::metrics::Metrics;
-use crate::xds::{AdsClient, Demander, LocalClient, ProxyStateUpdater};
+use crate::xds::{AdsClient, Demander, LocalClient, LocalConfigStatus, ProxyStateUpdater};
 use crate::{cert_fetcher, config, rbac, xds};
+use crate::{strng, tls};
 use futures_util::FutureExt;
 use hickory_resolver::config::*;
 use hickory_resolver::name_server::TokioConnectionProvider;
 use hickory_resolver::TokioAsyncResolver;
 use rand::prelude::IteratorRandom;
-use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::Serializer;
 use std::collections::{HashMap, HashSet};
 use std::convert::Into;
 use std::default::Default;
 use std::fmt;
 use std::net::{IpAddr, SocketAddr};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 use tracing::{debug, error, trace, warn};
 
+pub mod outlier;
 pub mod policy;
 pub mod service;
 pub mod workload;
@@ -137,6 +143,131 @@ impl fmt::Display for ProxyRbacContext {
         Ok(())
     }
 }
+
+/// The outcome of evaluating authorization policies against a connection.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RbacDecision {
+    pub allowed: bool,
+    /// The policies that matched the connection, other than dry-run ones.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched: Vec<Strng>,
+    /// The AUDIT policies that matched the connection.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub audited: Vec<rbac::AuditMatch>,
+    /// The decision if dry-run policies were enforced. Only set if any dry-run policy applies to the
+    /// connection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<DryRunDecision>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DryRunDecision {
+    pub allowed: bool,
+    /// The dry-run policies that matched the connection.
+    pub matched: Vec<Strng>,
+}
+
+/// Where a policy that applies to a workload was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicySource {
+    Namespace,
+    Global,
+    Workload,
+}
+
+/// Explains the authorization decision for a connection.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RbacExplanation {
+    pub destination_workload: Strng,
+    pub attributes: rbac::ConnectionAttributes,
+    /// Whether a CUSTOM policy matched, so the external authorization server would be consulted.
+    /// The decision is then the one made before asking it.
+    pub would_consult_ext_authz: bool,
+    #[serde(flatten)]
+    pub decision: RbacDecision,
+    /// Every policy considered, in evaluation order.
+    pub policies: Vec<PolicyEvaluation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyEvaluation {
+    pub source: PolicySource,
+    #[serde(flatten)]
+    pub explanation: rbac::PolicyExplanation,
+}
+
+/// The decision made by the authorization policies that apply to a connection, before CUSTOM
+/// policies are resolved.
+struct PolicyDecision {
+    decision: RbacDecision,
+    /// Whether an enforced CUSTOM policy matched.
+    custom: bool,
+    /// Whether a CUSTOM policy matched when dry-run policies are enforced as well.
+    dry_run_custom: bool,
+}
+
+impl PolicyDecision {
+    /// Whether the answer of the external authorization server can change the outcome.
+    fn needs_ext_authz(&self) -> bool {
+        let dry_run_allowed = self.decision.dry_run.as_ref().map(|d| d.allowed);
+        (self.custom && self.decision.allowed)
+            || (self.dry_run_custom && dry_run_allowed == Some(true))
+    }
+}
+
+/// The kinds of policies that matched a connection.
+#[derive(Debug, Default, Clone, Copy)]
+struct MatchedActions {
+    allow: bool,
+    deny: bool,
+    custom: bool,
+}
+
+impl MatchedActions {
+    fn add(&mut self, action: rbac::RbacAction) {
+        match action {
+            rbac::RbacAction::Allow => self.allow = true,
+            rbac::RbacAction::Deny => self.deny = true,
+            rbac::RbacAction::Custom => self.custom = true,
+            rbac::RbacAction::Audit => {}
+        }
+    }
+
+    fn or(self, other: MatchedActions) -> MatchedActions {
+        MatchedActions {
+            allow: self.allow || other.allow,
+            deny: self.deny || other.deny,
+            custom: self.custom || other.custom,
+        }
+    }
+}
+
+// Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
+// `has_allow` is whether any ALLOW policy applies to the workload at all, matching or not.
+fn rbac_allowed(matched: MatchedActions, has_allow: bool) -> bool {
+    // "If there are any DENY policies that match the request, deny the request."
+    if matched.deny {
+        debug!("deny policy match");
+        return false;
+    }
+    // "If there are no ALLOW policies for the workload, allow the request."
+    if !has_allow {
+        debug!("no allow policies, allow");
+        return true;
+    }
+    // "If any of the ALLOW policies match the request, allow the request."
+    if matched.allow {
+        debug!("allow policy match");
+        return true;
+    }
+    // "Deny the request."
+    debug!("no allow policies matched");
+    false
+}
 /// The current state information for this proxy.
 #[derive(Default, Debug)]
 pub struct ProxyState {
@@ -147,6 +278,81 @@ pub struct ProxyState {
     pub policies: PolicyStore,
 
     pub resolved_dns: ResolvedDnsStore,
+
+    pub load_balancing: LoadBalancing,
+
+    /// The status of the config loaded from LOCAL_XDS_PATH, if any.
+    pub local_config: Option<LocalConfigStatus>,
+}
+
+/// Load balancing configuration and state, shared by all connections.
+#[derive(Default, Debug)]
+pub struct LoadBalancing {
+    /// The strategy used when a service does not specify one.
+    pub default_strategy: LoadBalancerStrategy,
+    /// The next index to pick from, for round-robin. Keyed by service or workload.
+    round_robin: Mutex<HashMap<Strng, usize>>,
+    /// Tracks failing endpoints, so they are not picked.
+    pub outlier_detector: OutlierDetector,
+}
+
+impl LoadBalancing {
+    pub fn new(
+        default_strategy: LoadBalancerStrategy,
+        outlier_detection: OutlierDetectionConfig,
+    ) -> Self {
+        Self {
+            default_strategy,
+            outlier_detector: OutlierDetector::new(outlier_detection),
+            ..Default::default()
+        }
+    }
+
+    /// Forgets the round-robin position of a removed service or workload. `key` is the
+    /// `<namespace>/<hostname>` of a service, or the UID of a workload.
+    pub fn remove(&self, key: &Strng) {
+        self.round_robin.lock().unwrap().remove(key);
+    }
+
+    /// Picks one of the candidates using the given strategy. `key` identifies the set of candidates
+    /// for round-robin, and `load` reports the outstanding connections for a candidate.
+    fn pick<T>(
+        &self,
+        strategy: LoadBalancerStrategy,
+        key: &Strng,
+        mut candidates: Vec<T>,
+        load: impl Fn(&T) -> usize,
+    ) -> Option<T> {
+        let mut rng = rand::thread_rng();
+        let idx = match (strategy, candidates.len()) {
+            (_, 0) => return None,
+            (_, 1) => 0,
+            (LoadBalancerStrategy::Random, n) => rng.gen_range(0..n),
+            (LoadBalancerStrategy::RoundRobin, n) => {
+                let mut round_robin = self.round_robin.lock().unwrap();
+                let next = round_robin.entry(key.clone()).or_default();
+                let idx = *next % n;
+                *next = idx + 1;
+                idx
+            }
+            (LoadBalancerStrategy::LeastRequest, n) => {
+                let loads = candidates.iter().map(&load).collect::<Vec<_>>();
+                let min = *loads.iter().min()?;
+                // Break ties randomly, so we do not pile onto the first candidate.
+                (0..n).filter(|i| loads[*i] == min).choose(&mut rng)?
+            }
+            (LoadBalancerStrategy::PowerOfTwoChoices, n) => {
+                let picks = rand::seq::index::sample(&mut rng, n, 2);
+                let (a, b) = (picks.index(0), picks.index(1));
+                if load(&candidates[b]) < load(&candidates[a]) {
+                    b
+                } else {
+                    a
+                }
+            }
+        };
+        Some(candidates.swap_remove(idx))
+    }
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -155,7 +361,10 @@ struct ProxyStateSerialization<'a> {
     workloads: &'a HashMap<NetworkAddress, Arc<Workload>>,
     services: &'a HashMap<NetworkAddress, Arc<Service>>,
     staged_services: &'a HashMap<NamespacedHostname, HashMap<Strng, Endpoint>>,
-    policies: &'a HashMap<Strng, Authorization>,
+    policies: HashMap<&'a Strng, PolicyDump<'a>>,
+    outlier_detection: &'a OutlierDetector,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_config: Option<&'a LocalConfigStatus>,
 }
 
 impl serde::Serialize for ProxyState {
@@ -167,7 +376,9 @@ impl serde::Serialize for ProxyState {
             workloads: &self.workloads.by_addr,
             services: &self.services.by_vip,
             staged_services: &self.services.staged_services,
-            policies: &self.policies.by_key,
+            policies: self.policies.dump(),
+            outlier_detection: &self.load_balancing.outlier_detector,
+            local_config: self.local_config.as_ref(),
         };
         serializable.serialize(serializer)
     }
@@ -196,6 +407,151 @@ pub struct ResolvedDns {
 }
 
 impl ProxyState {
+    /// Returns the keys of the authorization policies that apply to the workload, and where they
+    /// were found.
+    pub fn applicable_policies(&self, wl: &Workload) -> Vec<(PolicySource, Strng)> {
+        // We can get policies from namespace, global, and workload...
+        let ns = self.policies.get_by_namespace(&wl.namespace);
+        let global = self.policies.get_by_namespace(&crate::strng::EMPTY);
+        let workload = wl.authorization_policies.iter().cloned();
+        ns.into_iter()
+            .map(|k| (PolicySource::Namespace, k))
+            .chain(global.into_iter().map(|k| (PolicySource::Global, k)))
+            .chain(workload.map(|k| (PolicySource::Workload, k)))
+            .collect()
+    }
+
+    /// Resolves the attributes of the source workload and destination service of the connection
+    /// that authorization policies can match on. The source workload is only looked up in the
+    /// current state; it is never fetched on demand.
+    pub fn connection_attributes(
+        &self,
+        conn: &rbac::Connection,
+        dst: &Workload,
+        services: Vec<Arc<Service>>,
+        for_host: Option<&str>,
+    ) -> rbac::ConnectionAttributes {
+        // Like inbound, we assume the source is on our network.
+        let src_addr = network_addr(conn.dst_network.clone(), conn.src.ip());
+        let src = self.workloads.find_address(&src_addr).filter(|wl| {
+            // The workload is found by IP, so do not trust it if it contradicts the peer's identity.
+            match &conn.src_identity {
+                Some(id) => *id == wl.identity(),
+                None => true,
+            }
+        });
+        let for_host = for_host.map(str::to_string);
+        let dst_service = proxy::guess_inbound_service(conn, &for_host, services, dst)
+            .map(|s| s.hostname)
+            .unwrap_or_default();
+        match src {
+            Some(src) => rbac::ConnectionAttributes {
+                src_service_account: src.service_account,
+                src_canonical_name: src.canonical_name,
+                src_canonical_revision: src.canonical_revision,
+                src_cluster_id: src.cluster_id,
+                dst_service,
+            },
+            None => rbac::ConnectionAttributes {
+                dst_service,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Evaluates the authorization policies that apply to the connection to `wl`, without
+    /// consulting the external authorization server for CUSTOM policies.
+    fn evaluate_policies(
+        &self,
+        conn: &rbac::Connection,
+        wl: &Workload,
+        services: Vec<Arc<Service>>,
+        for_host: Option<&str>,
+    ) -> PolicyDecision {
+        // We can get policies from namespace, global, and workload. Namespace and global
+        // policies are indexed, so only the ones that may match are returned.
+        let mut candidates = Candidates::default();
+        self.policies
+            .candidates(&wl.namespace, conn, &mut candidates);
+        self.policies
+            .candidates(&crate::strng::EMPTY, conn, &mut candidates);
+        for k in wl.authorization_policies.iter() {
+            let Some(pol) = self.policies.get(k) else {
+                // Policy not found. This is probably transition state where the policy hasn't been sent
+                // by the control plane, or it was just removed.
+                warn!("skipping unknown policy {k}");
+                continue;
+            };
+            candidates.add(pol);
+        }
+        // Workload and service attributes are only resolved if a policy may match on them.
+        let attrs = if candidates
+            .policies
+            .iter()
+            .any(|pol| pol.uses_connection_attributes())
+        {
+            self.connection_attributes(conn, wl, services, for_host)
+        } else {
+            rbac::ConnectionAttributes::default()
+        };
+
+        // Evaluate each policy once; which kinds of policies matched decides the outcome.
+        // Dry-run policies are kept apart, so they do not affect the enforced decision.
+        let (mut matched, mut audited, mut dry_run_matched) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut enforced, mut dry_run_only) =
+            (MatchedActions::default(), MatchedActions::default());
+        for pol in candidates.policies {
+            let Some(rule) = pol.matched_rule(conn, &attrs) else {
+                continue;
+            };
+            let key = pol.to_key();
+            debug!(
+                policy = key.as_str(),
+                rule,
+                dry_run = pol.dry_run,
+                "policy match"
+            );
+            if pol.action == rbac::RbacAction::Audit {
+                // AUDIT policies are reported regardless of the outcome, and never change it.
+                // They are never enforced, so dry-run makes no difference.
+                audited.push(rbac::AuditMatch {
+                    policy: key.clone(),
+                    rule,
+                });
+                matched.push(key);
+            } else if pol.dry_run {
+                dry_run_only.add(pol.action);
+                dry_run_matched.push(key);
+            } else {
+                enforced.add(pol.action);
+                matched.push(key);
+            }
+        }
+
+        let allowed = rbac_allowed(enforced, candidates.has_allow);
+        // Evaluate the decision as if the dry-run policies were enforced as well.
+        let dry_run_actions = enforced.or(dry_run_only);
+        let dry_run = candidates.has_dry_run.then(|| DryRunDecision {
+            allowed: rbac_allowed(
+                dry_run_actions,
+                candidates.has_allow || candidates.has_dry_run_allow,
+            ),
+            matched: dry_run_matched,
+        });
+        // CUSTOM policies are left to the caller to resolve with the external authorization
+        // server; here we only find out whether it needs to be asked.
+        PolicyDecision {
+            decision: RbacDecision {
+                allowed,
+                matched,
+                audited,
+                dry_run,
+            },
+            custom: enforced.custom,
+            dry_run_custom: candidates.has_dry_run && dry_run_actions.custom,
+        }
+    }
+
     /// Find either a workload or service by the destination.
     pub fn find_destination(&self, dest: &Destination) -> Option<Address> {
         match dest {
@@ -234,43 +590,20 @@ impl ProxyState {
         }
     }
 
+    /// Find the upstream for the given address. If the address is a service, an endpoint is picked
+    /// from it using `opts`.
     pub fn find_upstream(
         &self,
         network: Strng,
         source_workload: &Workload,
         addr: SocketAddr,
+        opts: PickOptions,
     ) -> Option<Upstream> {
         if let Some(svc) = self
             .services
             .get_by_vip(&network_addr(network.clone(), addr.ip()))
         {
-            let Some(target_port) = svc.ports.get(&addr.port()) else {
-                debug!(
-                    "found VIP {}, but port {} was unknown",
-                    addr.ip(),
-                    addr.port()
-                );
-                return None;
-            };
-            // Randomly pick an upstream
-            // TODO: do this more efficiently, and not just randomly
-            let Some(ep) = self.load_balance(source_workload, &svc) else {
-                debug!("VIP {} has no healthy endpoints", addr);
-                return None;
-            };
-            let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
-                debug!("failed to fetch workload for {}", ep.workload_uid);
-                return None;
-            };
-            // If endpoint overrides the target port, use that instead
-            let target_port = ep.port.get(&addr.port()).unwrap_or(target_port);
-            let us = Upstream {
-                workload: wl,
-                port: *target_port,
-                sans: svc.subject_alt_names.clone(),
-                destination_service: Some(ServiceDescription::from(svc.as_ref())),
-            };
-            return Some(us);
+            return self.find_upstream_from_service(source_workload, addr.port(), svc, opts);
         }
         if let Some(wl) = self
             .workloads
@@ -287,14 +620,68 @@ impl ProxyState {
         None
     }
 
-    fn load_balance<'a>(&self, src: &Workload, svc: &'a Service) -> Option<&'a Endpoint> {
-        match svc.load_balancer {
-            None => svc.endpoints.values().choose(&mut rand::thread_rng()),
+    /// Find an upstream for the given service and service port, picking an endpoint to send to.
+    pub fn find_upstream_from_service(
+        &self,
+        source_workload: &Workload,
+        svc_port: u16,
+        svc: Arc<Service>,
+        opts: PickOptions,
+    ) -> Option<Upstream> {
+        let Some(target_port) = svc.ports.get(&svc_port) else {
+            debug!(
+                "found service {}, but port {} was unknown",
+                svc.hostname, svc_port
+            );
+            return None;
+        };
+        let Some(ep) = self.load_balance(source_workload, &svc, opts) else {
+            debug!("service {} has no healthy endpoints", svc.hostname);
+            return None;
+        };
+        let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
+            debug!("failed to fetch workload for {}", ep.workload_uid);
+            return None;
+        };
+        // If endpoint overrides the target port, use that instead
+        let target_port = ep.port.get(&svc_port).unwrap_or(target_port);
+        Some(Upstream {
+            workload: wl,
+            port: *target_port,
+            sans: svc.subject_alt_names.clone(),
+            destination_service: Some(ServiceDescription::from(svc.as_ref())),
+        })
+    }
+
+    fn load_balance<'a>(
+        &self,
+        src: &Workload,
+        svc: &'a Service,
+        opts: PickOptions,
+    ) -> Option<&'a Endpoint> {
+        let strategy = svc
+            .load_balancer
+            .as_ref()
+            .and_then(|lb| lb.strategy)
+            .unwrap_or(self.load_balancing.default_strategy);
+        let ejected = self
+            .load_balancing
+            .outlier_detector
+            .ejected(svc.endpoints.values().map(|ep| &ep.workload_uid));
+        let endpoints = svc
+            .endpoints
+            .values()
+            .filter(|ep| !ejected.contains(&ep.workload_uid))
+            .filter(|ep| {
+                !ep.address
+                    .as_ref()
+                    .is_some_and(|addr| opts.excluded.contains(&addr.address))
+            });
+        let mut candidates = match svc.load_balancer {
+            None => endpoints.collect::<Vec<_>>(),
             Some(ref lb) => {
-                let ranks = svc
-                    .endpoints
-                    .iter()
-                    .filter_map(|(_, ep)| {
+                let ranks = endpoints
+                    .filter_map(|ep| {
                         let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
                             debug!("failed to fetch workload for {}", ep.workload_uid);
                             return None;
@@ -338,9 +725,62 @@ impl ProxyState {
                     .into_iter()
                     .filter(|(rank, _ep)| *rank == max)
                     .map(|(_, ep)| ep)
-                    .choose(&mut rand::thread_rng())
+                    .collect()
+            }
+        };
+        if strategy == LoadBalancerStrategy::RoundRobin {
+            // Endpoints are stored in a map; give them a stable order so we actually take turns.
+            candidates.sort_by(|a, b| a.workload_uid.cmp(&b.workload_uid));
+        }
+        let key = strng::format!("{}/{}", svc.namespace, svc.hostname);
+        self.load_balancing
+            .pick(strategy, &key, candidates, |ep: &&Endpoint| {
+                outstanding(opts.conns, ep.address.as_ref().map(|a| a.address))
+            })
+    }
+
+    /// Picks one of the given IPs of a workload, using the default load balancing strategy.
+    fn load_balance_ips(
+        &self,
+        workload: &Workload,
+        ips: Vec<IpAddr>,
+        opts: PickOptions,
+    ) -> Option<IpAddr> {
+        let strategy = self.load_balancing.default_strategy;
+        let mut ips = ips;
+        ips.retain(|ip| !opts.excluded.contains(ip));
+        if let Some(source) = opts.source {
+            // Prefer the address family the source connected with, in case the other is not routable.
+            let same_family = |ip: &IpAddr| ip.is_ipv4() == source.to_canonical().is_ipv4();
+            if ips.iter().any(same_family) {
+                ips.retain(same_family);
             }
         }
+        if strategy == LoadBalancerStrategy::RoundRobin {
+            ips.sort();
+        }
+        self.load_balancing
+            .pick(strategy, &workload.uid, ips, |ip| {
+                outstanding(opts.conns, Some(*ip))
+            })
+    }
+}
+
+/// Per-connection inputs used when picking an endpoint or IP to connect to.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PickOptions<'a> {
+    /// If set, used to find the outstanding connections to each endpoint.
+    pub conns: Option<&'a ConnectionManager>,
+    /// Addresses that must not be picked, such as ones that already failed to connect.
+    pub excluded: &'a [IpAddr],
+    /// The address of the source. If set, addresses of the same family are preferred.
+    pub source: Option<IpAddr>,
+}
+
+fn outstanding(conns: Option<&ConnectionManager>, ip: Option<IpAddr>) -> usize {
+    match (conns, ip) {
+        (Some(conns), Some(ip)) => conns.outbound_count(ip),
+        _ => 0,
     }
 }
 
@@ -360,6 +800,18 @@ pub struct DemandProxyState {
 
     #[serde(skip_serializing)]
     dns_resolver_opts: ResolverOpts,
+
+    /// If present, used to find outstanding connections for load balancing.
+    #[serde(skip_serializing)]
+    connection_manager: Option<ConnectionManager>,
+
+    /// Addresses that will not be picked when load balancing.
+    #[serde(skip_serializing)]
+    excluded: Vec<IpAddr>,
+
+    /// If present, used to resolve CUSTOM authorization policies.
+    #[serde(skip_serializing)]
+    ext_authz: Option<ExtAuthz>,
 }
 
 impl DemandProxyState {
@@ -374,6 +826,41 @@ impl DemandProxyState {
             demand,
             dns_resolver_cfg,
             dns_resolver_opts,
+            connection_manager: None,
+            excluded: Vec::new(),
+            ext_authz: None,
+        }
+    }
+
+    /// Returns a copy of this state that resolves CUSTOM authorization policies using `ext_authz`.
+    pub fn with_ext_authz(self, ext_authz: ExtAuthz) -> Self {
+        Self {
+            ext_authz: Some(ext_authz),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this state that load balances using the connections tracked by `cm`.
+    pub fn with_connection_manager(self, cm: ConnectionManager) -> Self {
+        Self {
+            connection_manager: Some(cm),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this state that never picks the given addresses when load balancing.
+    pub fn excluding(&self, ips: &[IpAddr]) -> Self {
+        Self {
+            excluded: ips.to_vec(),
+            ..self.clone()
+        }
+    }
+
+    fn pick_options(&self) -> PickOptions<'_> {
+        PickOptions {
+            conns: self.connection_manager.as_ref(),
+            source: None,
+            excluded: &self.excluded,
         }
     }
 
@@ -382,79 +869,115 @@ impl DemandProxyState {
     }
 
     pub async fn assert_rbac(&self, ctx: &ProxyRbacContext) -> bool {
+        self.evaluate_rbac(ctx, None).await.allowed
+    }
+
+    /// Evaluates the authorization policies that apply to the connection.
+    /// `for_host` is the destination service the client asked for, if any.
+    pub async fn evaluate_rbac(
+        &self,
+        ctx: &ProxyRbacContext,
+        for_host: Option<&str>,
+    ) -> RbacDecision {
         let nw_addr = network_addr(ctx.conn.dst_network.clone(), ctx.conn.dst.ip());
-        let Some(wl) = self.fetch_workload(&nw_addr).await else {
+        let Some((wl, services)) = self.fetch_workload_services(&nw_addr).await else {
             debug!("destination workload not found {}", nw_addr);
-            return false;
+            return RbacDecision::default();
         };
         if let Some(ref wl_info) = ctx.dest_workload_info {
             // make sure that the workload we fetched matches the workload info we got over ZDS.
             if !wl_info.matches(&wl) {
                 error!("workload does not match proxy workload uid. this is probably a bug. please report an issue");
-                return false;
+                return RbacDecision::default();
             }
         }
         let conn = &ctx.conn;
-        let state = self.state.read().unwrap();
-
-        // We can get policies from namespace, global, and workload...
-        let ns = state.policies.get_by_namespace(&wl.namespace);
-        let global = state.policies.get_by_namespace(&crate::strng::EMPTY);
-        let workload = wl.authorization_policies.iter();
-
-        // Aggregate all of them based on type
-        let (allow, deny): (Vec<_>, Vec<_>) = ns
-            .iter()
-            .chain(global.iter())
-            .chain(workload)
-            .filter_map(|k| {
-                let pol = state.policies.get(k);
-                // Policy not found. This is probably transition state where the policy hasn't been sent
-                // by the control plane, or it was just removed.
-                if pol.is_none() {
-                    warn!("skipping unknown policy {k}");
+        let local = self.read().evaluate_policies(conn, &wl, services, for_host);
+        let needs_ext_authz = local.needs_ext_authz();
+        let PolicyDecision {
+            mut decision,
+            custom,
+            dry_run_custom,
+        } = local;
+
+        // The external authorization server is only asked if its answer can change the outcome.
+        if needs_ext_authz {
+            let ext_allowed = self.check_ext_authz(conn, &wl).await;
+            if custom {
+                decision.allowed &= ext_allowed;
+            }
+            if let Some(dry_run) = decision.dry_run.as_mut() {
+                if dry_run_custom {
+                    dry_run.allowed &= ext_allowed;
                 }
-                pol
-            })
-            .partition(|p| p.action == rbac::RbacAction::Allow);
-
-        trace!(
-            allow = allow.len(),
-            deny = deny.len(),
-            "checking connection"
-        );
-
-        // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
-
-        // "If there are any DENY policies that match the request, deny the request."
-        for pol in deny.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key().as_str(), "deny policy match");
-                return false;
-            } else {
-                trace!(policy = pol.to_key().as_str(), "deny policy does not match");
             }
         }
-        // "If there are no ALLOW policies for the workload, allow the request."
-        if allow.is_empty() {
-            debug!("no allow policies, allow");
-            return true;
-        }
-        // "If any of the ALLOW policies match the request, allow the request."
-        for pol in allow.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key().as_str(), "allow policy match");
-                return true;
-            } else {
-                trace!(
-                    policy = pol.to_key().as_str(),
-                    "allow policy does not match"
+        if let Some(dry_run) = &decision.dry_run {
+            if dry_run.allowed != decision.allowed {
+                debug!(
+                    allowed = decision.allowed,
+                    dry_run_allowed = dry_run.allowed,
+                    "dry-run policies change the decision"
                 );
             }
         }
-        // "Deny the request."
-        debug!("no allow policies matched");
-        false
+        decision
+    }
+
+    /// Records which policies matched a connection, and the decision made for it. Dry-run policies
+    /// are recorded with the decision they would have made.
+    pub fn record_policy_matches(&self, decision: &RbacDecision) {
+        let state = self.state.read().unwrap();
+        state
+            .policies
+            .record_matches(&decision.matched, decision.allowed);
+        if let Some(dry_run) = &decision.dry_run {
+            state
+                .policies
+                .record_matches(&dry_run.matched, dry_run.allowed);
+        }
+    }
+
+    /// Evaluates the authorization policies that apply to the connection like `evaluate_rbac`, and
+    /// explains how each policy was evaluated. Returns None if the destination workload is unknown.
+    /// This has no side effects: workloads are not fetched on demand, and the external
+    /// authorization server is not consulted for CUSTOM policies.
+    pub fn explain_rbac(&self, ctx: &ProxyRbacContext) -> Option<RbacExplanation> {
+        let conn = &ctx.conn;
+        let nw_addr = network_addr(conn.dst_network.clone(), conn.dst.ip());
+        let state = self.state.read().unwrap();
+        let wl = state.workloads.find_address(&nw_addr)?;
+        let services = state.services.get_by_workload(&wl);
+        let local = state.evaluate_policies(conn, &wl, services.clone(), None);
+        let attrs = state.connection_attributes(conn, &wl, services, None);
+        let policies = state
+            .applicable_policies(&wl)
+            .into_iter()
+            .filter_map(|(source, k)| {
+                let pol = state.policies.get(&k)?;
+                Some(PolicyEvaluation {
+                    source,
+                    explanation: pol.explain(conn, &attrs),
+                })
+            })
+            .collect();
+        Some(RbacExplanation {
+            destination_workload: wl.uid.clone(),
+            attributes: attrs,
+            would_consult_ext_authz: local.needs_ext_authz(),
+            decision: local.decision,
+            policies,
+        })
+    }
+
+    async fn check_ext_authz(&self, conn: &rbac::Connection, wl: &Workload) -> bool {
+        let Some(ext_authz) = &self.ext_authz else {
+            warn!(
+                "CUSTOM policy matched, but no external authorization server is configured, deny"
+            );
+            return false;
+        };
+        ext_authz.check(conn, Some(&wl.identity())).await
     }
 
     // this should only be called once per request (for the workload itself and potentially its waypoint)
@@ -462,14 +985,18 @@ impl DemandProxyState {
         &self,
         dst_workload: &Workload,
         src_workload: &Workload,
+        src_addr: IpAddr,
         metrics: Arc<proxy::Metrics>,
     ) -> Result<IpAddr, Error> {
-        // TODO: add more sophisticated routing logic, perhaps based on ipv4/ipv6 support underneath us.
-        // if/when we support that, this function may need to move to get access to the necessary metadata.
-        // Randomly pick an IP
-        // TODO: do this more efficiently, and not just randomly
-        if let Some(ip) = dst_workload.workload_ips.choose(&mut rand::thread_rng()) {
-            return Ok(*ip);
+        let opts = PickOptions {
+            source: Some(src_addr),
+            ..self.pick_options()
+        };
+        if let Some(ip) =
+            self.read()
+                .load_balance_ips(dst_workload, dst_workload.workload_ips.clone(), opts)
+        {
+            return Ok(ip);
         }
         if dst_workload.hostname.is_empty() {
             debug!(
@@ -479,7 +1006,8 @@ impl DemandProxyState {
             return Err(Error::NoValidDestination(Box::new(dst_workload.clone())));
         }
         let ip =
-            Box::pin(self.load_balance_for_hostname(dst_workload, src_workload, metrics)).await?;
+            Box::pin(self.load_balance_for_hostname(dst_workload, src_workload, opts, metrics))
+                .await?;
         Ok(ip)
     }
 
@@ -487,6 +1015,7 @@ impl DemandProxyState {
         &self,
         workload: &Workload,
         src_workload: &Workload,
+        opts: PickOptions<'_>,
         metrics: Arc<proxy::Metrics>,
     ) -> Result<IpAddr, Error> {
         let labels = OnDemandDnsLabels::new()
@@ -518,14 +1047,27 @@ impl DemandProxyState {
             }
         };
 
-        // TODO: add more sophisticated routing logic, perhaps based on ipv4/ipv6 support underneath us.
-        // if/when we support that, this function may need to move to get access to the necessary metadata.
-        // Randomly pick an IP
-        // TODO: do this more efficiently, and not just randomly
-        let Some(ip) = rdns.ips.iter().choose(&mut rand::thread_rng()) else {
+        let ips = rdns.ips.iter().copied().collect();
+        let Some(ip) = self.read().load_balance_ips(workload, ips, opts) else {
             return Err(Error::EmptyResolvedAddresses(workload_uid.to_string()));
         };
-        Ok(*ip)
+        Ok(ip)
+    }
+
+    /// Resolves the hostname with the system resolver, returning any A/AAAA records found.
+    pub async fn resolve_hostname(&self, hostname: &str) -> Vec<IpAddr> {
+        let resolver = TokioAsyncResolver::new(
+            self.dns_resolver_cfg.to_owned(),
+            self.dns_resolver_opts.clone(),
+            TokioConnectionProvider::default(),
+        );
+        match resolver.lookup_ip(hostname).await {
+            Ok(resp) => resp.iter().collect(),
+            Err(e) => {
+                debug!(%hostname, "system dns resolution failed: {:?}", e);
+                Vec::new()
+            }
+        }
     }
 
     async fn resolve_on_demand_dns(state: &DemandProxyState, workload: &Workload) {
@@ -670,10 +1212,12 @@ impl DemandProxyState {
     ) -> Option<Upstream> {
         self.fetch_address(&network_addr(network.clone(), addr.ip()))
             .await;
-        self.state
-            .read()
-            .unwrap()
-            .find_upstream(network, source_workload, addr)
+        self.state.read().unwrap().find_upstream(
+            network,
+            source_workload,
+            addr,
+            self.pick_options(),
+        )
     }
 
     pub async fn fetch_waypoint(
@@ -687,17 +1231,8 @@ impl DemandProxyState {
         };
         // Even in this case, we are picking a single upstream pod and deciding if it has a remote proxy.
         // Typically this is all or nothing, but if not we should probably send to remote proxy if *any* upstream has one.
-        let wp_nw_addr = match &gw_address.destination {
-            Destination::Address(ip) => ip,
-            Destination::Hostname(_) => {
-                return Err(WaypointError::UnsupportedFeature(
-                    "hostname lookup not supported yet".to_string(),
-                ));
-            }
-        };
-        let wp_socket_addr = SocketAddr::new(wp_nw_addr.address, gw_address.hbone_mtls_port);
         match self
-            .fetch_upstream(wp_nw_addr.network.clone(), source_workload, wp_socket_addr)
+            .fetch_gateway_upstream(gw_address, source_workload)
             .await
         {
             Some(mut upstream) => {
@@ -717,6 +1252,61 @@ impl DemandProxyState {
         }
     }
 
+    /// Finds the upstream for a gateway (such as a waypoint), targeting its HBONE mTLS port.
+    /// Address based gateways are looked up by IP; hostname based gateways are resolved to a
+    /// service or workload, fetching on-demand if needed and falling back to DNS if the hostname
+    /// is not known to the mesh.
+    pub async fn fetch_gateway_upstream(
+        &self,
+        gw_address: &GatewayAddress,
+        source_workload: &Workload,
+    ) -> Option<Upstream> {
+        let hbone_port = gw_address.hbone_mtls_port;
+        let hostname = match &gw_address.destination {
+            Destination::Address(addr) => {
+                let gw_socket_addr = SocketAddr::new(addr.address, hbone_port);
+                return self
+                    .fetch_upstream(addr.network.clone(), source_workload, gw_socket_addr)
+                    .await;
+            }
+            Destination::Hostname(hostname) => hostname,
+        };
+        match self.fetch_hostname(hostname).await {
+            Some(Address::Service(svc)) => {
+                let state = self.read();
+                state.find_upstream_from_service(
+                    source_workload,
+                    hbone_port,
+                    svc,
+                    self.pick_options(),
+                )
+            }
+            Some(Address::Workload(wl)) => Some(Upstream {
+                workload: wl.deref().clone(),
+                port: hbone_port,
+                sans: Vec::new(),
+                destination_service: None,
+            }),
+            None => {
+                debug!(%hostname, "gateway hostname not found, falling back to DNS");
+                for ip in self.resolve_hostname(&hostname.hostname).await {
+                    let gw_socket_addr = SocketAddr::new(ip, hbone_port);
+                    if let Some(us) = self
+                        .fetch_upstream(
+                            source_workload.network.clone(),
+                            source_workload,
+                            gw_socket_addr,
+                        )
+                        .await
+                    {
+                        return Some(us);
+                    }
+                }
+                None
+            }
+        }
+    }
+
     /// Looks for either a workload or service by the destination. If not found locally,
     /// attempts to fetch on-demand.
     pub async fn fetch_destination(&self, dest: &Destination) -> Option<Address> {
@@ -814,7 +1404,13 @@ impl ProxyStateManager {
         cert_manager: Arc<SecretManager>,
     ) -> anyhow::Result<ProxyStateManager> {
         let cert_fetcher = cert_fetcher::new(&config, cert_manager);
-        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState::default()));
+        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState {
+            load_balancing: LoadBalancing::new(
+                config.load_balancer_strategy,
+                config.outlier_detection.clone(),
+            ),
+            ..Default::default()
+        }));
         let xds_client = if config.xds_address.is_some() {
             let updater = ProxyStateUpdater::new(state.clone(), cert_fetcher.clone());
             let tls_client_fetcher = Box::new(tls::ControlPlaneAuthentication::RootCert(
@@ -824,7 +1420,7 @@ impl ProxyStateManager {
                 xds::Config::new(config.clone(), tls_client_fetcher)
                     .with_watched_handler::<XdsAddress>(xds::ADDRESS_TYPE, updater.clone())
                     .with_watched_handler::<XdsAuthorization>(xds::AUTHORIZATION_TYPE, updater)
-                    .build(metrics, awaiting_ready),
+                    .build(metrics.clone(), awaiting_ready),
             )
         } else {
             None
@@ -834,19 +1430,21 @@ impl ProxyStateManager {
                 cfg: cfg.clone(),
                 state: state.clone(),
                 cert_fetcher,
+                metrics,
             };
             local_client.run().await?;
         }
         let demand = xds_client.as_ref().and_then(AdsClient::demander);
-        Ok(ProxyStateManager {
-            xds_client,
-            state: DemandProxyState {
-                state,
-                demand,
-                dns_resolver_cfg: config.dns_resolver_cfg.clone(),
-                dns_resolver_opts: config.dns_resolver_opts.clone(),
-            },
-        })
+        let mut state = DemandProxyState::new(
+            state,
+            demand,
+            config.dns_resolver_cfg.clone(),
+            config.dns_resolver_opts.clone(),
+        );
+        if let Some(cfg) = &config.ext_authz {
+            state = state.with_ext_authz(ExtAuthz::new(cfg.clone()).await?);
+        }
+        Ok(ProxyStateManager { xds_client, state })
     }
 
     pub fn state(&self) -> DemandProxyState {
@@ -1010,6 +1608,381 @@ mod tests {
         }
     }
 
+    fn port_policy(
+        name: &str,
+        action: rbac::RbacAction,
+        dry_run: bool,
+        ports: Vec<Vec<u16>>,
+    ) -> rbac::Authorization {
+        rbac::Authorization {
+            name: name.into(),
+            namespace: "default".into(),
+            scope: rbac::RbacScope::Global,
+            action,
+            rules: ports
+                .into_iter()
+                .map(|destination_ports| {
+                    vec![vec![rbac::RbacMatch {
+                        destination_ports,
+                        ..Default::default()
+                    }]]
+                })
+                .collect(),
+            dry_run,
+        }
+    }
+
+    async fn evaluate_rbac_to_port(
+        policies: Vec<rbac::Authorization>,
+        ext_authz: Option<ExtAuthz>,
+        ports: &[u16],
+    ) -> Vec<RbacDecision> {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        for pol in policies {
+            state.policies.insert(pol);
+        }
+        let mut state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        if let Some(ext_authz) = ext_authz {
+            state = state.with_ext_authz(ext_authz);
+        }
+        let mut decisions = Vec::new();
+        for port in ports {
+            let ctx = ProxyRbacContext {
+                conn: rbac::Connection {
+                    src_identity: None,
+                    src: "192.168.0.1:1234".parse().unwrap(),
+                    dst_network: "".into(),
+                    dst: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), *port),
+                },
+                dest_workload_info: None,
+            };
+            decisions.push(state.evaluate_rbac(&ctx, None).await);
+        }
+        decisions
+    }
+
+    #[tokio::test]
+    async fn evaluate_rbac_source_workload() {
+        let mut state = ProxyState::default();
+        let src = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1))],
+            uid: "src".into(),
+            name: "src".into(),
+            service_account: "src-sa".into(),
+            canonical_name: "src-app".into(),
+            ..test_helpers::test_default_workload()
+        };
+        let dst = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(src.clone()), true);
+        state.workloads.insert(Arc::new(dst), true);
+        state.policies.insert(rbac::Authorization {
+            name: "allow".into(),
+            namespace: "default".into(),
+            scope: rbac::RbacScope::Global,
+            action: rbac::RbacAction::Allow,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                canonical_names: vec![rbac::StringMatch::Exact("src-app".into())],
+                ..Default::default()
+            }]]],
+            dry_run: false,
+        });
+        let state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        let ctx = |src_identity| ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".into(),
+                dst: "192.168.0.2:8080".parse().unwrap(),
+            },
+            dest_workload_info: None,
+        };
+
+        // The source workload is found by IP.
+        assert!(state.assert_rbac(&ctx(None)).await);
+        assert!(state.assert_rbac(&ctx(Some(src.identity()))).await);
+        // A peer authenticated as someone else does not get the attributes of the workload at its IP.
+        let other = crate::identity::Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "default".into(),
+            service_account: "other".into(),
+        };
+        assert!(!state.assert_rbac(&ctx(Some(other))).await);
+    }
+
+    #[tokio::test]
+    async fn evaluate_rbac_audit() {
+        let policies = vec![
+            port_policy(
+                "audit",
+                rbac::RbacAction::Audit,
+                false,
+                vec![vec![1], vec![8080, 9090]],
+            ),
+            port_policy("deny", rbac::RbacAction::Deny, false, vec![vec![9090]]),
+        ];
+        let audited = vec![rbac::AuditMatch {
+            policy: "default/audit".into(),
+            rule: 1,
+        }];
+
+        // AUDIT policies never change the outcome, but are always reported.
+        assert_eq!(
+            evaluate_rbac_to_port(policies, None, &[8080, 9090, 7070]).await,
+            vec![
+                RbacDecision {
+                    allowed: true,
+                    matched: vec!["default/audit".into()],
+                    audited: audited.clone(),
+                    dry_run: None,
+                },
+                RbacDecision {
+                    allowed: false,
+                    matched: vec!["default/audit".into(), "default/deny".into()],
+                    audited,
+                    dry_run: None,
+                },
+                RbacDecision {
+                    allowed: true,
+                    matched: vec![],
+                    audited: vec![],
+                    dry_run: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn evaluate_rbac_custom() {
+        let policies = vec![
+            port_policy("custom", rbac::RbacAction::Custom, false, vec![vec![8080]]),
+            port_policy("deny", rbac::RbacAction::Deny, false, vec![vec![9090]]),
+            port_policy(
+                "dry-custom",
+                rbac::RbacAction::Custom,
+                true,
+                vec![vec![6060]],
+            ),
+        ];
+        let decision = |allowed, dry_run_allowed, matched: &[&str]| RbacDecision {
+            allowed,
+            matched: matched.iter().map(|m| strng::new(m)).collect(),
+            audited: vec![],
+            dry_run: Some(DryRunDecision {
+                allowed: dry_run_allowed,
+                matched: if dry_run_allowed == allowed {
+                    vec![]
+                } else {
+                    vec!["default/dry-custom".into()]
+                },
+            }),
+        };
+
+        // Without an external authorization server, matching CUSTOM policies deny.
+        assert_eq!(
+            evaluate_rbac_to_port(policies.clone(), None, &[8080, 7070]).await,
+            vec![
+                decision(false, false, &["default/custom"]),
+                decision(true, true, &[])
+            ]
+        );
+
+        let (mut server, ext_authz) =
+            test_helpers::ext_authz::AuthzServer::spawn(Duration::from_secs(1), false).await;
+        server
+            .response
+            .send(Ok(crate::xds::service::auth::v3::CheckResponse {
+                status: Some(crate::xds::service::auth::v3::Status {
+                    code: 7,
+                    message: "denied".to_string(),
+                }),
+            }))
+            .unwrap();
+        assert_eq!(
+            evaluate_rbac_to_port(policies, Some(ext_authz), &[8080, 9090, 7070, 6060]).await,
+            vec![
+                decision(false, false, &["default/custom"]),
+                decision(false, false, &["default/deny"]),
+                decision(true, true, &[]),
+                // Dry-run CUSTOM policies consult the server, but do not enforce its answer.
+                decision(true, false, &[]),
+            ]
+        );
+        // The server is only asked when a CUSTOM policy matches and could change the outcome.
+        let ports = std::iter::from_fn(|| server.requests.try_recv().ok())
+            .map(|r| {
+                r.attributes
+                    .unwrap()
+                    .destination
+                    .unwrap()
+                    .address
+                    .unwrap()
+                    .socket_address
+                    .unwrap()
+                    .port_value
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ports, vec![8080, 6060]);
+    }
+
+    #[tokio::test]
+    async fn explain_rbac_custom() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        state.policies.insert(port_policy(
+            "custom",
+            rbac::RbacAction::Custom,
+            false,
+            vec![vec![8080]],
+        ));
+        let (mut server, ext_authz) =
+            test_helpers::ext_authz::AuthzServer::spawn(Duration::from_secs(1), false).await;
+        let state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        )
+        .with_ext_authz(ext_authz);
+        let ctx = |port| ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".into(),
+                dst: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), port),
+            },
+            dest_workload_info: None,
+        };
+
+        let explanation = state.explain_rbac(&ctx(8080)).unwrap();
+        assert!(explanation.would_consult_ext_authz);
+        assert!(explanation.decision.allowed);
+        assert_eq!(
+            explanation.decision.matched,
+            vec![strng::new("default/custom")]
+        );
+        let explanation = state.explain_rbac(&ctx(9090)).unwrap();
+        assert!(!explanation.would_consult_ext_authz);
+        // Explaining a decision never consults the external authorization server.
+        assert!(server.requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn evaluate_rbac_dry_run() {
+        let policies = vec![
+            port_policy("deny", rbac::RbacAction::Deny, false, vec![vec![6060]]),
+            port_policy("dry-allow", rbac::RbacAction::Allow, true, vec![vec![8080]]),
+            port_policy("dry-deny", rbac::RbacAction::Deny, true, vec![vec![7070]]),
+        ];
+        let decision = |allowed, dry_run_allowed, matched: &[&str]| RbacDecision {
+            allowed,
+            matched: if allowed {
+                vec![]
+            } else {
+                vec!["default/deny".into()]
+            },
+            audited: vec![],
+            dry_run: Some(DryRunDecision {
+                allowed: dry_run_allowed,
+                matched: matched.iter().map(|m| strng::new(m)).collect(),
+            }),
+        };
+
+        // Dry-run policies are reported, but only enforced policies decide.
+        assert_eq!(
+            evaluate_rbac_to_port(policies, None, &[8080, 9090, 7070, 6060]).await,
+            vec![
+                decision(true, true, &["default/dry-allow"]),
+                // The dry-run ALLOW policy would deny anything else
+                decision(true, false, &[]),
+                decision(true, false, &["default/dry-deny"]),
+                decision(false, false, &[]),
+            ]
+        );
+
+        // Without dry-run policies, there is no dry-run decision.
+        let policies = vec![port_policy(
+            "deny",
+            rbac::RbacAction::Deny,
+            false,
+            vec![vec![6060]],
+        )];
+        let decisions = evaluate_rbac_to_port(policies, None, &[8080]).await;
+        assert_eq!(decisions[0].dry_run, None);
+    }
+
+    #[tokio::test]
+    async fn record_policy_matches() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        state.policies.insert(port_policy(
+            "deny",
+            rbac::RbacAction::Deny,
+            false,
+            vec![vec![9090]],
+        ));
+        state.policies.insert(port_policy(
+            "unused",
+            rbac::RbacAction::Deny,
+            false,
+            vec![vec![6060]],
+        ));
+        let state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        for port in [8080, 9090, 9090] {
+            let ctx = ProxyRbacContext {
+                conn: rbac::Connection {
+                    src_identity: None,
+                    src: "192.168.0.1:1234".parse().unwrap(),
+                    dst_network: "".into(),
+                    dst: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), port),
+                },
+                dest_workload_info: None,
+            };
+            let decision = state.evaluate_rbac(&ctx, None).await;
+            state.record_policy_matches(&decision);
+        }
+
+        let dump = serde_json::to_value(&*state.read()).unwrap();
+        let matches = &dump["policies"]["default/deny"]["matches"];
+        assert_eq!(matches["allowed"], 0);
+        assert_eq!(matches["denied"], 2);
+        assert!(matches["lastDenied"].is_string());
+        assert!(matches.get("lastAllowed").is_none());
+        // Policies that never matched have no stats.
+        assert!(dump["policies"]["default/unused"].get("matches").is_none());
+        assert_eq!(dump["policies"]["default/unused"]["name"], "unused");
+    }
+
     #[tokio::test]
     async fn test_load_balance() {
         let mut state = ProxyState::default();
@@ -1138,6 +2111,7 @@ mod tests {
                     LoadBalancerScopes::Region,
                     LoadBalancerScopes::Zone,
                 ],
+                strategy: None,
             }),
             ..test_helpers::mock_default_service()
         };
@@ -1150,6 +2124,7 @@ mod tests {
                     LoadBalancerScopes::Region,
                     LoadBalancerScopes::Zone,
                 ],
+                strategy: None,
             }),
             ..test_helpers::mock_default_service()
         };
@@ -1163,7 +2138,7 @@ mod tests {
 
         let assert_endpoint = |src: &Workload, svc: &Service, ips: Vec<&str>, desc: &str| {
             let got = state
-                .load_balance(src, svc)
+                .load_balance(src, svc, Default::default())
                 .and_then(|ep| ep.address.clone())
                 .map(|addr| addr.address.to_string());
             if ips.is_empty() {
@@ -1207,4 +2182,175 @@ mod tests {
             "failover full match selects closest match",
         );
     }
+
+    #[tokio::test]
+    async fn test_load_balance_strategy() {
+        let mut state = ProxyState::default();
+        let src = test_helpers::test_default_workload();
+        let ips = ["192.168.0.1", "192.168.0.2", "192.168.0.3"];
+        let endpoints: HashMap<Strng, Endpoint> = ips
+            .iter()
+            .map(|ip| {
+                let uid = strng::format!("cluster1//v1/Pod/default/{ip}");
+                let wl = Workload {
+                    uid: uid.clone(),
+                    name: strng::format!("wl-{ip}"),
+                    workload_ips: vec![ip.parse().unwrap()],
+                    ..test_helpers::test_default_workload()
+                };
+                state.workloads.insert(Arc::new(wl), true);
+                let ep = Endpoint {
+                    workload_uid: uid.clone(),
+                    service: NamespacedHostname {
+                        namespace: TEST_SERVICE_NAMESPACE.into(),
+                        hostname: "example.com".into(),
+                    },
+                    address: Some(NetworkAddress {
+                        address: ip.parse().unwrap(),
+                        network: "".into(),
+                    }),
+                    port: HashMap::from([(80u16, 80u16)]),
+                };
+                (uid, ep)
+            })
+            .collect();
+        let svc_with = |strategy: Option<LoadBalancerStrategy>| Service {
+            endpoints: endpoints.clone(),
+            load_balancer: Some(LoadBalancer {
+                mode: LoadBalancerMode::Failover,
+                routing_preferences: vec![],
+                strategy,
+            }),
+            ..test_helpers::mock_default_service()
+        };
+        let pick = |state: &ProxyState, svc: &Service, conns: Option<&ConnectionManager>| {
+            let opts = PickOptions {
+                conns,
+                ..Default::default()
+            };
+            state
+                .load_balance(&src, svc, opts)
+                .and_then(|ep| ep.address.clone())
+                .map(|addr| addr.address.to_string())
+                .unwrap()
+        };
+
+        // Round robin takes turns, in a stable order.
+        let svc = svc_with(Some(LoadBalancerStrategy::RoundRobin));
+        let got: Vec<_> = (0..6).map(|_| pick(&state, &svc, None)).collect();
+        assert_eq!(got, [ips, ips].concat());
+        // Removing the service forgets its position.
+        pick(&state, &svc, None);
+        state
+            .load_balancing
+            .remove(&strng::format!("{}/{}", svc.namespace, svc.hostname));
+        assert!(state.load_balancing.round_robin.lock().unwrap().is_empty());
+        assert_eq!(pick(&state, &svc, None), ips[0]);
+
+        // The per-service strategy overrides the default one.
+        state.load_balancing =
+            LoadBalancing::new(LoadBalancerStrategy::RoundRobin, Default::default());
+        let svc = svc_with(None);
+        let got: Vec<_> = (0..3).map(|_| pick(&state, &svc, None)).collect();
+        assert_eq!(got, ips);
+
+        // Least request picks the only endpoint without outstanding connections.
+        let cm = ConnectionManager::default();
+        let track = |ip: &str, port: u16| {
+            cm.track_outbound(
+                SocketAddr::new(src.workload_ips[0], port),
+                SocketAddr::new(ip.parse().unwrap(), 80),
+                SocketAddr::new(ip.parse().unwrap(), 80),
+            )
+        };
+        let guards = [
+            track(ips[0], 1000),
+            track(ips[0], 1001),
+            track(ips[1], 1002),
+        ];
+        assert_eq!(cm.outbound_count(ips[0].parse().unwrap()), 2);
+        let svc = svc_with(Some(LoadBalancerStrategy::LeastRequest));
+        for _ in 0..10 {
+            assert_eq!(pick(&state, &svc, Some(&cm)), ips[2]);
+        }
+
+        // Power of two choices never picks the most loaded endpoint.
+        let svc = svc_with(Some(LoadBalancerStrategy::PowerOfTwoChoices));
+        for _ in 0..10 {
+            assert_ne!(pick(&state, &svc, Some(&cm)), ips[0]);
+        }
+
+        // Strategies only pick between the endpoints allowed by the routing preferences.
+        let svc = Service {
+            load_balancer: Some(LoadBalancer {
+                mode: LoadBalancerMode::Strict,
+                routing_preferences: vec![LoadBalancerScopes::Network],
+                strategy: Some(LoadBalancerStrategy::LeastRequest),
+            }),
+            ..svc_with(None)
+        };
+        let other = Workload {
+            network: "other".into(),
+            ..test_helpers::test_default_workload()
+        };
+        assert!(state
+            .load_balance(&other, &svc, Default::default())
+            .is_none());
+        drop(guards);
+        assert_eq!(cm.outbound_count(ips[0].parse().unwrap()), 0);
+
+        // Ejected endpoints are skipped.
+        let uid = strng::format!("cluster1//v1/Pod/default/{}", ips[0]);
+        let detector = state.load_balancing.outlier_detector.clone();
+        let ejected = (0..5)
+            .map(|_| detector.record(&uid, false))
+            .fold(false, |a, b| a || b);
+        assert!(ejected);
+        let svc = svc_with(Some(LoadBalancerStrategy::Random));
+        for _ in 0..10 {
+            assert_ne!(pick(&state, &svc, None), ips[0]);
+        }
+
+        // Excluded addresses are never picked.
+        let excluded = [ips[1].parse().unwrap()];
+        let opts = PickOptions {
+            excluded: &excluded,
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            let ep = state.load_balance(&src, &svc, opts).unwrap();
+            assert_eq!(ep.address.as_ref().unwrap().address.to_string(), ips[2]);
+        }
+    }
+
+    #[test]
+    fn test_load_balance_ips_prefers_source_family() {
+        let state = ProxyState::default();
+        let v4: IpAddr = "10.0.0.1".parse().unwrap();
+        let v6: IpAddr = "fd00::1".parse().unwrap();
+        let wl = Workload {
+            workload_ips: vec![v6, v4],
+            ..test_helpers::test_default_workload()
+        };
+        let pick = |source: Option<IpAddr>, excluded: &[IpAddr]| {
+            let opts = PickOptions {
+                source,
+                excluded,
+                ..Default::default()
+            };
+            state.load_balance_ips(&wl, wl.workload_ips.clone(), opts)
+        };
+
+        for _ in 0..10 {
+            assert_eq!(pick(Some("10.0.0.2".parse().unwrap()), &[]), Some(v4));
+            assert_eq!(pick(Some("fd00::2".parse().unwrap()), &[]), Some(v6));
+            // IPv4-mapped IPv6 sources are treated as IPv4
+            assert_eq!(
+                pick(Some("::ffff:10.0.0.2".parse().unwrap()), &[]),
+                Some(v4)
+            );
+        }
+        // The other family is used if there is no address of the same family
+        assert_eq!(pick(Some("10.0.0.2".parse().unwrap()), &[v4]), Some(v6));
+    }
 }
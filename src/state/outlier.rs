@@ -0,0 +1,309 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use tracing::debug;
+
+use crate::strng::Strng;
+
+/// OutlierDetectionConfig configures passive outlier detection of service endpoints.
+#[derive(serde::Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlierDetectionConfig {
+    /// The number of consecutive connection failures before an endpoint is ejected.
+    /// If 0, outlier detection is disabled.
+    pub consecutive_failures: u32,
+    /// How long an endpoint is ejected for the first time. Each consecutive ejection doubles this.
+    pub base_ejection_time: Duration,
+    /// The maximum time an endpoint is ejected for.
+    pub max_ejection_time: Duration,
+    /// The maximum percentage of a service's endpoints that can be ejected at once.
+    /// Regardless of this, at least one endpoint is always kept.
+    pub max_ejection_percent: u8,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            base_ejection_time: Duration::from_secs(30),
+            max_ejection_time: Duration::from_secs(300),
+            max_ejection_percent: 50,
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    // The number of times the endpoint was ejected without a successful connection in between.
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.is_some_and(|until| until > now)
+    }
+}
+
+/// OutlierDetector tracks connection failures per endpoint, keyed by workload UID, and temporarily
+/// ejects endpoints that fail too many times in a row.
+#[derive(Clone, Debug, Default)]
+pub struct OutlierDetector {
+    cfg: OutlierDetectionConfig,
+    endpoints: Arc<Mutex<HashMap<Strng, EndpointHealth>>>,
+}
+
+impl OutlierDetector {
+    pub fn new(cfg: OutlierDetectionConfig) -> Self {
+        Self {
+            cfg,
+            endpoints: Default::default(),
+        }
+    }
+
+    /// Records the result of a connection to the endpoint. Returns true if the endpoint was ejected
+    /// as a result.
+    pub fn record(&self, endpoint: &Strng, success: bool) -> bool {
+        self.record_at(endpoint, success, Instant::now())
+    }
+
+    fn record_at(&self, endpoint: &Strng, success: bool, now: Instant) -> bool {
+        if self.cfg.consecutive_failures == 0 {
+            return false;
+        }
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if success {
+            endpoints.remove(endpoint);
+            return false;
+        }
+        let health = endpoints.entry(endpoint.clone()).or_default();
+        if health.is_ejected(now) {
+            // Connections picked this endpoint before it was ejected; do not extend the ejection.
+            return false;
+        }
+        health.consecutive_failures += 1;
+        if health.consecutive_failures < self.cfg.consecutive_failures {
+            return false;
+        }
+        health.consecutive_failures = 0;
+        health.ejections += 1;
+        let ejection_time = self
+            .cfg
+            .base_ejection_time
+            .saturating_mul(2u32.saturating_pow(health.ejections - 1))
+            .min(self.cfg.max_ejection_time);
+        health.ejected_until = Some(now + ejection_time);
+        debug!(
+            "ejecting endpoint {endpoint} for {ejection_time:?} after {} consecutive failures",
+            self.cfg.consecutive_failures
+        );
+        true
+    }
+
+    /// Forgets an endpoint, once it is removed.
+    pub fn remove(&self, endpoint: &Strng) {
+        self.endpoints.lock().unwrap().remove(endpoint);
+    }
+
+    /// Returns the endpoints, out of the given endpoints of a service, that should not be picked.
+    pub fn ejected<'a>(&self, endpoints: impl Iterator<Item = &'a Strng>) -> HashSet<Strng> {
+        self.ejected_at(endpoints, Instant::now())
+    }
+
+    fn ejected_at<'a>(
+        &self,
+        endpoints: impl Iterator<Item = &'a Strng>,
+        now: Instant,
+    ) -> HashSet<Strng> {
+        let health = self.endpoints.lock().unwrap();
+        if health.is_empty() {
+            return HashSet::new();
+        }
+        let mut total = 0;
+        let mut ejected = Vec::new();
+        for ep in endpoints {
+            total += 1;
+            if let Some(until) = health
+                .get(ep)
+                .filter(|h| h.is_ejected(now))
+                .and_then(|h| h.ejected_until)
+            {
+                ejected.push((until, ep));
+            }
+        }
+        let max_ejected =
+            (total * self.cfg.max_ejection_percent as usize / 100).min(total.saturating_sub(1));
+        if ejected.len() > max_ejected {
+            // Only eject the endpoints with the longest remaining ejection time.
+            ejected.sort_by(|a, b| b.0.cmp(&a.0));
+            ejected.truncate(max_ejected);
+        }
+        ejected.into_iter().map(|(_, ep)| ep.clone()).collect()
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointHealthDump {
+    consecutive_failures: u32,
+    ejections: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ejected_until: Option<String>,
+}
+
+impl Serialize for OutlierDetector {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let now = Instant::now();
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut map = serializer.serialize_map(Some(endpoints.len()))?;
+        for (uid, health) in endpoints.iter() {
+            let ejected_until = health
+                .ejected_until
+                .filter(|_| health.is_ejected(now))
+                .map(|until| rfc3339(SystemTime::now() + (until - now)));
+            let dump = EndpointHealthDump {
+                consecutive_failures: health.consecutive_failures,
+                ejections: health.ejections,
+                ejected_until,
+            };
+            map.serialize_entry(uid, &dump)?;
+        }
+        map.end()
+    }
+}
+
+pub(super) fn rfc3339(t: SystemTime) -> String {
+    use chrono::prelude::{DateTime, Utc};
+    let dt: DateTime<Utc> = t.into();
+    dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strng;
+
+    fn detector(max_ejection_percent: u8) -> OutlierDetector {
+        OutlierDetector::new(OutlierDetectionConfig {
+            consecutive_failures: 3,
+            base_ejection_time: Duration::from_secs(10),
+            max_ejection_time: Duration::from_secs(30),
+            max_ejection_percent,
+        })
+    }
+
+    fn fail(d: &OutlierDetector, ep: &Strng, times: usize, now: Instant) -> bool {
+        (0..times)
+            .map(|_| d.record_at(ep, false, now))
+            .fold(false, |a, b| a || b)
+    }
+
+    #[test]
+    fn test_ejection() {
+        let d = detector(100);
+        let eps = [strng::new("a"), strng::new("b")];
+        let now = Instant::now();
+
+        assert!(!fail(&d, &eps[0], 2, now));
+        assert!(d.ejected_at(eps.iter(), now).is_empty());
+        // A success resets the consecutive failures
+        d.record_at(&eps[0], true, now);
+        assert!(!fail(&d, &eps[0], 2, now));
+        assert!(fail(&d, &eps[0], 1, now));
+        assert_eq!(
+            d.ejected_at(eps.iter(), now),
+            HashSet::from([eps[0].clone()])
+        );
+        // Failures of in-flight connections while ejected do not count
+        assert!(!fail(&d, &eps[0], 5, now));
+
+        // First ejection is the base ejection time
+        let now = now + Duration::from_secs(10);
+        assert!(d.ejected_at(eps.iter(), now).is_empty());
+        // Each consecutive ejection doubles, up to the max
+        assert!(fail(&d, &eps[0], 3, now));
+        assert_eq!(
+            d.ejected_at(eps.iter(), now + Duration::from_secs(19))
+                .len(),
+            1
+        );
+        assert!(d
+            .ejected_at(eps.iter(), now + Duration::from_secs(20))
+            .is_empty());
+        let now = now + Duration::from_secs(20);
+        assert!(fail(&d, &eps[0], 3, now));
+        assert_eq!(
+            d.ejected_at(eps.iter(), now + Duration::from_secs(29))
+                .len(),
+            1
+        );
+        assert!(d
+            .ejected_at(eps.iter(), now + Duration::from_secs(30))
+            .is_empty());
+
+        // Success resets the ejection time
+        let now = now + Duration::from_secs(30);
+        d.record_at(&eps[0], true, now);
+        assert!(fail(&d, &eps[0], 3, now));
+        assert!(d
+            .ejected_at(eps.iter(), now + Duration::from_secs(10))
+            .is_empty());
+
+        // Removed endpoints are forgotten
+        d.remove(&eps[0]);
+        assert!(d.endpoints.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_ejection_percent() {
+        let d = detector(50);
+        let eps: Vec<_> = (0..4).map(|i| strng::format!("ep-{i}")).collect();
+        let now = Instant::now();
+        for ep in &eps {
+            assert!(fail(&d, ep, 3, now));
+        }
+        // Only half of the endpoints can be ejected
+        assert_eq!(d.ejected_at(eps.iter(), now).len(), 2);
+        // A single endpoint is never ejected
+        assert!(d.ejected_at(eps[..1].iter(), now).is_empty());
+
+        // Even with 100%, one endpoint is kept
+        let d = detector(100);
+        for ep in &eps {
+            assert!(fail(&d, ep, 3, now));
+        }
+        assert_eq!(d.ejected_at(eps.iter(), now).len(), 3);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let d = OutlierDetector::new(OutlierDetectionConfig {
+            consecutive_failures: 0,
+            ..Default::default()
+        });
+        let ep = strng::new("a");
+        assert!(!fail(&d, &ep, 100, Instant::now()));
+        assert!(d.ejected(std::iter::once(&ep)).is_empty());
+    }
+}
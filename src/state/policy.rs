@@ -12,24 +12,109 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::rbac::{Authorization, RbacScope};
+use crate::rbac::{Authorization, Connection, RbacAction, RbacMatch, RbacScope, StringMatch};
 use crate::strng;
 use crate::strng::Strng;
+use ipnet::IpNet;
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::watch;
 
+use super::outlier::rfc3339;
+
 /// A PolicyStore encapsulates all policy information about workloads in the mesh
 #[derive(Default, Debug)]
 pub struct PolicyStore {
     /// policies maintains a mapping of ns/name to policy.
     pub(super) by_key: HashMap<Strng, Authorization>,
 
-    /// policies_by_namespace maintains a mapping of namespace (or "" for global) to policy names
-    by_namespace: HashMap<Strng, HashSet<Strng>>,
+    /// policies_by_namespace maintains a mapping of namespace (or "" for global) to an index of its
+    /// policies.
+    by_namespace: HashMap<Strng, PolicyIndex>,
+
+    /// stats maintains a mapping of ns/name to how often the policy matched connections. Entries
+    /// are added and removed along with the policies, so matches are recorded with atomics only.
+    stats: HashMap<Strng, PolicyCounters>,
 
     notifier: PolicyStoreNotify,
 }
 
+/// How often a policy matched connections, by the decision made for them.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct PolicyStats {
+    pub allowed: u64,
+    pub denied: u64,
+    pub last_allowed: Option<SystemTime>,
+    pub last_denied: Option<SystemTime>,
+}
+
+/// The counters behind PolicyStats. Times are in milliseconds since the epoch, or 0 if never.
+#[derive(Default, Debug)]
+struct PolicyCounters {
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    last_allowed: AtomicU64,
+    last_denied: AtomicU64,
+}
+
+impl PolicyCounters {
+    fn record(&self, allowed: bool, now: u64) {
+        let (count, last) = if allowed {
+            (&self.allowed, &self.last_allowed)
+        } else {
+            (&self.denied, &self.last_denied)
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        last.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> PolicyStats {
+        let time = |t: &AtomicU64| match t.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        };
+        PolicyStats {
+            allowed: self.allowed.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            last_allowed: time(&self.last_allowed),
+            last_denied: time(&self.last_denied),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct PolicyDump<'a> {
+    #[serde(flatten)]
+    policy: &'a Authorization,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches: Option<PolicyStatsDump>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyStatsDump {
+    allowed: u64,
+    denied: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_allowed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_denied: Option<String>,
+}
+
+impl From<&PolicyStats> for PolicyStatsDump {
+    fn from(stats: &PolicyStats) -> Self {
+        PolicyStatsDump {
+            allowed: stats.allowed,
+            denied: stats.denied,
+            last_allowed: stats.last_allowed.map(rfc3339),
+            last_denied: stats.last_denied.map(rfc3339),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct PolicyStoreNotify {
     sender: watch::Sender<()>,
@@ -42,6 +127,28 @@ impl Default for PolicyStoreNotify {
     }
 }
 
+/// The policies that may match a connection, collected from one or more namespaces and workloads.
+#[derive(Default, Debug)]
+pub struct Candidates<'a> {
+    pub policies: Vec<&'a Authorization>,
+    /// Whether any enforced ALLOW policy applies, whether it may match or not.
+    pub has_allow: bool,
+    /// Whether any dry-run ALLOW policy applies, whether it may match or not.
+    pub has_dry_run_allow: bool,
+    /// Whether any dry-run policy applies, whether it may match or not.
+    pub has_dry_run: bool,
+}
+
+impl<'a> Candidates<'a> {
+    /// Adds a policy that applies, and may match.
+    pub fn add(&mut self, pol: &'a Authorization) {
+        self.has_allow |= pol.action == RbacAction::Allow && !pol.dry_run;
+        self.has_dry_run_allow |= pol.action == RbacAction::Allow && pol.dry_run;
+        self.has_dry_run |= pol.dry_run;
+        self.policies.push(pol);
+    }
+}
+
 impl PolicyStore {
     pub fn get(&self, key: &Strng) -> Option<&Authorization> {
         self.by_key.get(key)
@@ -51,48 +158,113 @@ impl PolicyStore {
         self.by_namespace
             .get(namespace)
             .into_iter()
-            .flatten()
+            .flat_map(|idx| idx.keys.iter())
             .cloned()
             .collect()
     }
 
+    /// Adds the policies in `namespace` (or "" for global) that may match the connection to `out`.
+    /// Policies that are not returned are guaranteed not to match.
+    pub fn candidates<'a>(
+        &'a self,
+        namespace: &Strng,
+        conn: &Connection,
+        out: &mut Candidates<'a>,
+    ) {
+        let Some(idx) = self.by_namespace.get(namespace) else {
+            return;
+        };
+        out.has_allow |= idx.allow > 0;
+        out.has_dry_run_allow |= idx.dry_run_allow > 0;
+        out.has_dry_run |= idx.dry_run > 0;
+        let mut keys = HashSet::new();
+        idx.lookup(conn, &mut keys);
+        let start = out.policies.len();
+        out.policies
+            .extend(keys.into_iter().filter_map(|k| self.by_key.get(k)));
+        // Keep evaluation order deterministic.
+        out.policies[start..].sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+    }
+
     pub fn insert(&mut self, rbac: Authorization) {
         let key: Strng = rbac.to_key();
-        match rbac.scope {
-            RbacScope::Global => {
-                self.by_namespace
-                    .entry(strng::EMPTY)
-                    .or_default()
-                    .insert(key.clone());
-            }
-            RbacScope::Namespace => {
-                self.by_namespace
-                    .entry(strng::new(&rbac.namespace))
-                    .or_default()
-                    .insert(key.clone());
-            }
-            RbacScope::WorkloadSelector => {}
+        // The policy may have changed scope, or the rules it is indexed by.
+        self.unindex(&key);
+        if let Some(ns) = Self::index_namespace(&rbac) {
+            self.by_namespace
+                .entry(ns)
+                .or_default()
+                .insert(key.clone(), &rbac);
         }
+        // Updates keep the stats of the policy.
+        self.stats.entry(key.clone()).or_default();
         self.by_key.insert(key, rbac);
     }
 
     pub fn remove(&mut self, name: Strng) {
-        let Some(rbac) = self.by_key.remove(&name) else {
+        self.unindex(&name);
+        self.stats.remove(&name);
+    }
+
+    // Removes the policy, but not its stats.
+    fn unindex(&mut self, name: &Strng) {
+        let Some(rbac) = self.by_key.remove(name) else {
             return;
         };
-        if let Some(key) = match rbac.scope {
-            RbacScope::Global => Some(strng::EMPTY),
-            RbacScope::Namespace => Some(rbac.namespace),
-            RbacScope::WorkloadSelector => None,
-        } {
+        if let Some(key) = Self::index_namespace(&rbac) {
             if let Some(pl) = self.by_namespace.get_mut(&key) {
-                pl.remove(&name);
-                if pl.is_empty() {
+                pl.remove(name, &rbac);
+                if pl.keys.is_empty() {
                     self.by_namespace.remove(&key);
                 }
             }
         }
     }
+
+    // Returns the namespace a policy is indexed under, or None for workload selector policies,
+    // which are referenced by the workloads themselves.
+    fn index_namespace(rbac: &Authorization) -> Option<Strng> {
+        match rbac.scope {
+            RbacScope::Global => Some(strng::EMPTY),
+            RbacScope::Namespace => Some(rbac.namespace.clone()),
+            RbacScope::WorkloadSelector => None,
+        }
+    }
+
+    /// Records that the policies matched a connection, and whether it was allowed.
+    pub fn record_matches(&self, keys: &[Strng], allowed: bool) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        // The policy may have been removed since the connection was evaluated.
+        for counters in keys.iter().filter_map(|k| self.stats.get(k)) {
+            counters.record(allowed, now);
+        }
+    }
+
+    /// Returns how often the policy matched connections.
+    pub fn stats(&self, key: &Strng) -> PolicyStats {
+        self.stats
+            .get(key)
+            .map(PolicyCounters::stats)
+            .unwrap_or_default()
+    }
+
+    pub(super) fn dump(&self) -> HashMap<&Strng, PolicyDump<'_>> {
+        self.by_key
+            .iter()
+            .map(|(k, policy)| {
+                let stats = self.stats(k);
+                let dump = PolicyDump {
+                    policy,
+                    // Policies that never matched have no stats.
+                    matches: (stats != PolicyStats::default()).then(|| (&stats).into()),
+                };
+                (k, dump)
+            })
+            .collect()
+    }
+
     pub fn subscribe(&self) -> watch::Receiver<()> {
         self.notifier.sender.subscribe()
     }
@@ -102,5 +274,600 @@ impl PolicyStore {
     pub fn clear_all_policies(&mut self) {
         self.by_namespace.clear();
         self.by_key.clear();
+        self.stats.clear();
+    }
+
+    /// Replaces all policies with `policies`. Policies that are kept keep their stats.
+    pub fn replace_all(&mut self, policies: impl IntoIterator<Item = Authorization>) {
+        self.by_namespace.clear();
+        self.by_key.clear();
+        for rbac in policies {
+            self.insert(rbac);
+        }
+        let by_key = &self.by_key;
+        self.stats.retain(|key, _| by_key.contains_key(key));
+    }
+}
+
+/// PolicyIndex finds the policies of a namespace that may match a connection, without evaluating
+/// each of them. Every policy is indexed by one attribute that any connection it matches must have:
+/// its destination port, destination or source IP, or principal prefix, in that order of
+/// preference. Policies without such an attribute are always candidates.
+#[derive(Default, Debug)]
+struct PolicyIndex {
+    keys: HashSet<Strng>,
+    by_port: HashMap<u16, HashSet<Strng>>,
+    by_destination_ip: CidrTrie,
+    by_source_ip: CidrTrie,
+    by_principal_prefix: HashMap<Strng, HashSet<Strng>>,
+    unindexed: HashSet<Strng>,
+
+    // The number of policies that affect the decision even if they do not match.
+    allow: usize,
+    dry_run_allow: usize,
+    dry_run: usize,
+}
+
+/// The attribute a policy is indexed by.
+#[derive(Debug, PartialEq, Eq)]
+enum IndexKey {
+    Ports(Vec<u16>),
+    DestinationIps(Vec<IpNet>),
+    SourceIps(Vec<IpNet>),
+    PrincipalPrefixes(Vec<Strng>),
+    Unindexed,
+}
+
+impl IndexKey {
+    fn for_policy(pol: &Authorization) -> IndexKey {
+        if let Some(ports) = required(pol, |m| Some(m.destination_ports.clone())) {
+            return IndexKey::Ports(ports);
+        }
+        if let Some(ips) = required(pol, |m| Some(m.destination_ips.clone())) {
+            return IndexKey::DestinationIps(ips);
+        }
+        if let Some(ips) = required(pol, |m| Some(m.source_ips.clone())) {
+            return IndexKey::SourceIps(ips);
+        }
+        let principal_prefixes = |m: &RbacMatch| {
+            m.principals
+                .iter()
+                .map(|p| match p {
+                    // An exact match is a prefix of itself.
+                    StringMatch::Exact(s) | StringMatch::Prefix(s) => Some(s.clone()),
+                    StringMatch::Suffix(_) | StringMatch::Presence() => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        if let Some(prefixes) = required(pol, principal_prefixes) {
+            return IndexKey::PrincipalPrefixes(prefixes);
+        }
+        IndexKey::Unindexed
+    }
+}
+
+/// Returns the values, one of which any connection matching the policy must have, or None if there
+/// is no such set. `values` returns the values a match requires one of, or None if it cannot tell;
+/// an empty list means the match does not constrain the attribute.
+fn required<T>(
+    pol: &Authorization,
+    values: impl Fn(&RbacMatch) -> Option<Vec<T>>,
+) -> Option<Vec<T>> {
+    let mut res = Vec::new();
+    // The policy matches if ANY rule matches, so every rule must be constrained.
+    for rule in &pol.rules {
+        // The rule matches if ALL clauses match, so one constrained clause is enough.
+        let constrained = rule.iter().find_map(|clause| {
+            if clause.is_empty() {
+                // Empty clauses always match.
+                return None;
+            }
+            // The clause matches if ANY match does, so every match must be constrained. Empty
+            // matches never match, so they do not count.
+            let mut clause_values = Vec::new();
+            for m in clause.iter().filter(|m| !m.is_empty()) {
+                match values(m) {
+                    Some(v) if !v.is_empty() => clause_values.extend(v),
+                    _ => return None,
+                }
+            }
+            Some(clause_values)
+        });
+        res.extend(constrained?);
+    }
+    Some(res)
+}
+
+impl PolicyIndex {
+    fn insert(&mut self, key: Strng, pol: &Authorization) {
+        if !self.keys.insert(key.clone()) {
+            return;
+        }
+        self.count(pol, true);
+        match IndexKey::for_policy(pol) {
+            IndexKey::Ports(ports) => {
+                for port in ports {
+                    self.by_port.entry(port).or_default().insert(key.clone());
+                }
+            }
+            IndexKey::DestinationIps(ips) => {
+                for ip in ips {
+                    self.by_destination_ip.insert(ip, key.clone());
+                }
+            }
+            IndexKey::SourceIps(ips) => {
+                for ip in ips {
+                    self.by_source_ip.insert(ip, key.clone());
+                }
+            }
+            IndexKey::PrincipalPrefixes(prefixes) => {
+                for prefix in prefixes {
+                    self.by_principal_prefix
+                        .entry(prefix)
+                        .or_default()
+                        .insert(key.clone());
+                }
+            }
+            IndexKey::Unindexed => {
+                self.unindexed.insert(key);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &Strng, pol: &Authorization) {
+        if !self.keys.remove(key) {
+            return;
+        }
+        self.count(pol, false);
+        match IndexKey::for_policy(pol) {
+            IndexKey::Ports(ports) => {
+                for port in ports {
+                    remove_from(&mut self.by_port, &port, key);
+                }
+            }
+            IndexKey::DestinationIps(ips) => {
+                for ip in ips {
+                    self.by_destination_ip.remove(ip, key);
+                }
+            }
+            IndexKey::SourceIps(ips) => {
+                for ip in ips {
+                    self.by_source_ip.remove(ip, key);
+                }
+            }
+            IndexKey::PrincipalPrefixes(prefixes) => {
+                for prefix in prefixes {
+                    remove_from(&mut self.by_principal_prefix, &prefix, key);
+                }
+            }
+            IndexKey::Unindexed => {
+                self.unindexed.remove(key);
+            }
+        }
+    }
+
+    fn count(&mut self, pol: &Authorization, add: bool) {
+        let counters = [
+            (
+                &mut self.allow,
+                pol.action == RbacAction::Allow && !pol.dry_run,
+            ),
+            (
+                &mut self.dry_run_allow,
+                pol.action == RbacAction::Allow && pol.dry_run,
+            ),
+            (&mut self.dry_run, pol.dry_run),
+        ];
+        for (counter, applies) in counters {
+            if applies && add {
+                *counter += 1;
+            } else if applies {
+                *counter -= 1;
+            }
+        }
+    }
+
+    fn lookup<'a>(&'a self, conn: &Connection, out: &mut HashSet<&'a Strng>) {
+        out.extend(self.unindexed.iter());
+        if let Some(keys) = self.by_port.get(&conn.dst.port()) {
+            out.extend(keys.iter());
+        }
+        self.by_destination_ip.lookup(conn.dst.ip(), out);
+        self.by_source_ip.lookup(conn.src.ip(), out);
+        if self.by_principal_prefix.is_empty() {
+            return;
+        }
+        let Some(id) = &conn.src_identity else {
+            // Principals never match plaintext connections.
+            return;
+        };
+        let id = id.to_string();
+        // Principals are matched without the spiffe:// prefix.
+        let Some(id) = id.strip_prefix("spiffe://") else {
+            return;
+        };
+        for (i, _) in id.char_indices().chain(std::iter::once((id.len(), ' '))) {
+            if let Some(keys) = self.by_principal_prefix.get(&id[..i]) {
+                out.extend(keys.iter());
+            }
+        }
+    }
+}
+
+fn remove_from<K: std::hash::Hash + Eq>(m: &mut HashMap<K, HashSet<Strng>>, k: &K, key: &Strng) {
+    if let Some(keys) = m.get_mut(k) {
+        keys.remove(key);
+        if keys.is_empty() {
+            m.remove(k);
+        }
+    }
+}
+
+/// CidrTrie is a binary trie of CIDRs, to find all the CIDRs that contain an address.
+#[derive(Default, Debug)]
+struct CidrTrie {
+    v4: TrieNode,
+    v6: TrieNode,
+}
+
+#[derive(Default, Debug)]
+struct TrieNode {
+    keys: HashSet<Strng>,
+    children: [Option<Box<TrieNode>>; 2],
+}
+
+impl TrieNode {
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.children.iter().all(Option::is_none)
+    }
+}
+
+// Returns the address as bits, most significant first, and the number of bits.
+fn address_bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(ip) => ((u32::from(ip) as u128) << 96, 32),
+        IpAddr::V6(ip) => (u128::from(ip), 128),
+    }
+}
+
+fn bit(bits: u128, i: u8) -> usize {
+    ((bits >> (127 - i)) & 1) as usize
+}
+
+impl CidrTrie {
+    fn root(&mut self, ip: IpAddr) -> &mut TrieNode {
+        match ip {
+            IpAddr::V4(_) => &mut self.v4,
+            IpAddr::V6(_) => &mut self.v6,
+        }
+    }
+
+    fn insert(&mut self, net: IpNet, key: Strng) {
+        let (bits, _) = address_bits(net.network());
+        let mut node = self.root(net.network());
+        for i in 0..net.prefix_len() {
+            node = node.children[bit(bits, i)]
+                .get_or_insert_with(Default::default)
+                .as_mut();
+        }
+        node.keys.insert(key);
+    }
+
+    fn remove(&mut self, net: IpNet, key: &Strng) {
+        fn remove_node(node: &mut TrieNode, bits: u128, i: u8, len: u8, key: &Strng) {
+            if i == len {
+                node.keys.remove(key);
+                return;
+            }
+            let child = &mut node.children[bit(bits, i)];
+            if let Some(c) = child {
+                remove_node(c, bits, i + 1, len, key);
+                if c.is_empty() {
+                    *child = None;
+                }
+            }
+        }
+        let (bits, _) = address_bits(net.network());
+        remove_node(self.root(net.network()), bits, 0, net.prefix_len(), key);
+    }
+
+    fn lookup<'a>(&'a self, ip: IpAddr, out: &mut HashSet<&'a Strng>) {
+        let (bits, len) = address_bits(ip);
+        let mut node = match ip {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        };
+        out.extend(node.keys.iter());
+        for i in 0..len {
+            match &node.children[bit(bits, i)] {
+                Some(child) => node = child.as_ref(),
+                None => return,
+            }
+            out.extend(node.keys.iter());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+
+    fn policy(name: &str, action: RbacAction, rules: Vec<Vec<Vec<RbacMatch>>>) -> Authorization {
+        Authorization {
+            name: name.into(),
+            namespace: "ns".into(),
+            scope: RbacScope::Namespace,
+            action,
+            rules,
+            dry_run: false,
+        }
+    }
+
+    fn single(m: RbacMatch) -> Vec<Vec<Vec<RbacMatch>>> {
+        vec![vec![vec![m]]]
+    }
+
+    fn conn(src: &str, dst: &str, namespace: Option<&str>) -> Connection {
+        Connection {
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            src_identity: namespace.map(|ns| Identity::Spiffe {
+                trust_domain: "cluster.local".into(),
+                namespace: ns.into(),
+                service_account: "sa".into(),
+            }),
+            dst_network: "".into(),
+        }
+    }
+
+    fn candidates(store: &PolicyStore, conn: &Connection) -> (Vec<&str>, bool) {
+        let mut out = Candidates::default();
+        store.candidates(&strng::new("ns"), conn, &mut out);
+        let names = out.policies.iter().map(|p| p.name.as_str()).collect();
+        (names, out.has_allow)
+    }
+
+    #[test]
+    fn index_candidates() {
+        let mut store = PolicyStore::default();
+        store.insert(policy(
+            "port",
+            RbacAction::Deny,
+            single(RbacMatch {
+                destination_ports: vec![80],
+                destination_ips: vec!["10.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            }),
+        ));
+        store.insert(policy(
+            "dst",
+            RbacAction::Deny,
+            single(RbacMatch {
+                destination_ips: vec!["10.1.0.0/16".parse().unwrap()],
+                ..Default::default()
+            }),
+        ));
+        store.insert(policy(
+            "src",
+            RbacAction::Deny,
+            single(RbacMatch {
+                source_ips: vec!["192.168.0.0/24".parse().unwrap()],
+                ..Default::default()
+            }),
+        ));
+        store.insert(policy(
+            "principal",
+            RbacAction::Allow,
+            single(RbacMatch {
+                principals: vec![StringMatch::Prefix("cluster.local/ns/a/".into())],
+                ..Default::default()
+            }),
+        ));
+        store.insert(policy(
+            "namespace",
+            RbacAction::Audit,
+            single(RbacMatch {
+                namespaces: vec![StringMatch::Exact("a".into())],
+                ..Default::default()
+            }),
+        ));
+        // Each rule is constrained by a different attribute, so nothing is required of all of them.
+        store.insert(policy(
+            "mixed",
+            RbacAction::Deny,
+            vec![
+                vec![vec![RbacMatch {
+                    destination_ports: vec![90],
+                    ..Default::default()
+                }]],
+                vec![vec![RbacMatch {
+                    source_ips: vec!["192.168.0.0/24".parse().unwrap()],
+                    ..Default::default()
+                }]],
+            ],
+        ));
+        // Only one clause needs to be constrained.
+        store.insert(policy(
+            "clauses",
+            RbacAction::Deny,
+            vec![vec![
+                vec![RbacMatch {
+                    namespaces: vec![StringMatch::Exact("a".into())],
+                    ..Default::default()
+                }],
+                vec![
+                    RbacMatch {
+                        destination_ports: vec![443],
+                        ..Default::default()
+                    },
+                    RbacMatch {
+                        destination_ports: vec![8443],
+                        not_namespaces: vec![StringMatch::Exact("b".into())],
+                        ..Default::default()
+                    },
+                ],
+            ]],
+        ));
+
+        assert_eq!(
+            candidates(&store, &conn("172.16.0.1:1234", "10.1.0.1:80", Some("a"))),
+            (vec!["dst", "mixed", "namespace", "port", "principal"], true)
+        );
+        assert_eq!(
+            candidates(
+                &store,
+                &conn("192.168.0.1:1234", "10.2.0.1:8443", Some("b"))
+            ),
+            (vec!["clauses", "mixed", "namespace", "src"], true)
+        );
+        assert_eq!(
+            candidates(&store, &conn("172.16.0.1:1234", "10.2.0.1:443", None)),
+            (vec!["clauses", "mixed", "namespace"], true)
+        );
+
+        // The index returns a superset of the matching policies.
+        for c in [
+            conn("172.16.0.1:1234", "10.1.0.1:80", Some("a")),
+            conn("192.168.0.1:1234", "10.2.0.1:8443", Some("b")),
+            conn("192.168.0.1:1234", "10.1.0.1:90", None),
+            conn("[::1]:1234", "[::2]:443", Some("a")),
+        ] {
+            let (got, _) = candidates(&store, &c);
+            for pol in store.by_key.values() {
+                if pol.matches(&c) {
+                    assert!(
+                        got.contains(&pol.name.as_str()),
+                        "{} missing for {c}",
+                        pol.name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn index_update() {
+        let mut store = PolicyStore::default();
+        let ports = |p| {
+            single(RbacMatch {
+                destination_ports: vec![p],
+                ..Default::default()
+            })
+        };
+        store.insert(policy("pol", RbacAction::Allow, ports(80)));
+        let c80 = conn("127.0.0.1:1234", "127.0.0.2:80", None);
+        let c90 = conn("127.0.0.1:1234", "127.0.0.2:90", None);
+        assert_eq!(candidates(&store, &c80), (vec!["pol"], true));
+        assert_eq!(candidates(&store, &c90), (vec![], true));
+
+        // Updating a policy re-indexes it.
+        store.insert(policy("pol", RbacAction::Deny, ports(90)));
+        assert_eq!(candidates(&store, &c80), (vec![], false));
+        assert_eq!(candidates(&store, &c90), (vec!["pol"], false));
+
+        // Moving it out of the namespace removes it from the index.
+        store.insert(Authorization {
+            scope: RbacScope::WorkloadSelector,
+            ..policy("pol", RbacAction::Deny, ports(90))
+        });
+        assert_eq!(candidates(&store, &c90), (vec![], false));
+        assert!(store.by_namespace.is_empty());
+
+        store.insert(policy("pol", RbacAction::Deny, ports(90)));
+        store.remove("ns/pol".into());
+        assert_eq!(candidates(&store, &c90), (vec![], false));
+        assert!(store.by_namespace.is_empty());
+        assert!(store.by_key.is_empty());
+    }
+
+    #[test]
+    fn policy_stats() {
+        let mut store = PolicyStore::default();
+        let pol = |port| {
+            policy(
+                "pol",
+                RbacAction::Allow,
+                single(RbacMatch {
+                    destination_ports: vec![port],
+                    ..Default::default()
+                }),
+            )
+        };
+        let key = strng::new("ns/pol");
+        store.insert(pol(80));
+        store.record_matches(&[key.clone()], true);
+        store.record_matches(&[key.clone()], true);
+        store.record_matches(&[key.clone()], false);
+        // Unknown policies are not tracked.
+        store.record_matches(&["ns/unknown".into()], true);
+        let stats = store.stats(&key);
+        assert_eq!((stats.allowed, stats.denied), (2, 1));
+        assert!(stats.last_allowed.is_some() && stats.last_denied.is_some());
+        assert_eq!(store.stats(&"ns/unknown".into()), PolicyStats::default());
+
+        // Updates keep the stats, removal drops them.
+        store.insert(pol(90));
+        assert_eq!(store.stats(&key).allowed, 2);
+        store.remove(key.clone());
+        store.insert(pol(90));
+        assert_eq!(store.stats(&key), PolicyStats::default());
+    }
+
+    #[test]
+    fn replace_all_keeps_stats() {
+        let mut store = PolicyStore::default();
+        let pol = |name| {
+            policy(
+                name,
+                RbacAction::Allow,
+                single(RbacMatch {
+                    destination_ports: vec![80],
+                    ..Default::default()
+                }),
+            )
+        };
+        let (a, b) = (strng::new("ns/a"), strng::new("ns/b"));
+        store.insert(pol("a"));
+        store.insert(pol("b"));
+        store.record_matches(&[a.clone(), b.clone()], true);
+
+        store.replace_all([pol("a")]);
+        assert_eq!(store.stats(&a).allowed, 1);
+        assert_eq!(store.stats(&b), PolicyStats::default());
+        assert!(!store.stats.contains_key(&b));
+        // A policy that comes back starts over.
+        store.replace_all([pol("a"), pol("b")]);
+        assert_eq!(store.stats(&a).allowed, 1);
+        assert_eq!(store.stats(&b), PolicyStats::default());
+    }
+
+    #[test]
+    fn cidr_trie() {
+        let mut trie = CidrTrie::default();
+        trie.insert("10.0.0.0/8".parse().unwrap(), "a".into());
+        trie.insert("10.1.0.0/16".parse().unwrap(), "b".into());
+        trie.insert("0.0.0.0/0".parse().unwrap(), "c".into());
+        trie.insert("2001:db8::/32".parse().unwrap(), "d".into());
+        let lookup = |trie: &CidrTrie, ip: &str| {
+            let mut out = HashSet::new();
+            trie.lookup(ip.parse().unwrap(), &mut out);
+            let mut out: Vec<_> = out.into_iter().map(|k| k.to_string()).collect();
+            out.sort();
+            out
+        };
+        assert_eq!(lookup(&trie, "10.1.2.3"), vec!["a", "b", "c"]);
+        assert_eq!(lookup(&trie, "10.2.0.1"), vec!["a", "c"]);
+        assert_eq!(lookup(&trie, "11.0.0.1"), vec!["c"]);
+        assert_eq!(lookup(&trie, "2001:db8::1"), vec!["d"]);
+        assert_eq!(lookup(&trie, "2001:db9::1"), Vec::<String>::new());
+
+        trie.remove("10.1.0.0/16".parse().unwrap(), &"b".into());
+        trie.remove("10.0.0.0/8".parse().unwrap(), &"a".into());
+        trie.remove("2001:db8::/32".parse().unwrap(), &"d".into());
+        assert_eq!(lookup(&trie, "10.1.2.3"), vec!["c"]);
+        // Empty branches are pruned.
+        assert!(trie.v4.children.iter().all(Option::is_none));
+        assert!(trie.v6.is_empty());
     }
 }
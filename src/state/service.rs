@@ -14,6 +14,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use bytes::Bytes;
@@ -28,6 +29,7 @@ use crate::state::workload::{
 };
 use crate::strng::Strng;
 use crate::xds::istio::workload::load_balancing::Scope as XdsScope;
+use crate::xds::istio::workload::load_balancing::Strategy as XdsStrategy;
 use crate::xds::istio::workload::PortList;
 use crate::{strng, xds};
 
@@ -96,11 +98,57 @@ impl TryFrom<XdsScope> for LoadBalancerScopes {
     }
 }
 
+/// LoadBalancerStrategy determines how an endpoint is picked from the candidates allowed by the
+/// routing preferences.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub enum LoadBalancerStrategy {
+    /// Pick a random endpoint.
+    #[default]
+    Random,
+    /// Cycle through the endpoints in order.
+    RoundRobin,
+    /// Pick the endpoint with the fewest outstanding connections.
+    LeastRequest,
+    /// Pick two random endpoints, and use the one with fewer outstanding connections.
+    PowerOfTwoChoices,
+}
+
+impl FromStr for LoadBalancerStrategy {
+    type Err = WorkloadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "RANDOM" => Ok(LoadBalancerStrategy::Random),
+            "ROUND_ROBIN" => Ok(LoadBalancerStrategy::RoundRobin),
+            "LEAST_REQUEST" => Ok(LoadBalancerStrategy::LeastRequest),
+            "POWER_OF_TWO_CHOICES" => Ok(LoadBalancerStrategy::PowerOfTwoChoices),
+            _ => Err(WorkloadError::EnumParse(format!(
+                "invalid load balancer strategy {s}"
+            ))),
+        }
+    }
+}
+
+impl From<XdsStrategy> for Option<LoadBalancerStrategy> {
+    fn from(value: XdsStrategy) -> Self {
+        match value {
+            XdsStrategy::UnspecifiedStrategy => None,
+            XdsStrategy::Random => Some(LoadBalancerStrategy::Random),
+            XdsStrategy::RoundRobin => Some(LoadBalancerStrategy::RoundRobin),
+            XdsStrategy::LeastRequest => Some(LoadBalancerStrategy::LeastRequest),
+            XdsStrategy::PowerOfTwoChoices => Some(LoadBalancerStrategy::PowerOfTwoChoices),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct LoadBalancer {
     pub routing_preferences: Vec<LoadBalancerScopes>,
     pub mode: LoadBalancerMode,
+    /// The strategy for this service. If unset, the proxy's default strategy is used.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub strategy: Option<LoadBalancerStrategy>,
 }
 
 impl Service {
@@ -187,6 +235,7 @@ impl TryFrom<&XdsService> for Service {
                     })
                     .collect::<Result<Vec<LoadBalancerScopes>, WorkloadError>>()?,
                 mode: xds::istio::workload::load_balancing::Mode::try_from(lb.mode)?.into(),
+                strategy: XdsStrategy::try_from(lb.strategy)?.into(),
             })
         } else {
             None
@@ -442,6 +491,14 @@ impl ServiceStore {
         }
     }
 
+    /// Returns the namespaced hostnames of all services.
+    pub fn namespaced_hostnames(&self) -> impl Iterator<Item = NamespacedHostname> + '_ {
+        self.by_host
+            .values()
+            .flatten()
+            .map(|svc| svc.namespaced_hostname())
+    }
+
     #[cfg(test)]
     pub fn num_vips(&self) -> usize {
         self.by_vip.len()
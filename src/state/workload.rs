@@ -82,6 +82,32 @@ impl From<xds::istio::workload::Locality> for Locality {
     }
 }
 
+/// RateLimit limits the rate of new connections with a token bucket.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RateLimit {
+    /// The average number of connections allowed per second.
+    pub connections_per_second: u32,
+    /// The number of connections allowed at once after a quiet period.
+    pub burst: u32,
+}
+
+impl From<xds::istio::workload::ConnectionRateLimit> for Option<RateLimit> {
+    fn from(value: xds::istio::workload::ConnectionRateLimit) -> Self {
+        if value.connections_per_second == 0 {
+            return None;
+        }
+        Some(RateLimit {
+            connections_per_second: value.connections_per_second,
+            burst: if value.burst == 0 {
+                value.connections_per_second
+            } else {
+                value.burst
+            },
+        })
+    }
+}
+
 impl From<xds::istio::workload::WorkloadStatus> for HealthStatus {
     fn from(value: xds::istio::workload::WorkloadStatus) -> Self {
         match value {
@@ -206,6 +232,11 @@ pub struct Workload {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub locality: Locality,
+
+    /// Limits the rate of new inbound connections from each source. If unset, the proxy's default
+    /// limit applies.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub connection_rate_limit: Option<RateLimit>,
 }
 
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
@@ -429,6 +460,8 @@ impl TryFrom<XdsWorkload> for (Workload, HashMap<String, PortList>) {
 
             locality: resource.locality.map(Locality::from).unwrap_or_default(),
 
+            connection_rate_limit: resource.connection_rate_limit.and_then(Into::into),
+
             cluster_id: {
                 let result = resource.cluster_id;
                 if result.is_empty() {
@@ -671,6 +704,11 @@ impl WorkloadStore {
     pub fn has_identity(&self, identity: &Identity) -> bool {
         self.by_identity.get(identity).is_some()
     }
+
+    /// Returns the UIDs of all workloads.
+    pub fn uids(&self) -> impl Iterator<Item = &Strng> {
+        self.by_uid.keys()
+    }
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -702,14 +740,16 @@ mod tests {
     use crate::xds::istio::workload::PortList as XdsPortList;
     use crate::xds::istio::workload::Service as XdsService;
     use crate::xds::istio::workload::WorkloadStatus as XdsStatus;
-    use crate::xds::{LocalClient, ProxyStateUpdateMutator};
+    use crate::xds::{LocalClient, LocalConfig, ProxyStateUpdateMutator};
     use crate::{cert_fetcher, test_helpers};
     use bytes::Bytes;
     use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+    use prometheus_client::registry::Registry;
     use std::collections::HashSet;
     use std::default::Default;
     use std::net::{Ipv4Addr, Ipv6Addr};
     use std::sync::RwLock;
+    use std::time::Duration;
     use xds::istio::workload::NetworkAddress as XdsNetworkAddress;
 
     #[test]
@@ -1246,6 +1286,7 @@ mod tests {
                 strng::EMPTY,
                 &wl,
                 "127.0.1.1:80".parse().unwrap(),
+                Default::default(),
             ) {
                 let n = &us.workload.name; // borrow name instead of cloning
                 found.insert(n.to_string()); // insert an owned copy of the borrowed n
@@ -1278,6 +1319,7 @@ mod tests {
             cfg,
             state: state.clone(),
             cert_fetcher: Arc::new(cert_fetcher::NoCertFetcher()),
+            metrics: xds::Metrics::new(&mut Registry::default()),
         };
         local_client.run().await.expect("client should run");
         let wl = demand
@@ -1293,6 +1335,7 @@ mod tests {
             strng::EMPTY,
             wl.as_ref().unwrap(),
             "127.10.0.1:80".parse().unwrap(),
+            Default::default(),
         );
         // Make sure we get a valid VIP
         assert!(us.is_some());
@@ -1307,9 +1350,175 @@ mod tests {
             "remote".into(),
             wl.as_ref().unwrap(),
             "127.10.0.2:80".parse().unwrap(),
+            Default::default(),
         );
         // Make sure we get a valid VIP
         assert!(us.is_some());
         assert_eq!(us.unwrap().port, 8080);
     }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn local_client_reload() {
+        let tp = std::env::temp_dir().join("ztunnel_local_xds.XXXXXX");
+        let dir = nix::unistd::mkdtemp(&tp).expect("tmp dir");
+        let path = dir.join("config.yaml");
+        let workload = |name: &str, ip: &str| {
+            format!(
+                "workloads:\n- uid: cluster1//v1/Pod/default/{name}\n  name: {name}\n  namespace: default\n  workloadIps: [\"{ip}\"]\n  services: {{}}\n"
+            )
+        };
+        std::fs::write(&path, workload("a", "127.0.0.1")).unwrap();
+
+        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let mut policies = state.read().unwrap().policies.subscribe();
+        let metrics = xds::Metrics::new(&mut Registry::default());
+        let local_client = LocalClient {
+            cfg: ConfigSource::File(path.clone()),
+            state: state.clone(),
+            cert_fetcher: Arc::new(cert_fetcher::NoCertFetcher()),
+            metrics: metrics.clone(),
+        };
+        local_client.run().await.expect("client should run");
+        let has = |ip: &str| {
+            state
+                .read()
+                .unwrap()
+                .workloads
+                .find_address(&network_addr(strng::EMPTY, ip.parse().unwrap()))
+                .is_some()
+        };
+        let reloads = |result: xds::LocalConfigReloadResult| {
+            metrics
+                .local_config_reloads
+                .get_or_create(&xds::LocalConfigReload { result })
+                .get()
+        };
+        assert!(has("127.0.0.1"));
+        policies.mark_unchanged();
+        let outliers = || {
+            serde_json::to_value(&state.read().unwrap().load_balancing.outlier_detector).unwrap()
+        };
+        state
+            .read()
+            .unwrap()
+            .load_balancing
+            .outlier_detector
+            .record(&strng::new("cluster1//v1/Pod/default/a"), false);
+        assert_ne!(outliers(), serde_json::json!({}));
+
+        // An invalid update is rejected, and the previous config is kept
+        std::fs::write(&path, "workloads: [invalid").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while reloads(xds::LocalConfigReloadResult::ParseError) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("update should be rejected");
+        assert!(has("127.0.0.1"));
+        let status = state.read().unwrap().local_config.clone().unwrap();
+        assert!(status.error.is_some());
+        assert!(!policies.has_changed().unwrap());
+
+        // A valid update replaces the config, and policies are re-evaluated
+        std::fs::write(&path, workload("b", "127.0.0.2")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !has("127.0.0.2") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("update should be applied");
+        assert!(!has("127.0.0.1"));
+        assert_eq!(reloads(xds::LocalConfigReloadResult::Success), 1);
+        let status = state.read().unwrap().local_config.clone().unwrap();
+        assert!(status.error.is_none());
+        assert!(policies.has_changed().unwrap());
+        // The removed workload is forgotten by outlier detection
+        assert_eq!(outliers(), serde_json::json!({}));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_config_directory() {
+        let tp = std::env::temp_dir().join("ztunnel_local_xds.XXXXXX");
+        let dir = nix::unistd::mkdtemp(&tp).expect("tmp dir");
+        // Workloads in one file select a service defined in another.
+        std::fs::write(
+            dir.join("a-workloads.yaml"),
+            r#"
+workloads:
+- uid: cluster1//v1/Pod/default/a
+  name: a
+  namespace: default
+  workloadIps: ["127.0.0.1"]
+  services:
+    "default/example.com":
+      80: 8080
+---
+workloads:
+- uid: cluster1//v1/Pod/default/b
+  name: b
+  namespace: default
+  workloadIps: ["127.0.0.2"]
+  services:
+    "default/example.com":
+      80: 8080
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b-services.json"),
+            r#"{"services": [{"name": "example", "namespace": "default", "hostname": "example.com", "vips": ["/127.10.0.1"], "ports": {}}]}"#,
+        )
+        .unwrap();
+        // Files without a config extension, and hidden files, are ignored.
+        std::fs::write(dir.join("README.md"), "not config").unwrap();
+        std::fs::write(dir.join(".hidden.yaml"), "not: config").unwrap();
+
+        let cfg = LocalConfig::read(&dir).await.unwrap();
+        assert_eq!(cfg.workloads.len(), 2);
+        assert_eq!(cfg.services.len(), 1);
+
+        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let local_client = LocalClient {
+            cfg: ConfigSource::File(dir.clone()),
+            state: state.clone(),
+            cert_fetcher: Arc::new(cert_fetcher::NoCertFetcher()),
+            metrics: xds::Metrics::new(&mut Registry::default()),
+        };
+        local_client.run().await.expect("client should run");
+        let svc = state
+            .read()
+            .unwrap()
+            .services
+            .get_by_vip(&network_addr(strng::EMPTY, "127.10.0.1".parse().unwrap()))
+            .unwrap();
+        assert_eq!(svc.endpoints.len(), 2);
+
+        // Duplicates name both files
+        std::fs::write(
+            dir.join("c-dup.yaml"),
+            r#"
+workloads:
+- uid: cluster1//v1/Pod/default/b
+  name: b
+  namespace: default
+  workloadIps: ["127.0.0.3"]
+  services: {}
+"#,
+        )
+        .unwrap();
+        let err = LocalConfig::read(&dir).await.unwrap_err().to_string();
+        assert!(
+            err.contains("duplicate workload cluster1//v1/Pod/default/b"),
+            "{err}"
+        );
+        assert!(err.contains("c-dup.yaml, already defined in"), "{err}");
+        assert!(err.contains("a-workloads.yaml (document 2)"), "{err}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
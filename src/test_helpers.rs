@@ -49,6 +49,7 @@ use tracing::{debug, trace};
 pub mod app;
 pub mod ca;
 pub mod dns;
+pub mod ext_authz;
 pub mod helpers;
 #[cfg(target_os = "linux")]
 pub mod inpod;
@@ -77,6 +78,21 @@ pub fn test_config_with_waypoint(addr: IpAddr) -> config::Config {
     }
 }
 
+/// test_config_with_hostname_waypoint configures the HBONE test workload (or, if `service_attached`
+/// is set, the test service) to use a waypoint referenced by hostname. The waypoint is expected to
+/// be listening for HBONE on TEST_WORKLOAD_WAYPOINT:waypoint_port.
+pub fn test_config_with_hostname_waypoint(
+    waypoint_port: u16,
+    service_attached: bool,
+) -> config::Config {
+    config::Config {
+        local_xds_config: Some(ConfigSource::Static(
+            local_xds_config_with_hostname_waypoint(80, waypoint_port, service_attached).unwrap(),
+        )),
+        ..test_config()
+    }
+}
+
 pub fn test_config_with_port_xds_addr_and_root_cert(
     port: u16,
     xds_addr: Option<String>,
@@ -128,11 +144,14 @@ pub const TEST_WORKLOAD_TCP: &str = "127.0.0.4";
 pub const TEST_WORKLOAD_WAYPOINT: &str = "127.0.0.5";
 pub const TEST_VIP: &str = "127.10.0.1";
 pub const TEST_VIP_DNS: &str = "127.10.0.2";
+pub const TEST_WAYPOINT_VIP: &str = "127.10.0.3";
 pub const TEST_SERVICE_NAMESPACE: &str = "default";
 pub const TEST_SERVICE_NAME: &str = "local-vip";
 pub const TEST_SERVICE_HOST: &str = "local-vip.default.svc.cluster.local";
 pub const TEST_SERVICE_DNS_HBONE_NAME: &str = "local-vip-async-dns";
 pub const TEST_SERVICE_DNS_HBONE_HOST: &str = "local-vip-async-dns.default.svc.cluster.local";
+pub const TEST_WAYPOINT_NAME: &str = "waypoint";
+pub const TEST_WAYPOINT_HOST: &str = "waypoint.default.svc.cluster.local";
 
 pub fn localhost_error_message() -> String {
     let addrs = &[
@@ -197,6 +216,7 @@ pub fn test_default_workload() -> Workload {
         native_tunnel: false,
         application_tunnel: None,
         locality: Default::default(),
+        connection_rate_limit: None,
     }
 }
 
@@ -282,6 +302,79 @@ pub fn local_xds_config(
     waypoint_ip: Option<IpAddr>,
     policies: Vec<crate::rbac::Authorization>,
 ) -> anyhow::Result<Bytes> {
+    local_config_to_bytes(&local_config(echo_port, waypoint_ip, policies)?)
+}
+
+/// local_xds_config_with_hostname_waypoint is like local_xds_config, but adds a waypoint workload
+/// and service, and attaches the waypoint to the HBONE workload (or the test service) by hostname.
+pub fn local_xds_config_with_hostname_waypoint(
+    echo_port: u16,
+    waypoint_port: u16,
+    service_attached: bool,
+) -> anyhow::Result<Bytes> {
+    let mut lc = local_config(echo_port, None, vec![])?;
+    let mut waypoint_svc = test_custom_svc(
+        TEST_WAYPOINT_NAME,
+        TEST_WAYPOINT_HOST,
+        TEST_WAYPOINT_VIP,
+        TEST_WAYPOINT_NAME,
+        TEST_WORKLOAD_WAYPOINT,
+        waypoint_port,
+    )?;
+    waypoint_svc.ports = HashMap::from([(waypoint_port, waypoint_port)]);
+    waypoint_svc.subject_alt_names = vec![];
+    let mut waypoint = test_custom_workload(
+        TEST_WORKLOAD_WAYPOINT,
+        TEST_WAYPOINT_NAME,
+        HBONE,
+        waypoint_port,
+        vec![],
+        false,
+    )?;
+    waypoint.workload.service_account = TEST_WAYPOINT_NAME.into();
+    waypoint.services = HashMap::from([(
+        format!("{}/{}", waypoint_svc.namespace, waypoint_svc.hostname),
+        HashMap::from([(waypoint_port, waypoint_port)]),
+    )]);
+
+    let gw = GatewayAddress {
+        destination: gatewayaddress::Destination::Hostname(waypoint_svc.namespaced_hostname()),
+        hbone_mtls_port: waypoint_port,
+        hbone_single_tls_port: None,
+    };
+    if service_attached {
+        for svc in lc
+            .services
+            .iter_mut()
+            .filter(|s| s.name == TEST_SERVICE_NAME)
+        {
+            svc.waypoint = Some(gw.clone());
+        }
+    } else {
+        for wl in lc
+            .workloads
+            .iter_mut()
+            .filter(|w| w.workload.name == "local-hbone")
+        {
+            wl.workload.waypoint = Some(gw.clone());
+        }
+    }
+    lc.workloads.push(waypoint);
+    lc.services.push(waypoint_svc);
+    local_config_to_bytes(&lc)
+}
+
+fn local_config_to_bytes(lc: &LocalConfig) -> anyhow::Result<Bytes> {
+    let mut b = bytes::BytesMut::new().writer();
+    serde_yaml::to_writer(&mut b, lc)?;
+    Ok(b.into_inner().freeze())
+}
+
+fn local_config(
+    echo_port: u16,
+    waypoint_ip: Option<IpAddr>,
+    policies: Vec<crate::rbac::Authorization>,
+) -> anyhow::Result<LocalConfig> {
     let default_svc = test_custom_svc(
         TEST_SERVICE_NAME,
         TEST_SERVICE_HOST,
@@ -357,14 +450,11 @@ pub fn local_xds_config(
         })
     }
     let svcs: Vec<Service> = vec![default_svc, dns_svc];
-    let lc = LocalConfig {
+    Ok(LocalConfig {
         workloads: res,
         policies,
         services: svcs,
-    };
-    let mut b = bytes::BytesMut::new().writer();
-    serde_yaml::to_writer(&mut b, &lc)?;
-    Ok(b.into_inner().freeze())
+    })
 }
 
 /// check_eventually runs a function many times until it reaches the expected result.
@@ -202,6 +202,32 @@ impl TestApp {
     }
 
     pub async fn socks5_connect(&self, addr: SocketAddr, source: IpAddr) -> TcpStream {
+        let stream = self.socks5_stream(source).await;
+        socks5_connect(stream, addr).await.unwrap()
+    }
+
+    pub async fn socks5_connect_hostname(
+        &self,
+        host: &str,
+        port: u16,
+        source: IpAddr,
+    ) -> TcpStream {
+        let stream = self.socks5_stream(source).await;
+        socks5_connect_hostname(stream, host, port).await.unwrap()
+    }
+
+    pub async fn socks5_connect_with_auth(
+        &self,
+        addr: SocketAddr,
+        source: IpAddr,
+        username: &str,
+        password: &str,
+    ) -> anyhow::Result<TcpStream> {
+        let stream = self.socks5_stream(source).await;
+        socks5_connect_with_auth(stream, addr, username, password).await
+    }
+
+    async fn socks5_stream(&self, source: IpAddr) -> TcpStream {
         // Always use IPv4 address. In theory, we can resolve `localhost` to pick to support any machine
         // However, we need to make sure the WorkloadStore knows about both families then.
         let socks_addr = with_ip(
@@ -217,7 +243,7 @@ impl TestApp {
 
         let stream = socket.connect(socks_addr).await.unwrap();
         stream.set_nodelay(true).unwrap();
-        socks5_connect(stream, addr).await.unwrap()
+        stream
     }
 
     pub async fn dns_request(
@@ -261,34 +287,95 @@ pub async fn dns_request(
     send_request(&mut client, n(hostname), query_type).await
 }
 
-pub async fn socks5_connect(mut stream: TcpStream, addr: SocketAddr) -> anyhow::Result<TcpStream> {
-    let addr_type = if addr.ip().is_ipv4() { 0x01u8 } else { 0x04u8 };
+pub async fn socks5_connect(stream: TcpStream, addr: SocketAddr) -> anyhow::Result<TcpStream> {
+    let mut dst = match socket::to_canonical(addr).ip() {
+        IpAddr::V6(ip) => [&[0x04u8][..], &ip.octets()].concat(),
+        IpAddr::V4(ip) => [&[0x01u8][..], &ip.octets()].concat(),
+    };
+    dst.extend_from_slice(&addr.port().to_be_bytes());
+    socks5_request(stream, &dst, None).await
+}
+
+pub async fn socks5_connect_with_auth(
+    stream: TcpStream,
+    addr: SocketAddr,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<TcpStream> {
+    let mut dst = match socket::to_canonical(addr).ip() {
+        IpAddr::V6(ip) => [&[0x04u8][..], &ip.octets()].concat(),
+        IpAddr::V4(ip) => [&[0x01u8][..], &ip.octets()].concat(),
+    };
+    dst.extend_from_slice(&addr.port().to_be_bytes());
+    socks5_request(stream, &dst, Some((username, password))).await
+}
+
+pub async fn socks5_connect_hostname(
+    stream: TcpStream,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<TcpStream> {
+    let mut dst = vec![0x03u8, host.len() as u8];
+    dst.extend_from_slice(host.as_bytes());
+    dst.extend_from_slice(&port.to_be_bytes());
+    socks5_request(stream, &dst, None).await
+}
+
+async fn socks5_request(
+    mut stream: TcpStream,
+    dst: &[u8],
+    credentials: Option<(&str, &str)>,
+) -> anyhow::Result<TcpStream> {
+    let method = if credentials.is_some() {
+        0x2u8 // username/password auth method
+    } else {
+        0x0u8 // unauthenticated auth method
+    };
     stream
         .write_all(&[
             0x05u8, // socks5
             0x1u8,  // 1 auth method
-            0x0u8,  // unauthenticated auth method
+            method,
         ])
         .await?;
     let mut auth = [0u8; 2];
     stream.read_exact(&mut auth).await?;
+    if auth[1] != method {
+        anyhow::bail!("socks5 auth method {method} not accepted");
+    }
+    if let Some((username, password)) = credentials {
+        let mut req = vec![0x01u8, username.len() as u8];
+        req.extend_from_slice(username.as_bytes());
+        req.push(password.len() as u8);
+        req.extend_from_slice(password.as_bytes());
+        stream.write_all(&req).await?;
+        let mut resp = [0u8; 2];
+        stream.read_exact(&mut resp).await?;
+        if resp[1] != 0x00 {
+            anyhow::bail!("socks5 authentication failed");
+        }
+    }
 
     let mut cmd = vec![
         0x05u8, // socks5
         0x1u8,  // establish tcp stream
         0x0u8,  // RSV
-        addr_type,
     ];
-    match socket::to_canonical(addr).ip() {
-        IpAddr::V6(ip) => cmd.extend_from_slice(&ip.octets()),
-        IpAddr::V4(ip) => cmd.extend_from_slice(&ip.octets()),
-    };
-    cmd.extend_from_slice(&addr.port().to_be_bytes());
+    cmd.extend_from_slice(dst);
     stream.write_all(&cmd).await?;
 
-    // We don't care about response but need to clear out the stream
-    let mut resp = [0u8; 10];
+    // We don't care about the bound address in the response, but need to clear out the stream
+    let mut resp = [0u8; 4];
     stream.read_exact(&mut resp).await?;
+    if resp[1] != 0x00 {
+        anyhow::bail!("socks5 request failed with status {}", resp[1]);
+    }
+    let addr_len = match resp[3] {
+        0x04 => 16,
+        _ => 4,
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
 
     Ok(stream)
 }
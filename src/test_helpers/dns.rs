@@ -20,7 +20,7 @@ use hickory_client::error::ClientError;
 use hickory_proto::error::{ProtoError, ProtoErrorKind};
 use hickory_proto::iocompat::AsyncIoTokioAsStd;
 use hickory_proto::op::{Edns, Message, MessageType, OpCode, Query};
-use hickory_proto::rr::rdata::{A, AAAA, CNAME};
+use hickory_proto::rr::rdata::{A, AAAA, CNAME, PTR, SRV};
 use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
 use hickory_proto::serialize::binary::BinDecodable;
 use hickory_proto::tcp::TcpClientStream;
@@ -57,6 +57,16 @@ pub fn cname(name: Name, canonical_name: Name) -> Record {
     Record::from_rdata(name, TTL, RData::CNAME(CNAME(canonical_name)))
 }
 
+/// Creates an SRV record for the given port and target.
+pub fn srv(name: Name, port: u16, target: Name) -> Record {
+    Record::from_rdata(name, TTL, RData::SRV(SRV::new(0, 1, port, target)))
+}
+
+/// Creates a PTR record for the given hostname.
+pub fn ptr(name: Name, hostname: Name) -> Record {
+    Record::from_rdata(name, TTL, RData::PTR(PTR(hostname)))
+}
+
 #[cfg(any(unix, target_os = "windows"))]
 /// Creates a [Forwarder] that uses the system configuration (e.g. /etc/resolv.conf).
 pub fn system_forwarder() -> Forwarder {
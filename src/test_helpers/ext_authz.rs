@@ -0,0 +1,126 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use hyper_util::rt::TokioIo;
+use itertools::Itertools;
+
+use tokio::sync::{mpsc, watch};
+
+use tracing::error;
+
+use crate::config::RootCert;
+
+use crate::rbac::ext_authz::{ExtAuthz, ExtAuthzConfig};
+use crate::tls;
+use crate::xds::service::auth::v3::authorization_server::{Authorization, AuthorizationServer};
+use crate::xds::service::auth::v3::{CheckRequest, CheckResponse};
+
+/// AuthzServer provides a fake ext_authz server implementation. Mocked responses can be assigned to it.
+#[derive(Clone)]
+pub struct AuthzServer {
+    response: watch::Receiver<Result<CheckResponse, tonic::Status>>,
+    delay: watch::Receiver<Duration>,
+    requests: mpsc::UnboundedSender<CheckRequest>,
+}
+
+/// Handle to control a spawned AuthzServer.
+pub struct AuthzServerHandle {
+    /// The response returned for every request.
+    pub response: watch::Sender<Result<CheckResponse, tonic::Status>>,
+    /// How long the server waits before responding.
+    pub delay: watch::Sender<Duration>,
+    /// Requests received by the server.
+    pub requests: mpsc::UnboundedReceiver<CheckRequest>,
+}
+
+impl AuthzServer {
+    /// Spawns a server, and returns a client for it using the given timeout and failure mode.
+    pub async fn spawn(timeout: Duration, fail_open: bool) -> (AuthzServerHandle, ExtAuthz) {
+        let default = Err(tonic::Status::not_found("mock not set"));
+        let (response_tx, response_rx) = watch::channel(default);
+        let (delay_tx, delay_rx) = watch::channel(Duration::ZERO);
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+
+        let server = AuthzServer {
+            response: response_rx,
+            delay: delay_rx,
+            requests: requests_tx,
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let certs = tls::mock::generate_test_certs(
+            &server_addr.ip().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let root_cert = RootCert::Static(certs.chain.iter().map(|c| c.as_pem()).join("\n").into());
+        let acceptor = tls::mock::MockServerCertProvider::new(certs);
+        let mut tls_stream = crate::hyper_util::tls_server(acceptor, listener);
+        let srv = AuthorizationServer::new(server);
+        tokio::spawn(async move {
+            while let Some(socket) = tls_stream.next().await {
+                let srv = srv.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = crate::hyper_util::http2_server()
+                        .serve_connection(
+                            TokioIo::new(socket),
+                            tower_hyper_http_body_compat::TowerService03HttpServiceAsHyper1HttpService::new(srv)
+                        )
+                        .await
+                    {
+                        error!("Error serving connection: {:?}", err);
+                    }
+                });
+            }
+        });
+        let client = ExtAuthz::new(ExtAuthzConfig {
+            address: "https://".to_string() + &server_addr.to_string(),
+            root_cert,
+            timeout,
+            fail_open,
+            cache_ttl: Duration::from_secs(60),
+        })
+        .await
+        .unwrap();
+        let handle = AuthzServerHandle {
+            response: response_tx,
+            delay: delay_tx,
+            requests: requests_rx,
+        };
+        (handle, client)
+    }
+}
+
+#[async_trait]
+impl Authorization for AuthzServer {
+    async fn check(
+        &self,
+        request: tonic::Request<CheckRequest>,
+    ) -> Result<tonic::Response<CheckResponse>, tonic::Status> {
+        let _ = self.requests.send(request.into_inner());
+        let delay = *self.delay.borrow();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let b = self.response.borrow();
+        match &*b {
+            Ok(res) => Ok(tonic::Response::new(res.clone())),
+            Err(e) => Err(e.clone()),
+        }
+    }
+}
@@ -190,7 +190,12 @@ pub struct HboneTestServer {
 
 impl HboneTestServer {
     pub async fn new(mode: Mode, name: &str) -> Self {
-        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15008);
+        Self::new_with_port(mode, name, 15008).await
+    }
+
+    /// new_with_port creates a server listening on the given port; 0 picks a random port.
+    pub async fn new_with_port(mode: Mode, name: &str, port: u16) -> Self {
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
         let listener = TcpListener::bind(addr).await.unwrap();
         Self {
             listener,
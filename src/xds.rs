@@ -12,13 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::error::Error as StdErr;
 use std::fmt;
 use std::fmt::Formatter;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::Deserialize;
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, instrument, trace, warn};
 
@@ -34,9 +43,13 @@ use xds::istio::workload::Workload as XdsWorkload;
 
 use crate::cert_fetcher::{CertFetcher, NoCertFetcher};
 use crate::config::ConfigSource;
+#[cfg(target_os = "linux")]
+use crate::metrics::Recorder;
 use crate::rbac::Authorization;
 use crate::state::service::{endpoint_uid, Endpoint, Service, ServiceStore};
-use crate::state::workload::{network_addr, HealthStatus, NamespacedHostname, Workload};
+use crate::state::workload::{
+    network_addr, HealthStatus, NamespacedHostname, Workload, WorkloadStore,
+};
 use crate::state::ProxyState;
 use crate::strng::Strng;
 use crate::{rbac, strng};
@@ -175,6 +188,11 @@ impl ProxyStateUpdateMutator {
                     .remove_endpoint(&prev.uid, &endpoint_uid(&prev.uid, None));
             }
 
+            if !for_insert {
+                state.load_balancing.remove(&prev.uid);
+                state.load_balancing.outlier_detector.remove(&prev.uid);
+            }
+
             // This is a real removal (not a removal before insertion), and nothing else references the cert
             // Clear it out
             if !for_insert
@@ -215,6 +233,9 @@ impl ProxyStateUpdateMutator {
         if state.services.remove(&name).is_none() && !for_insert {
             warn!("tried to remove service keyed by {name}, but it was not found");
         }
+        if !for_insert {
+            state.load_balancing.remove(&strng::new(name.to_string()));
+        }
     }
 
     pub fn insert_address(&self, state: &mut ProxyState, a: XdsAddress) -> anyhow::Result<()> {
@@ -383,11 +404,27 @@ impl Handler<XdsAuthorization> for ProxyStateUpdater {
     }
 }
 
-/// LocalClient serves as a local file reader alternative for XDS. This is intended for testing.
+/// LocalClient serves as a local file reader alternative for XDS. Config read from a file is
+/// watched, and reloaded when it changes.
 pub struct LocalClient {
     pub cfg: ConfigSource,
     pub state: Arc<RwLock<ProxyState>>,
     pub cert_fetcher: Arc<dyn CertFetcher>,
+    pub metrics: Metrics,
+}
+
+/// LocalConfigStatus reports the outcome of (re)loading the local config.
+#[derive(Default, Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalConfigStatus {
+    /// When the config currently in use was loaded.
+    pub loaded_at: String,
+    /// When the latest update to the config was rejected, if it was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected_at: Option<String>,
+    /// Why the latest update was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
@@ -409,6 +446,137 @@ pub struct LocalConfig {
     pub services: Vec<Service>,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum LocalConfigError {
+    #[error("failed to read {0}: {1}")]
+    Read(String, #[source] std::io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Parse(String, #[source] serde_yaml::Error),
+    #[error("duplicate {kind} {key} in {file}, already defined in {existing}")]
+    Duplicate {
+        kind: &'static str,
+        key: Strng,
+        file: String,
+        existing: String,
+    },
+}
+
+impl LocalConfig {
+    /// Reads the config at `path`, which is either a file or a directory of YAML or JSON files.
+    /// Each file may contain multiple YAML documents. All documents are merged into one config.
+    pub async fn read(path: &Path) -> Result<LocalConfig, LocalConfigError> {
+        let read_err = |p: &Path| {
+            let p = p.display().to_string();
+            move |e| LocalConfigError::Read(p, e)
+        };
+        let mut files = Vec::new();
+        if tokio::fs::metadata(path)
+            .await
+            .map_err(read_err(path))?
+            .is_dir()
+        {
+            let mut entries = tokio::fs::read_dir(path).await.map_err(read_err(path))?;
+            while let Some(entry) = entries.next_entry().await.map_err(read_err(path))? {
+                let file = entry.path();
+                // Skip hidden files, such as the ones Kubernetes uses to update mounted ConfigMaps.
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                let ext = file.extension().and_then(|ext| ext.to_str());
+                if !hidden && matches!(ext, Some("yaml" | "yml" | "json")) {
+                    files.push(file);
+                }
+            }
+            // Merge in a stable order, so errors are reported consistently.
+            files.sort();
+        } else {
+            files.push(path.to_path_buf());
+        }
+        let mut merged = LocalConfigMerger::default();
+        for file in files {
+            let data = tokio::fs::read_to_string(&file)
+                .await
+                .map_err(read_err(&file))?;
+            merged.add(&data, &file.display().to_string())?;
+        }
+        Ok(merged.config)
+    }
+
+    /// Parses a YAML stream, which may contain multiple documents. `source` names the stream in
+    /// errors.
+    pub fn parse(data: &str, source: &str) -> Result<LocalConfig, LocalConfigError> {
+        let mut merged = LocalConfigMerger::default();
+        merged.add(data, source)?;
+        Ok(merged.config)
+    }
+}
+
+/// LocalConfigMerger merges configs from multiple documents, rejecting any workload, policy or
+/// service that is defined more than once.
+#[derive(Default)]
+struct LocalConfigMerger {
+    config: LocalConfig,
+    // Where each workload, policy and service was defined, keyed by kind and key.
+    defined_in: HashMap<(&'static str, Strng), String>,
+}
+
+impl LocalConfigMerger {
+    fn add(&mut self, data: &str, source: &str) -> Result<(), LocalConfigError> {
+        let docs = serde_yaml::Deserializer::from_str(data)
+            .map(LocalConfig::deserialize)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| LocalConfigError::Parse(source.to_string(), e))?;
+        let multi_doc = docs.len() > 1;
+        for (i, doc) in docs.into_iter().enumerate() {
+            let source = if multi_doc {
+                format!("{source} (document {})", i + 1)
+            } else {
+                source.to_string()
+            };
+            self.merge(doc, source)?;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, cfg: LocalConfig, source: String) -> Result<(), LocalConfigError> {
+        for wl in &cfg.workloads {
+            // Workloads without a UID cannot be told apart, so they are not checked.
+            if !wl.workload.uid.is_empty() {
+                self.define("workload", wl.workload.uid.clone(), &source)?;
+            }
+        }
+        for policy in &cfg.policies {
+            self.define("policy", policy.to_key(), &source)?;
+        }
+        for svc in &cfg.services {
+            let key = strng::format!("{}", svc.namespaced_hostname());
+            self.define("service", key, &source)?;
+        }
+        self.config.workloads.extend(cfg.workloads);
+        self.config.policies.extend(cfg.policies);
+        self.config.services.extend(cfg.services);
+        Ok(())
+    }
+
+    fn define(
+        &mut self,
+        kind: &'static str,
+        key: Strng,
+        source: &str,
+    ) -> Result<(), LocalConfigError> {
+        match self.defined_in.entry((kind, key)) {
+            Entry::Occupied(e) => Err(LocalConfigError::Duplicate {
+                kind,
+                key: e.key().1.clone(),
+                file: source.to_string(),
+                existing: e.get().clone(),
+            }),
+            Entry::Vacant(e) => {
+                e.insert(source.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+
 impl LocalClient {
     #[instrument(skip_all, name = "local_client")]
     pub async fn run(self) -> Result<(), anyhow::Error> {
@@ -424,8 +592,28 @@ impl LocalClient {
                 self.load_config(r)?;
                 rx.ack().await?;
             }
+            #[cfg(target_os = "linux")]
+            ConfigSource::File(path) => {
+                // Start watching before the initial read, so no change can be missed.
+                let watcher = FileWatcher::new(path)?;
+                let r = LocalConfig::read(path).await?;
+                self.load_config(r.clone())?;
+                let path = path.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = self.watch(&path, watcher, r).await {
+                        error!("stopped watching local config {}: {e}", path.display());
+                    }
+                });
+                return Ok(());
+            }
+            // The config is only reloaded on Linux; elsewhere it is read once.
+            #[cfg(not(target_os = "linux"))]
+            ConfigSource::File(path) => {
+                let r = LocalConfig::read(path).await?;
+                self.load_config(r)?;
+            }
             f => {
-                let r: LocalConfig = serde_yaml::from_str(&f.read_to_string().await?)?;
+                let r = LocalConfig::parse(&f.read_to_string().await?, "local config")?;
                 self.load_config(r)?;
             }
         };
@@ -448,43 +636,175 @@ impl LocalClient {
         Ok(())
     }
 
+    /// Reloads the config each time the watched path changes. `current` is the config that is
+    /// currently loaded.
+    #[cfg(target_os = "linux")]
+    async fn watch(
+        &self,
+        path: &Path,
+        mut watcher: FileWatcher,
+        mut current: LocalConfig,
+    ) -> anyhow::Result<()> {
+        loop {
+            watcher.changed().await?;
+            let r = match LocalConfig::read(path).await {
+                Ok(r) => r,
+                Err(e) => {
+                    let result = match e {
+                        LocalConfigError::Read(..) => LocalConfigReloadResult::ReadError,
+                        LocalConfigError::Parse(..) => LocalConfigReloadResult::ParseError,
+                        LocalConfigError::Duplicate { .. } => LocalConfigReloadResult::Invalid,
+                    };
+                    self.reject(result, e.into());
+                    continue;
+                }
+            };
+            if r == current {
+                // An unrelated file changed, or the config was reverted to the loaded one after an
+                // update was rejected.
+                if let Some(status) = self.state.write().unwrap().local_config.as_mut() {
+                    status.rejected_at = None;
+                    status.error = None;
+                }
+                continue;
+            }
+            match self.load_config(r.clone()) {
+                Ok(()) => {
+                    self.metrics.record(&LocalConfigReloadResult::Success, 1);
+                    current = r;
+                }
+                Err(e) => self.reject(LocalConfigReloadResult::Invalid, e),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn reject(&self, result: LocalConfigReloadResult, e: anyhow::Error) {
+        warn!("rejected local config update, keeping the previous config: {e:#}");
+        self.metrics.record(&result, 1);
+        let mut state = self.state.write().unwrap();
+        let status = state.local_config.get_or_insert_with(Default::default);
+        status.rejected_at = Some(now_rfc3339());
+        status.error = Some(format!("{e:#}"));
+    }
+
+    /// Replaces the workloads, services and policies with the ones in the config. If the config is
+    /// invalid, the current state is left untouched.
     fn load_config(&self, r: LocalConfig) -> anyhow::Result<()> {
         debug!(
             "load local config: {}",
             serde_yaml::to_string(&r).unwrap_or_default()
         );
-        let mut state = self.state.write().unwrap();
-        // Clear the state
-        state.workloads = Default::default();
-        state.services = Default::default();
-        // Policies have some channels, so we don't want to reset it entirely
-        state.policies.clear_all_policies();
         let num_workloads = r.workloads.len();
         let num_policies = r.policies.len();
+        // Build the new state aside, so readers never observe a partially applied config.
+        let mut workloads = WorkloadStore::default();
+        let mut services = ServiceStore::default();
+        let mut inserted = Vec::with_capacity(num_workloads);
         for wl in r.workloads {
             trace!("inserting local workload {}", &wl.workload.uid);
-            self.cert_fetcher.prefetch_cert(&wl.workload);
             let w = Arc::new(wl.workload);
-            state.workloads.insert(
+            inserted.push(w.clone());
+            workloads.insert(
                 w.clone(),
                 self.cert_fetcher.should_track_certificates_for_removal(&w),
             );
 
-            let services: HashMap<String, PortList> = wl
+            let wl_services: HashMap<String, PortList> = wl
                 .services
                 .into_iter()
                 .map(|(k, v)| (k, PortList::from(v)))
                 .collect();
 
-            insert_service_endpoints(&w, &services, &mut state.services)?;
-        }
-        for rbac in r.policies {
-            state.policies.insert(rbac);
+            insert_service_endpoints(&w, &wl_services, &mut services)?;
         }
         for svc in r.services {
-            state.services.insert(svc);
+            services.insert(svc);
+        }
+        for w in inserted {
+            self.cert_fetcher.prefetch_cert(&w);
+        }
+
+        let mut state = self.state.write().unwrap();
+        // Forget the load balancing state of workloads and services the config no longer has, as
+        // removing them through XDS would.
+        for uid in state.workloads.uids() {
+            if workloads.find_uid(uid).is_none() {
+                state.load_balancing.remove(uid);
+                state.load_balancing.outlier_detector.remove(uid);
+            }
         }
+        for name in state.services.namespaced_hostnames() {
+            if services.get_by_namespaced_host(&name).is_none() {
+                state.load_balancing.remove(&strng::new(name.to_string()));
+            }
+        }
+        state.workloads = workloads;
+        state.services = services;
+        // Policies have some channels, so we don't want to reset it entirely. Policies that are
+        // still present keep their match stats.
+        state.policies.replace_all(r.policies);
+        // Existing connections must be re-evaluated against the new policies.
+        state.policies.send();
+        state.local_config = Some(LocalConfigStatus {
+            loaded_at: now_rfc3339(),
+            ..Default::default()
+        });
         info!(%num_workloads, %num_policies, "local config initialized");
         Ok(())
     }
 }
+
+fn now_rfc3339() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// FileWatcher is notified of changes to a file or directory, using inotify.
+#[cfg(target_os = "linux")]
+struct FileWatcher(AsyncFd<InotifyFd>);
+
+#[cfg(target_os = "linux")]
+struct InotifyFd(Inotify);
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FileWatcher {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        // Watch the directory rather than the file itself, so we are also notified when the file is
+        // atomically replaced, as editors and Kubernetes ConfigMap mounts do.
+        let dir = if path.is_dir() {
+            path
+        } else {
+            match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            }
+        };
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_DELETE,
+        )?;
+        Ok(Self(AsyncFd::new(InotifyFd(inotify))?))
+    }
+
+    /// Waits until something in the watched directory changed.
+    async fn changed(&mut self) -> std::io::Result<()> {
+        loop {
+            let mut guard = self.0.readable().await?;
+            match guard.try_io(|fd| fd.get_ref().0.read_events().map_err(std::io::Error::from)) {
+                Ok(res) => return res.map(|_| ()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
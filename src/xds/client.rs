@@ -857,6 +857,7 @@ mod tests {
                     }],
                 }],
             }],
+            dry_run: false,
         };
         ProtoResource {
             name: format!("foo{}", i),
@@ -19,8 +19,10 @@ use prometheus_client::registry::Registry;
 
 use crate::metrics::Recorder;
 
+#[derive(Clone)]
 pub struct Metrics {
     pub connection_terminations: Family<ConnectionTermination, Counter>,
+    pub local_config_reloads: Family<LocalConfigReload, Counter>,
 }
 
 #[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
@@ -36,6 +38,19 @@ pub enum ConnectionTerminationReason {
     Complete,
 }
 
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct LocalConfigReload {
+    pub result: LocalConfigReloadResult,
+}
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum LocalConfigReloadResult {
+    Success,
+    ReadError,
+    ParseError,
+    Invalid,
+}
+
 impl Metrics {
     pub fn new(registry: &mut Registry) -> Self {
         let connection_terminations = Family::default();
@@ -45,8 +60,16 @@ impl Metrics {
             connection_terminations.clone(),
         );
 
+        let local_config_reloads = Family::default();
+        registry.register(
+            "local_xds_config_reloads",
+            "The total number of attempts to reload the local xds config, by result (unstable)",
+            local_config_reloads.clone(),
+        );
+
         Self {
             connection_terminations,
+            local_config_reloads,
         }
     }
 }
@@ -58,3 +81,11 @@ impl Recorder<ConnectionTerminationReason, u64> for Metrics {
             .inc_by(count);
     }
 }
+
+impl Recorder<LocalConfigReloadResult, u64> for Metrics {
+    fn record(&self, result: &LocalConfigReloadResult, count: u64) {
+        self.local_config_reloads
+            .get_or_create(&LocalConfigReload { result: *result })
+            .inc_by(count);
+    }
+}
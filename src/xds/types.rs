@@ -25,6 +25,11 @@ pub mod service {
             tonic::include_proto!("envoy.service.discovery.v3");
         }
     }
+    pub mod auth {
+        pub mod v3 {
+            tonic::include_proto!("envoy.service.auth.v3");
+        }
+    }
 }
 
 #[allow(warnings)]
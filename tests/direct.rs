@@ -283,6 +283,146 @@ async fn test_vip_request_local() {
     run_request_test(&format!("{TEST_VIP}:80"), "local").await;
 }
 
+async fn run_hostname_request_test(host: &str) {
+    initialize_telemetry();
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    let cfg = test_config_with_port(echo_addr.port());
+    tokio::spawn(echo.run());
+    testapp::with_app(cfg, |app| async move {
+        let mut stream = app
+            .socks5_connect_hostname(host, 80, TEST_WORKLOAD_SOURCE.parse().unwrap())
+            .await;
+        read_write_stream(&mut stream).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_vip_hostname_request() {
+    run_hostname_request_test(TEST_SERVICE_HOST).await;
+}
+
+#[tokio::test]
+async fn test_vip_short_hostname_request() {
+    run_hostname_request_test(TEST_SERVICE_NAME).await;
+}
+
+const TEST_SOCKS5_USER: &str = "spiffe://cluster.local/ns/default/sa/default";
+const TEST_SOCKS5_OTHER_USER: &str = "spiffe://cluster.local/ns/default/sa/other";
+
+fn socks5_auth_config(port: u16) -> config::Config {
+    let credentials = format!("{TEST_SOCKS5_USER}: secret\n{TEST_SOCKS5_OTHER_USER}: other\n");
+    config::Config {
+        socks5_credentials: Some(config::ConfigSource::Static(credentials.into())),
+        ..test_config_with_port(port)
+    }
+}
+
+#[tokio::test]
+async fn test_socks5_auth_request() {
+    initialize_telemetry();
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(socks5_auth_config(echo_addr.port()), |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        let source = TEST_WORKLOAD_SOURCE.parse().unwrap();
+
+        // Wrong password is rejected during the handshake
+        assert!(app
+            .socks5_connect_with_auth(dst, source, TEST_SOCKS5_USER, "wrong")
+            .await
+            .is_err());
+
+        let mut stream = app
+            .socks5_connect_with_auth(dst, source, TEST_SOCKS5_USER, "secret")
+            .await
+            .unwrap();
+        read_write_stream(&mut stream).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_socks5_auth_mismatched_user() {
+    initialize_telemetry();
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    tokio::spawn(echo.run());
+    testapp::with_app(socks5_auth_config(echo_addr.port()), |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        // Valid credentials, but for a different identity than the source workload. The request
+        // is rejected as not allowed by the ruleset.
+        let err = app
+            .socks5_connect_with_auth(
+                dst,
+                TEST_WORKLOAD_SOURCE.parse().unwrap(),
+                TEST_SOCKS5_OTHER_USER,
+                "other",
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("status 2"), "{err}");
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_socks5_connection_refused() {
+    initialize_telemetry();
+    // Reserve a port with nothing listening on it.
+    let closed_port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    testapp::with_app(socks5_auth_config(closed_port), |app| async move {
+        let dst = SocketAddr::new(TEST_WORKLOAD_TCP.parse().unwrap(), closed_port);
+        // The failure is reported to the client, instead of a success before connecting.
+        let err = app
+            .socks5_connect_with_auth(
+                dst,
+                TEST_WORKLOAD_SOURCE.parse().unwrap(),
+                TEST_SOCKS5_USER,
+                "secret",
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("status 5"), "{err}");
+    })
+    .await;
+}
+
+async fn run_hostname_waypoint_test(target: &str, service_attached: bool) {
+    initialize_telemetry();
+    let waypoint =
+        tcp::HboneTestServer::new_with_port(tcp::Mode::ReadWrite, TEST_WAYPOINT_NAME, 0).await;
+    let waypoint_port = waypoint.address().port();
+    tokio::spawn(waypoint.run());
+    let cfg = test_config_with_hostname_waypoint(waypoint_port, service_attached);
+    testapp::with_app(cfg, |app| async move {
+        let dst = SocketAddr::from_str(target).unwrap();
+        let mut stream = app
+            .socks5_connect(dst, TEST_WORKLOAD_SOURCE.parse().unwrap())
+            .await;
+        read_waypoint_message(&mut stream).await;
+        read_write_stream(&mut stream).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_hostname_workload_waypoint_request() {
+    run_hostname_waypoint_test(&format!("{TEST_WORKLOAD_HBONE}:80"), false).await;
+}
+
+#[tokio::test]
+async fn test_hostname_service_waypoint_request() {
+    run_hostname_waypoint_test(&format!("{TEST_VIP}:80"), true).await;
+}
+
 #[tokio::test]
 async fn test_stats_exist() {
     testapp::with_app(test_config(), |app| async move {
@@ -478,6 +618,14 @@ async fn read_write_stream(stream: &mut TcpStream) -> usize {
     BODY.len()
 }
 
+/// read_waypoint_message reads the message the HBONE test server sends to identify itself as a waypoint
+async fn read_waypoint_message(stream: &mut TcpStream) {
+    const WAYPOINT_MESSAGE: &[u8] = b"waypoint\n";
+    let mut buf: [u8; WAYPOINT_MESSAGE.len()] = [0; WAYPOINT_MESSAGE.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(WAYPOINT_MESSAGE, buf);
+}
+
 /// admin_shutdown triggers a shutdown - from the admin server
 async fn admin_shutdown(addr: SocketAddr) {
     let req = Request::builder()